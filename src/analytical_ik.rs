@@ -0,0 +1,549 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, Isometry3, RealField, Rotation3, Unit, Vector3};
+
+use chain::*;
+use errors::*;
+use ik::*;
+use joint::*;
+
+/// Axis of a rotational joint at the zero position in the world frame
+#[derive(Debug, Clone, Copy)]
+struct Axis<T: RealField> {
+    /// direction of the axis
+    direction: Vector3<T>,
+    /// a point on the axis
+    point: Vector3<T>,
+}
+
+impl<T> Axis<T>
+where
+    T: RealField,
+{
+    fn rotation(&self, angle: T) -> Rotation3<T> {
+        Rotation3::from_axis_angle(&Unit::new_unchecked(self.direction), angle)
+    }
+    fn rotate_point(&self, p: &Vector3<T>, angle: T) -> Vector3<T> {
+        self.point + self.rotation(angle) * (p - self.point)
+    }
+    fn project(&self, v: &Vector3<T>) -> Vector3<T> {
+        v - self.direction * self.direction.dot(v)
+    }
+    fn distance_to(&self, p: &Vector3<T>) -> T {
+        self.project(&(p - self.point)).norm()
+    }
+}
+
+/// Closest point between two non-parallel lines
+fn closest_point_of_axes<T>(a: &Axis<T>, b: &Axis<T>) -> Vector3<T>
+where
+    T: RealField,
+{
+    let w0 = a.point - b.point;
+    let c = a.direction.dot(&b.direction);
+    let d = a.direction.dot(&w0);
+    let e = b.direction.dot(&w0);
+    let denom = T::one() - c * c;
+    let s = (c * e - d) / denom;
+    let t = (e - c * d) / denom;
+    let two: T = na::convert(2.0);
+    (a.point + a.direction * s + b.point + b.direction * t) / two
+}
+
+/// Paden-Kahan subproblem 1: rotate `p` around `axis` to `q`
+fn subproblem1<T>(axis: &Axis<T>, p: &Vector3<T>, q: &Vector3<T>) -> T
+where
+    T: RealField,
+{
+    let u = axis.project(&(p - axis.point));
+    let v = axis.project(&(q - axis.point));
+    axis.direction.dot(&u.cross(&v)).atan2(u.dot(&v))
+}
+
+/// Paden-Kahan subproblem 2 for the axes which intersect at the origin:
+/// `Rot(a0, t0) * Rot(a1, t1) * p = q`. Returns `(t0, t1)` pairs.
+fn subproblem2<T>(a0: &Vector3<T>, a1: &Vector3<T>, p: &Vector3<T>, q: &Vector3<T>) -> Vec<(T, T)>
+where
+    T: RealField,
+{
+    let eps: T = na::convert(1e-9);
+    let origin = Vector3::zeros();
+    let axis0 = Axis {
+        direction: *a0,
+        point: origin,
+    };
+    let axis1 = Axis {
+        direction: *a1,
+        point: origin,
+    };
+    let c = a0.dot(a1);
+    let denom = c * c - T::one();
+    let alpha = (c * a1.dot(p) - a0.dot(q)) / denom;
+    let beta = (c * a0.dot(q) - a1.dot(p)) / denom;
+    let cross = a0.cross(a1);
+    let two: T = na::convert(2.0);
+    let gamma2 = (p.norm_squared() - alpha * alpha - beta * beta - two * alpha * beta * c)
+        / cross.norm_squared();
+    if gamma2 < -eps {
+        return Vec::new();
+    }
+    let gamma = gamma2.max(T::zero()).sqrt();
+    let mut gammas = vec![gamma];
+    if gamma > eps {
+        gammas.push(-gamma);
+    }
+    gammas
+        .into_iter()
+        .map(|g| {
+            let z = *a0 * alpha + *a1 * beta + cross * g;
+            (subproblem1(&axis0, &z, q), subproblem1(&axis1, p, &z))
+        })
+        .collect()
+}
+
+/// Paden-Kahan subproblem 3: rotate `p` around `axis` so that
+/// the distance from `q` becomes `delta`.
+fn subproblem3<T>(axis: &Axis<T>, p: &Vector3<T>, q: &Vector3<T>, delta: T) -> Vec<T>
+where
+    T: RealField,
+{
+    let eps: T = na::convert(1e-9);
+    let u = axis.project(&(p - axis.point));
+    let v = axis.project(&(q - axis.point));
+    let height = axis.direction.dot(&(p - q));
+    let delta2 = delta * delta - height * height;
+    let theta0 = axis.direction.dot(&u.cross(&v)).atan2(u.dot(&v));
+    let two: T = na::convert(2.0);
+    let denom = two * u.norm() * v.norm();
+    if denom < eps {
+        return Vec::new();
+    }
+    let cos = (u.norm_squared() + v.norm_squared() - delta2) / denom;
+    angles_from_cos(theta0, cos)
+}
+
+/// Returns `center +- acos(cos)`, or nothing if `cos` is out of [-1, 1]
+fn angles_from_cos<T>(center: T, cos: T) -> Vec<T>
+where
+    T: RealField,
+{
+    let eps: T = na::convert(1e-9);
+    if cos.abs() > T::one() + eps {
+        return Vec::new();
+    }
+    let diff = cos.max(-T::one()).min(T::one()).acos();
+    if diff < eps {
+        vec![center]
+    } else {
+        vec![center + diff, center - diff]
+    }
+}
+
+/// Zero position geometry of an arm with a spherical wrist
+#[derive(Debug, Clone)]
+struct SphericalWristGeometry<T: RealField> {
+    axes: Vec<Axis<T>>,
+    wrist_center: Vector3<T>,
+    end_transform: Isometry3<T>,
+}
+
+impl<T> SphericalWristGeometry<T>
+where
+    T: RealField,
+{
+    fn new(arm: &SerialChain<T>) -> Result<Self, IKError> {
+        let tolerance: T = na::convert(1e-6);
+        let mut axes = Vec::new();
+        let mut trans = Isometry3::identity();
+        for node in arm.iter() {
+            let joint = node.joint();
+            trans *= joint.origin();
+            match joint.joint_type {
+                JointType::Rotational { axis } => axes.push(Axis {
+                    direction: trans.rotation * axis.into_inner(),
+                    point: trans.translation.vector,
+                }),
+                JointType::Linear { .. } => {
+                    return Err(IKError::PreconditionError {
+                        error: format!("{} is not a rotational joint", joint.name),
+                    });
+                }
+                JointType::Fixed => {}
+            }
+        }
+        if axes.len() != 6 {
+            return Err(IKError::PreconditionError {
+                error: format!("dof must be 6, but it is {}", axes.len()),
+            });
+        }
+        let is_parallel =
+            |a: &Axis<T>, b: &Axis<T>| a.direction.cross(&b.direction).norm() < tolerance;
+        if !is_parallel(&axes[1], &axes[2]) {
+            return Err(IKError::PreconditionError {
+                error: "the second and the third axes must be parallel".to_owned(),
+            });
+        }
+        if is_parallel(&axes[3], &axes[4]) || is_parallel(&axes[4], &axes[5]) {
+            return Err(IKError::PreconditionError {
+                error: "the wrist axes must not be parallel".to_owned(),
+            });
+        }
+        let wrist_center = closest_point_of_axes(&axes[3], &axes[4]);
+        if axes[3..6]
+            .iter()
+            .any(|axis| axis.distance_to(&wrist_center) > tolerance)
+        {
+            return Err(IKError::PreconditionError {
+                error: "the wrist axes must intersect at one point".to_owned(),
+            });
+        }
+        Ok(Self {
+            axes,
+            wrist_center,
+            end_transform: arm.iter().fold(Isometry3::identity(), |trans, node| {
+                trans * node.joint().origin()
+            }),
+        })
+    }
+
+    /// Calculate all the solutions without checking joint limits.
+    ///
+    /// `current` is used only for the singular configurations which have infinite solutions.
+    fn solve(&self, target_pose: &Isometry3<T>, current: &[T]) -> Vec<Vec<T>> {
+        let eps: T = na::convert(1e-9);
+        let axes = &self.axes;
+        // product of exponentials: target * end^-1 moves the wrist center only by the first three joints.
+        let offset = target_pose * self.end_transform.inverse();
+        let wrist = offset * na::Point3::from(self.wrist_center);
+        let wrist = wrist.coords;
+        // joint 1 keeps the height along the parallel axes of joint 2 and 3
+        let height = axes[1].direction.dot(&(self.wrist_center - axes[1].point));
+        let v = wrist - axes[0].point;
+        let v_par = axes[0].direction * axes[0].direction.dot(&v);
+        let v_perp = v - v_par;
+        let a = v_perp.dot(&axes[1].direction);
+        let b = axes[0].direction.cross(&v_perp).dot(&axes[1].direction);
+        let c = height
+            - (axes[0].point - axes[1].point).dot(&axes[1].direction)
+            - v_par.dot(&axes[1].direction);
+        let r = (a * a + b * b).sqrt();
+        let theta1_candidates = if r < eps {
+            // shoulder singularity
+            vec![current[0]]
+        } else {
+            angles_from_cos(b.atan2(a), c / r)
+                .into_iter()
+                .map(|psi| -psi)
+                .collect()
+        };
+        let mut solutions = Vec::new();
+        for theta1 in theta1_candidates {
+            let p = axes[0].rotate_point(&wrist, -theta1);
+            let delta = (p - axes[1].point).norm();
+            for theta3 in subproblem3(&axes[2], &self.wrist_center, &axes[1].point, delta) {
+                let rotated = axes[2].rotate_point(&self.wrist_center, theta3);
+                let theta2 = subproblem1(&axes[1], &rotated, &p);
+                let arm_rotation =
+                    axes[0].rotation(theta1) * axes[1].rotation(theta2) * axes[2].rotation(theta3);
+                let wrist_rotation = arm_rotation.inverse() * offset.rotation.to_rotation_matrix();
+                let x = axes[5].direction;
+                let y = wrist_rotation * x;
+                for (theta4, theta5) in subproblem2(&axes[3].direction, &axes[4].direction, &x, &y)
+                {
+                    let rest = (axes[3].rotation(theta4) * axes[4].rotation(theta5)).inverse()
+                        * wrist_rotation;
+                    let z = axes[5].direction.cross(&Vector3::x());
+                    let z = if z.norm() < na::convert(0.1) {
+                        axes[5].direction.cross(&Vector3::y())
+                    } else {
+                        z
+                    };
+                    let theta6 = subproblem1(
+                        &Axis {
+                            direction: axes[5].direction,
+                            point: Vector3::zeros(),
+                        },
+                        &z,
+                        &(rest * z),
+                    );
+                    solutions.push(vec![theta1, theta2, theta3, theta4, theta5, theta6]);
+                }
+            }
+        }
+        solutions
+    }
+}
+
+const SAME_SOLUTION_TOLERANCE: f64 = 1e-6;
+
+/// Move `angle` by 2 pi to be the nearest to `reference` and within `limits`
+fn normalize_angle<T>(angle: T, reference: T, limits: &Option<Range<T>>) -> Option<T>
+where
+    T: RealField,
+{
+    let two_pi = T::two_pi();
+    let mut nearest = angle + ((reference - angle) / two_pi).round() * two_pi;
+    match limits {
+        None => Some(nearest),
+        Some(range) => {
+            if range.is_valid(nearest) {
+                return Some(nearest);
+            }
+            // try the other side of the reference
+            nearest += if nearest < reference { two_pi } else { -two_pi };
+            if range.is_valid(nearest) {
+                Some(nearest)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Analytical IK solver for 6 DoF arms with a spherical wrist
+///
+/// The arm must have six rotational joints, the second and the third axes must be
+/// parallel, and the last three axes must intersect at one point (spherical wrist).
+/// Most industrial arms satisfy these conditions. Up to 8 solutions are calculated
+/// in closed form, and `solve` selects the closest one to the current joint positions.
+pub struct SphericalWristIKSolver<T: RealField> {
+    /// If the distance is smaller than this value, the solution is valid.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, the solution is valid.
+    pub allowable_target_angle: T,
+}
+
+impl<T> SphericalWristIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `SphericalWristIKSolver`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::SphericalWristIKSolver::new(0.001, 0.001);
+    /// ```
+    pub fn new(allowable_target_distance: T, allowable_target_angle: T) -> Self {
+        Self {
+            allowable_target_distance,
+            allowable_target_angle,
+        }
+    }
+    /// Check if the `arm` can be solved by this solver
+    pub fn is_solvable(arm: &SerialChain<T>) -> bool {
+        SphericalWristGeometry::new(arm).is_ok()
+    }
+    /// Calculate all the solutions for `target_pose` within the joint limits
+    ///
+    /// The solutions are sorted by the distance from the current joint positions,
+    /// so the first one is the closest. The joint positions of `arm` are not changed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let mut nodes = Vec::new();
+    /// let axes = [Vector3::z_axis(), Vector3::y_axis(), Vector3::y_axis(),
+    ///             Vector3::z_axis(), Vector3::y_axis(), Vector3::z_axis()];
+    /// let translations = [[0.0, 0.0, 0.0], [0.0, 0.0, 0.4], [0.0, 0.0, 0.5],
+    ///                     [0.0, 0.0, 0.4], [0.0, 0.0, 0.0], [0.0, 0.0, 0.0]];
+    /// for (axis, t) in axes.iter().zip(translations.iter()) {
+    ///     let node = JointBuilder::<f64>::new()
+    ///         .translation(Translation3::new(t[0], t[1], t[2]))
+    ///         .joint_type(JointType::Rotational { axis: *axis })
+    ///         .into_node();
+    ///     if let Some(parent) = nodes.last() {
+    ///         node.set_parent(parent);
+    ///     }
+    ///     nodes.push(node);
+    /// }
+    /// let arm = SerialChain::from_end(nodes.last().unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6]).unwrap();
+    /// let target = arm.end_transform();
+    ///
+    /// let solver = SphericalWristIKSolver::new(0.0001, 0.0001);
+    /// let solutions = solver.solutions(&arm, &target).unwrap();
+    /// assert_eq!(solutions.len(), 8);
+    /// assert!((solutions[0][5] - 0.6).abs() < 0.0001);
+    /// ```
+    pub fn solutions(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+    ) -> Result<Vec<Vec<T>>, IKError> {
+        let geometry = SphericalWristGeometry::new(arm)?;
+        let current = arm.joint_positions();
+        let limits = arm
+            .iter_joints()
+            .map(|joint| joint.limits)
+            .collect::<Vec<_>>();
+        let solutions = geometry
+            .solve(target_pose, &current)
+            .into_iter()
+            .filter_map(|solution| {
+                solution
+                    .iter()
+                    .zip(current.iter().zip(limits.iter()))
+                    .map(|(angle, (reference, limit))| normalize_angle(*angle, *reference, limit))
+                    .collect::<Option<Vec<_>>>()
+            })
+            .filter(|solution| {
+                arm.set_joint_positions_unchecked(solution);
+                let constraints = Constraints::default();
+                let diff = calc_pose_diff_with_constraints(
+                    target_pose,
+                    &arm.end_transform(),
+                    &constraints,
+                );
+                is_target_reached(
+                    &diff,
+                    &constraints,
+                    self.allowable_target_distance,
+                    self.allowable_target_angle,
+                )
+            })
+            .collect::<Vec<_>>();
+        arm.set_joint_positions_unchecked(&current);
+        Ok(rank_solutions(
+            solutions,
+            &current,
+            na::convert(SAME_SOLUTION_TOLERANCE),
+        ))
+    }
+}
+
+impl<T> Default for SphericalWristIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(na::convert(0.001), na::convert(0.005))
+    }
+}
+
+impl<T> InverseKinematicsSolver<T> for SphericalWristIKSolver<T>
+where
+    T: RealField,
+{
+    /// Set joint positions of `arm` to the closest solution to the current positions
+    ///
+    /// Only full constraints are supported.
+    fn solve_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        if constraints_to_bool_array(*constraints)
+            .iter()
+            .any(|use_i| !use_i)
+        {
+            return Err(IKError::PreconditionError {
+                error: "SphericalWristIKSolver supports only full constraints".to_owned(),
+            });
+        }
+        let solutions = self.solutions(arm, target_pose)?;
+        match solutions.first() {
+            Some(solution) => Ok(arm.set_joint_positions(solution)?),
+            None => Err(IKError::NotConvergedError {
+                error: "no analytical solution within the limits".to_owned(),
+            }),
+        }
+    }
+}
+
+impl<T> InverseKinematicsSolutions<T> for SphericalWristIKSolver<T>
+where
+    T: RealField,
+{
+    /// All the analytical solutions within the joint limits
+    ///
+    /// Only full constraints are supported.
+    fn solutions_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<Vec<Vec<T>>, IKError> {
+        if constraints_to_bool_array(*constraints)
+            .iter()
+            .any(|use_i| !use_i)
+        {
+            return Err(IKError::PreconditionError {
+                error: "SphericalWristIKSolver supports only full constraints".to_owned(),
+            });
+        }
+        SphericalWristIKSolver::solutions(self, arm, target_pose)
+    }
+}
+
+#[test]
+fn test_spherical_wrist_solutions() {
+    use na::Translation3;
+    use node::*;
+
+    let axes = [
+        Vector3::z_axis(),
+        Vector3::y_axis(),
+        Vector3::y_axis(),
+        Vector3::x_axis(),
+        Vector3::y_axis(),
+        Vector3::x_axis(),
+    ];
+    // shoulder offset and elbow offset
+    let translations = [
+        [0.0, 0.0, 0.3],
+        [0.05, 0.1, 0.2],
+        [0.0, 0.0, 0.4],
+        [0.35, 0.0, 0.05],
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 0.0],
+    ];
+    let mut nodes: Vec<Node<f64>> = Vec::new();
+    for (axis, t) in axes.iter().zip(translations.iter()) {
+        let node = JointBuilder::new()
+            .translation(Translation3::new(t[0], t[1], t[2]))
+            .joint_type(JointType::Rotational { axis: *axis })
+            .into_node();
+        if let Some(parent) = nodes.last() {
+            node.set_parent(parent);
+        }
+        nodes.push(node);
+    }
+    let tool = JointBuilder::new()
+        .translation(Translation3::new(0.1, 0.0, 0.0))
+        .into_node();
+    tool.set_parent(nodes.last().unwrap());
+    let arm = SerialChain::from_end(&tool);
+    assert!(SphericalWristIKSolver::is_solvable(&arm));
+
+    let answer = vec![0.3, -0.4, 0.8, 0.5, -0.7, 1.2];
+    arm.set_joint_positions(&answer).unwrap();
+    let target = arm.end_transform();
+    arm.set_joint_positions(&[0.2, -0.3, 0.7, 0.4, -0.6, 1.0])
+        .unwrap();
+    let solver = SphericalWristIKSolver::new(1e-6, 1e-6);
+    let solutions = solver.solutions(&arm, &target).unwrap();
+    assert_eq!(solutions.len(), 8);
+    for (a, b) in solutions[0].iter().zip(answer.iter()) {
+        assert!((a - b).abs() < 1e-6);
+    }
+    solver.solve(&arm, &target).unwrap();
+    let diff = arm.end_transform().translation.vector - target.translation.vector;
+    assert!(diff.norm() < 1e-6);
+}
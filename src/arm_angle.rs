@@ -0,0 +1,307 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+//! Arm angle (swivel angle) of 7-DoF arms
+//!
+//! The arm angle is defined by the shoulder, the elbow and the wrist, which a
+//! `SerialChain` does not know. So they are held by `ArmAngleIKSolver` with the
+//! parameters of the iteration, like the other solvers, and it takes the `arm`
+//! in all the methods: `arm_angle` for the current arm angle, `solve` for the IK
+//! with the arm angle, and `sweep` for the self-motion at a fixed end pose.
+use na::{self, DMatrix, DVector, Isometry3, RealField, Vector3};
+
+use chain::*;
+use errors::*;
+use funcs::*;
+use ik::*;
+use node::*;
+
+/// Wrap the angle into [-pi, pi]
+fn wrap_angle<T>(angle: T) -> T
+where
+    T: RealField,
+{
+    angle.sin().atan2(angle.cos())
+}
+
+/// IK solver for 7-DoF arms with the arm angle (swivel angle) of the elbow
+///
+/// The arm angle is the rotation of the elbow around the line from the shoulder
+/// to the wrist. It is zero when the elbow is in the plane which contains the line
+/// and `reference_direction`.
+pub struct ArmAngleIKSolver<T: RealField> {
+    /// The node at the center of the shoulder
+    pub shoulder: Node<T>,
+    /// The node at the elbow
+    pub elbow: Node<T>,
+    /// The node at the center of the wrist
+    pub wrist: Node<T>,
+    /// Direction in the world frame which defines the zero arm angle
+    pub reference_direction: Vector3<T>,
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, it is reached.
+    /// It is also used for the arm angle.
+    pub allowable_target_angle: T,
+    /// multiplier for jacobian
+    pub jacobian_multiplier: T,
+    /// How many times the joints are tried to be moved
+    pub num_max_try: usize,
+}
+
+impl<T> ArmAngleIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `ArmAngleIKSolver`
+    ///
+    /// The zero arm angle is defined by -z direction of the world (elbow down).
+    pub fn new(shoulder: Node<T>, elbow: Node<T>, wrist: Node<T>) -> Self {
+        Self {
+            shoulder,
+            elbow,
+            wrist,
+            reference_direction: -Vector3::z(),
+            allowable_target_distance: na::convert(0.001),
+            allowable_target_angle: na::convert(0.005),
+            jacobian_multiplier: na::convert(0.5),
+            num_max_try: 100,
+        }
+    }
+
+    /// Calculate the current arm angle of `arm`
+    ///
+    /// It returns zero if the elbow is on the line from the shoulder to the wrist.
+    /// The arm angle is not defined if `reference_direction` is parallel to the line.
+    pub fn arm_angle(&self, arm: &SerialChain<T>) -> T {
+        arm.update_transforms();
+        let position = |node: &Node<T>| {
+            node.world_transform()
+                .expect("cache must exist")
+                .translation
+                .vector
+        };
+        let shoulder = position(&self.shoulder);
+        let axis = position(&self.wrist) - shoulder;
+        if axis.norm() < T::default_epsilon() {
+            return T::zero();
+        }
+        let axis = axis.normalize();
+        let perpendicular = |v: Vector3<T>| v - axis * axis.dot(&v);
+        let elbow = perpendicular(position(&self.elbow) - shoulder);
+        let reference = perpendicular(self.reference_direction);
+        axis.dot(&reference.cross(&elbow))
+            .atan2(reference.dot(&elbow))
+    }
+
+    /// Numerical Jacobian of the arm angle (1 x dof)
+    fn arm_angle_jacobian(&self, arm: &SerialChain<T>) -> DMatrix<T> {
+        const STEP: f64 = 1e-6;
+        let step: T = na::convert(STEP);
+        let positions = arm.joint_positions();
+        let mut moved = positions.clone();
+        let mut jacobi = DMatrix::zeros(1, positions.len());
+        for i in 0..positions.len() {
+            moved[i] = positions[i] + step;
+            arm.set_joint_positions_unchecked(&moved);
+            let plus = self.arm_angle(arm);
+            moved[i] = positions[i] - step;
+            arm.set_joint_positions_unchecked(&moved);
+            let minus = self.arm_angle(arm);
+            moved[i] = positions[i];
+            jacobi[(0, i)] = wrap_angle(plus - minus) / (step + step);
+        }
+        arm.set_joint_positions_unchecked(&positions);
+        jacobi
+    }
+
+    fn solve_internal(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        arm_angle: T,
+    ) -> Result<(), IKError> {
+        const EPS: f64 = 0.0001;
+        let constraints = Constraints::default();
+        for _ in 0..self.num_max_try {
+            let end_pose = arm.end_transform();
+            let pose_err = calc_pose_diff_with_constraints(target_pose, &end_pose, &constraints);
+            let angle_err = wrap_angle(arm_angle - self.arm_angle(arm));
+            if is_target_reached(
+                &pose_err,
+                &constraints,
+                self.allowable_target_distance,
+                self.allowable_target_angle,
+            ) && angle_err.abs() < self.allowable_target_angle
+            {
+                let positions = arm.joint_positions();
+                arm.set_joint_positions(&positions)?;
+                return Ok(());
+            }
+            let dof = arm.dof();
+            let mut jacobi = DMatrix::zeros(7, dof);
+            jacobi.rows_mut(0, 6).copy_from(&jacobian(arm));
+            jacobi
+                .rows_mut(6, 1)
+                .copy_from(&self.arm_angle_jacobian(arm));
+            let mut err = DVector::zeros(7);
+            err.rows_mut(0, 6).copy_from(&pose_err);
+            err[6] = angle_err;
+            let d_q = jacobi
+                .svd(true, true)
+                .solve(&err, na::convert(EPS))
+                .map_err(|error| IKError::InvalidArgumentsError {
+                    error: error.to_owned(),
+                })?;
+            let positions = arm
+                .joint_positions()
+                .iter()
+                .zip(d_q.iter())
+                .map(|(position, d)| *position + self.jacobian_multiplier * *d)
+                .collect::<Vec<_>>();
+            arm.set_joint_positions_unchecked(&positions);
+        }
+        Err(IKError::NotConvergedError {
+            error: format!(
+                "iteration has not converged for the arm angle {}: tried {} times",
+                arm_angle, self.num_max_try
+            ),
+        })
+    }
+
+    /// Move the end of `arm` to `target_pose` with the arm angle
+    ///
+    /// The joint positions of `arm` are restored if it fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// // S-R-S 7-DoF arm
+    /// let axes = [Vector3::z_axis(), Vector3::y_axis(), Vector3::z_axis(), Vector3::y_axis(),
+    ///             Vector3::z_axis(), Vector3::y_axis(), Vector3::z_axis()];
+    /// let offsets = [0.0, 0.0, 0.0, 0.4, 0.4, 0.0, 0.0];
+    /// let mut nodes: Vec<Node<f64>> = Vec::new();
+    /// for (axis, offset) in axes.iter().zip(offsets.iter()) {
+    ///     let node = JointBuilder::new()
+    ///         .translation(Translation3::new(0.0, 0.0, *offset))
+    ///         .joint_type(JointType::Rotational { axis: *axis })
+    ///         .into_node();
+    ///     if let Some(parent) = nodes.last() {
+    ///         node.set_parent(parent);
+    ///     }
+    ///     nodes.push(node);
+    /// }
+    /// let arm = SerialChain::from_end(&nodes[6]);
+    /// arm.set_joint_positions(&[0.1, 0.8, 0.0, -1.0, 0.0, 0.3, 0.0]).unwrap();
+    /// let target = arm.end_transform();
+    ///
+    /// let solver = ArmAngleIKSolver::new(nodes[1].clone(), nodes[3].clone(), nodes[5].clone());
+    /// let angle = solver.arm_angle(&arm);
+    /// solver.solve(&arm, &target, angle + 0.5).unwrap();
+    /// assert!((solver.arm_angle(&arm) - angle - 0.5).abs() < 0.005);
+    /// ```
+    pub fn solve(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        arm_angle: T,
+    ) -> Result<(), IKError> {
+        if arm.dof() != 7 {
+            return Err(IKError::PreconditionError {
+                error: format!("ArmAngleIKSolver requires 7 dof, but dof = {}", arm.dof()),
+            });
+        }
+        let orig_positions = arm.joint_positions();
+        let re = self.solve_internal(arm, target_pose, arm_angle);
+        if re.is_err() {
+            arm.set_joint_positions(&orig_positions)?;
+        };
+        re
+    }
+
+    /// Sweep the self-motion at `target_pose` with the arm angles
+    ///
+    /// Returns the pairs of the arm angle and the joint positions which are solved.
+    /// Each arm angle is solved from the solution of the previous one, so sort
+    /// `arm_angles` to move continuously. The joint positions of `arm` are not changed.
+    pub fn sweep(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        arm_angles: &[T],
+    ) -> Vec<(T, Vec<T>)> {
+        let orig_positions = arm.joint_positions();
+        let solutions = arm_angles
+            .iter()
+            .filter_map(|angle| {
+                self.solve(arm, target_pose, *angle)
+                    .ok()
+                    .map(|_| (*angle, arm.joint_positions()))
+            })
+            .collect();
+        arm.set_joint_positions_unchecked(&orig_positions);
+        arm.update_transforms();
+        solutions
+    }
+}
+
+#[test]
+fn test_arm_angle_sweep() {
+    use joint::*;
+    use na::Translation3;
+
+    let axes = [
+        Vector3::z_axis(),
+        Vector3::y_axis(),
+        Vector3::z_axis(),
+        Vector3::y_axis(),
+        Vector3::z_axis(),
+        Vector3::y_axis(),
+        Vector3::z_axis(),
+    ];
+    let offsets = [0.0, 0.0, 0.0, 0.4, 0.4, 0.0, 0.0];
+    let mut nodes: Vec<Node<f64>> = Vec::new();
+    for (axis, offset) in axes.iter().zip(offsets.iter()) {
+        let node = JointBuilder::new()
+            .translation(Translation3::new(0.0, 0.0, *offset))
+            .joint_type(JointType::Rotational { axis: *axis })
+            .into_node();
+        if let Some(parent) = nodes.last() {
+            node.set_parent(parent);
+        }
+        nodes.push(node);
+    }
+    let arm = SerialChain::from_end(&nodes[6]);
+    arm.set_joint_positions(&[0.1, 0.8, 0.0, -1.0, 0.0, 0.3, 0.0])
+        .unwrap();
+    let target = arm.end_transform();
+    let solver = ArmAngleIKSolver::new(nodes[1].clone(), nodes[3].clone(), nodes[5].clone());
+    let angle = solver.arm_angle(&arm);
+    let angles = (0..5).map(|i| angle + 0.2 * i as f64).collect::<Vec<_>>();
+    let elbow = nodes[3].world_transform().unwrap();
+    let solutions = solver.sweep(&arm, &target, &angles);
+    assert_eq!(solutions.len(), 5);
+    // the transforms are restored with the positions
+    let restored = nodes[3].world_transform().unwrap();
+    assert!((restored.translation.vector - elbow.translation.vector).norm() < 1e-9);
+    for (angle, positions) in solutions {
+        arm.set_joint_positions(&positions).unwrap();
+        let diff = arm.end_transform().translation.vector - target.translation.vector;
+        assert!(diff.norm() < 0.001);
+        assert!((solver.arm_angle(&arm) - angle).abs() < 0.005);
+    }
+}
@@ -0,0 +1,378 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, DVector, RealField, Vector2};
+
+use chain::*;
+use errors::*;
+use funcs::*;
+use multi_ik::*;
+
+/// Check if the point is inside the polygon on the ground (x-y plane)
+///
+/// The vertices of the polygon are in order (clockwise or counterclockwise).
+///
+/// # Examples
+///
+/// ```
+/// # extern crate nalgebra as na;
+/// # extern crate k;
+/// use na::Vector2;
+///
+/// # fn main() {
+/// let square = vec![
+///     Vector2::new(-0.1, -0.1),
+///     Vector2::new(0.1, -0.1),
+///     Vector2::new(0.1, 0.1),
+///     Vector2::new(-0.1, 0.1),
+/// ];
+/// assert!(k::is_inside_polygon(&Vector2::new(0.05, 0.0), &square));
+/// assert!(!k::is_inside_polygon(&Vector2::new(0.15, 0.0), &square));
+/// # }
+/// ```
+pub fn is_inside_polygon<T>(point: &Vector2<T>, polygon: &[Vector2<T>]) -> bool
+where
+    T: RealField,
+{
+    // ray casting to +x direction
+    let mut is_inside = false;
+    for i in 0..polygon.len() {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.y > point.y) != (b.y > point.y) {
+            let x = a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y);
+            if point.x < x {
+                is_inside = !is_inside;
+            }
+        }
+    }
+    is_inside
+}
+
+/// Check if the projection of the center of mass of `chain` is inside `support_polygon`
+pub fn is_statically_stable<T>(chain: &Chain<T>, support_polygon: &[Vector2<T>]) -> bool
+where
+    T: RealField,
+{
+    let com = center_of_mass(chain);
+    is_inside_polygon(&Vector2::new(com.x, com.y), support_polygon)
+}
+
+/// Inverse Kinematics Solver for the targets and the center of mass
+///
+/// The x-y position of the center of mass (the projection to the ground) is
+/// solved with the targets of the end effectors at the same time, using the
+/// Jacobian of the center of mass. The result is statically stable: the center
+/// of mass is inside the support polygon.
+pub struct BalanceIKSolver<T: RealField> {
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, it is reached.
+    pub allowable_target_angle: T,
+    /// If the distance of the center of mass is smaller than this value, it is reached.
+    pub allowable_com_distance: T,
+    /// multiplier for jacobian
+    pub jacobian_multiplier: T,
+    /// How many times the joints are tried to be moved
+    pub num_max_try: usize,
+}
+
+impl<T> BalanceIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `BalanceIKSolver`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::BalanceIKSolver::new(0.001, 0.005, 0.001, 0.5, 100);
+    /// ```
+    pub fn new(
+        allowable_target_distance: T,
+        allowable_target_angle: T,
+        allowable_com_distance: T,
+        jacobian_multiplier: T,
+        num_max_try: usize,
+    ) -> Self {
+        Self {
+            allowable_target_distance,
+            allowable_target_angle,
+            allowable_com_distance,
+            jacobian_multiplier,
+            num_max_try,
+        }
+    }
+
+    fn com_error(chain: &Chain<T>, com_target: &Vector2<T>) -> DVector<T> {
+        let com = center_of_mass(chain);
+        DVector::from_column_slice(&[com_target.x - com.x, com_target.y - com.y])
+    }
+
+    fn is_reached(
+        &self,
+        chain: &Chain<T>,
+        targets: &[IKTarget<T>],
+        com_target: &Vector2<T>,
+    ) -> bool {
+        Self::com_error(chain, com_target).norm() < self.allowable_com_distance
+            && targets.iter().all(|target| {
+                target.is_reached(self.allowable_target_distance, self.allowable_target_angle)
+            })
+    }
+
+    fn solve_internal(
+        &self,
+        chain: &Chain<T>,
+        targets: &[IKTarget<T>],
+        com_target: &Vector2<T>,
+        support_polygon: &[Vector2<T>],
+    ) -> Result<(), IKError> {
+        const EPS: f64 = 0.0001;
+        for _ in 0..self.num_max_try {
+            let (target_jacobi, target_err) = stack_jacobians_and_errors(chain, targets);
+            let com_jacobi = center_of_mass_jacobian(chain);
+            let com_err = Self::com_error(chain, com_target);
+            let rows = target_err.len();
+            let mut jacobi = DMatrix::zeros(rows + 2, chain.dof());
+            jacobi.rows_mut(0, rows).copy_from(&target_jacobi);
+            jacobi.rows_mut(rows, 2).copy_from(&com_jacobi.rows(0, 2));
+            let mut err = DVector::zeros(rows + 2);
+            err.rows_mut(0, rows).copy_from(&target_err);
+            err.rows_mut(rows, 2).copy_from(&com_err);
+            let d_q = jacobi
+                .svd(true, true)
+                .solve(&err, na::convert(EPS))
+                .map_err(|error| IKError::InvalidArgumentsError {
+                    error: error.to_owned(),
+                })?;
+            let positions = chain
+                .joint_positions()
+                .iter()
+                .zip(d_q.iter())
+                .map(|(position, d)| *position + self.jacobian_multiplier * *d)
+                .collect::<Vec<_>>();
+            chain.set_joint_positions_unchecked(&positions);
+            chain.update_transforms();
+            if self.is_reached(chain, targets, com_target) {
+                chain.set_joint_positions(&positions)?;
+                if !is_statically_stable(chain, support_polygon) {
+                    return Err(IKError::NotConvergedError {
+                        error: "the center of mass is out of the support polygon".to_owned(),
+                    });
+                }
+                return Ok(());
+            }
+        }
+        Err(IKError::NotConvergedError {
+            error: format!(
+                "iteration has not converged for {} targets and the center of mass: tried {} times",
+                targets.len(),
+                self.num_max_try
+            ),
+        })
+    }
+
+    /// Move the `end`s of the `targets` and the x-y position of the center of mass to `com_target`
+    ///
+    /// `com_target` must be inside `support_polygon`, and it fails if the center of mass
+    /// of the result is not inside `support_polygon` (see `is_statically_stable`).
+    /// The joint positions of `chain` are restored if it fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # extern crate nalgebra as na;
+    /// # extern crate k;
+    /// use na::Vector2;
+    ///
+    /// # fn main() {
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// chain
+    ///     .set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3, 0.1, 0.2, 0.0, -0.5, 0.0, -0.3])
+    ///     .unwrap();
+    /// chain.update_transforms();
+    /// let r_wrist = chain.find("r_wrist_pitch").unwrap();
+    /// let mut target_pose = r_wrist.world_transform().unwrap();
+    /// target_pose.translation.vector.x += 0.05;
+    /// let com = k::center_of_mass(&chain);
+    /// let support_polygon = vec![
+    ///     Vector2::new(com.x - 0.1, com.y - 0.1),
+    ///     Vector2::new(com.x + 0.1, com.y - 0.1),
+    ///     Vector2::new(com.x + 0.1, com.y + 0.1),
+    ///     Vector2::new(com.x - 0.1, com.y + 0.1),
+    /// ];
+    ///
+    /// let solver = k::BalanceIKSolver::new(0.001, 0.005, 0.001, 0.5, 100);
+    /// solver
+    ///     .solve(
+    ///         &chain,
+    ///         &[k::IKTarget::new(r_wrist.clone(), target_pose)],
+    ///         &Vector2::new(com.x, com.y),
+    ///         &support_polygon,
+    ///     )
+    ///     .unwrap_or_else(|err| println!("Err: {}", err));
+    /// # }
+    /// ```
+    pub fn solve(
+        &self,
+        chain: &Chain<T>,
+        targets: &[IKTarget<T>],
+        com_target: &Vector2<T>,
+        support_polygon: &[Vector2<T>],
+    ) -> Result<(), IKError> {
+        if !is_inside_polygon(com_target, support_polygon) {
+            return Err(IKError::PreconditionError {
+                error: format!(
+                    "com_target {:?} is out of the support polygon",
+                    (com_target.x, com_target.y)
+                ),
+            });
+        }
+        let total_mass = chain
+            .iter()
+            .filter_map(|node| node.link().as_ref().map(|link| link.inertial.mass))
+            .fold(T::zero(), |sum, mass| sum + mass);
+        if total_mass <= T::zero() {
+            return Err(IKError::PreconditionError {
+                error: "the chain has no mass".to_owned(),
+            });
+        }
+        let orig_positions = chain.joint_positions();
+        let re = self.solve_internal(chain, targets, com_target, support_polygon);
+        if re.is_err() {
+            chain.set_joint_positions(&orig_positions)?;
+            chain.update_transforms();
+        };
+        re
+    }
+}
+
+impl<T> Default for BalanceIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(
+            na::convert(0.001),
+            na::convert(0.005),
+            na::convert(0.001),
+            na::convert(0.5),
+            100,
+        )
+    }
+}
+
+#[test]
+fn test_balance_ik() {
+    use ik::Constraints;
+    use joint::*;
+    use link::*;
+    use na::{Isometry3, Translation3, Vector3};
+    use node::*;
+
+    let mass_link = |mass: f64, x: f64, z: f64| {
+        let mut inertial = Inertial::from_mass(mass);
+        inertial.set_origin(Isometry3::translation(x, 0.0, z));
+        LinkBuilder::new().inertial(inertial).finalize()
+    };
+    let pitch = || JointType::Rotational {
+        axis: Vector3::y_axis(),
+    };
+    let torso = JointBuilder::new()
+        .name("torso")
+        .joint_type(pitch())
+        .into_node();
+    torso.set_link(Some(mass_link(10.0, 0.0, 0.5)));
+    let shoulder = JointBuilder::new()
+        .name("shoulder")
+        .translation(Translation3::new(0.0, 0.0, 1.0))
+        .joint_type(pitch())
+        .into_node();
+    shoulder.set_link(Some(mass_link(1.0, 0.25, 0.0)));
+    let elbow = JointBuilder::new()
+        .name("elbow")
+        .translation(Translation3::new(0.5, 0.0, 0.0))
+        .joint_type(pitch())
+        .into_node();
+    elbow.set_link(Some(mass_link(1.0, 0.25, 0.0)));
+    let hand = JointBuilder::new()
+        .name("hand")
+        .translation(Translation3::new(0.5, 0.0, 0.0))
+        .into_node();
+    shoulder.set_parent(&torso);
+    elbow.set_parent(&shoulder);
+    hand.set_parent(&elbow);
+    let chain = Chain::<f64>::from_root(torso);
+
+    chain.set_joint_positions(&[0.1, -0.5, 0.3]).unwrap();
+    chain.update_transforms();
+    let target_pose = hand.world_transform().unwrap();
+    let com = center_of_mass(&chain);
+    chain.set_joint_positions(&[0.0, 0.0, 0.0]).unwrap();
+
+    let constraints = Constraints {
+        position_y: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    let targets = vec![IKTarget::with_constraints(
+        hand.clone(),
+        target_pose,
+        constraints,
+    )];
+    let polygon = vec![
+        Vector2::new(com.x - 0.1, -0.1),
+        Vector2::new(com.x + 0.1, -0.1),
+        Vector2::new(com.x + 0.1, 0.1),
+        Vector2::new(com.x - 0.1, 0.1),
+    ];
+    let solver = BalanceIKSolver::new(0.0001, 0.001, 0.0001, 0.5, 100);
+    solver
+        .solve(&chain, &targets, &Vector2::new(com.x, com.y), &polygon)
+        .unwrap();
+    let solved_com = center_of_mass(&chain);
+    assert!((solved_com.x - com.x).abs() < 0.0001);
+    let diff = hand.world_transform().unwrap().translation.vector - target_pose.translation.vector;
+    assert!(diff.norm() < 0.0001);
+    assert!(is_statically_stable(&chain, &polygon));
+
+    // the target of the center of mass must be inside the support polygon
+    let positions = chain.joint_positions();
+    match solver.solve(
+        &chain,
+        &targets,
+        &Vector2::new(com.x + 0.2, com.y),
+        &polygon,
+    ) {
+        Err(IKError::PreconditionError { .. }) => {}
+        _ => panic!("must be precondition error"),
+    }
+    assert_eq!(chain.joint_positions(), positions);
+
+    // the transforms are restored with the positions after the failure
+    let unreachable = vec![IKTarget::with_constraints(
+        hand.clone(),
+        Isometry3::translation(3.0, 0.0, 1.0),
+        constraints,
+    )];
+    assert!(solver
+        .solve(&chain, &unreachable, &Vector2::new(com.x, com.y), &polygon)
+        .is_err());
+    let restored = hand.world_transform().unwrap().translation.vector;
+    assert!((restored - target_pose.translation.vector).norm() < 0.0001);
+}
@@ -0,0 +1,253 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, Isometry3, RealField};
+
+use chain::*;
+use errors::*;
+use funcs::*;
+use ik::*;
+
+/// Solve IK along a Cartesian path of the end of an arm
+///
+/// Each pose is solved from the solution of the previous pose (warm start),
+/// and the solutions are checked to keep the joint motion continuous.
+pub struct CartesianPathSolver<T, S>
+where
+    T: RealField,
+    S: InverseKinematicsSolver<T>,
+{
+    /// The solver which is used for each pose
+    pub solver: S,
+    /// Maximum change of each joint position between the adjacent poses
+    pub max_joint_step: T,
+    /// It fails if the manipulability of the constrained coordinates becomes smaller than this
+    pub min_manipulability: T,
+}
+
+impl<T, S> CartesianPathSolver<T, S>
+where
+    T: RealField,
+    S: InverseKinematicsSolver<T>,
+{
+    /// Create instance of `CartesianPathSolver`
+    ///
+    /// The singularity is not checked by default (`min_manipulability` = 0).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::CartesianPathSolver::new(k::JacobianIKSolver::<f64>::default(), 0.1);
+    /// ```
+    pub fn new(solver: S, max_joint_step: T) -> Self {
+        Self {
+            solver,
+            max_joint_step,
+            min_manipulability: T::zero(),
+        }
+    }
+
+    /// Solve IK for all the `poses` in order and returns the joint positions for them
+    ///
+    /// The joint positions of `arm` are set to the last solution if it succeeds,
+    /// or restored if it fails. The error has the index of the failed pose.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let start = arm.end_transform();
+    /// // straight line to -x direction
+    /// let poses = (0..=10)
+    ///     .map(|i| {
+    ///         let mut pose = start.clone();
+    ///         pose.translation.vector.x -= 0.01 * i as f64;
+    ///         pose
+    ///     })
+    ///     .collect::<Vec<_>>();
+    ///
+    /// let solver = k::CartesianPathSolver::new(k::JacobianIKSolver::default(), 0.1);
+    /// let trajectory = solver
+    ///     .solve_path(&arm, &poses, &k::Constraints::default())
+    ///     .unwrap();
+    /// assert_eq!(trajectory.len(), 11);
+    /// ```
+    pub fn solve_path(
+        &self,
+        arm: &SerialChain<T>,
+        poses: &[Isometry3<T>],
+        constraints: &Constraints<T>,
+    ) -> Result<Vec<Vec<T>>, CartesianPathError> {
+        let orig_positions = arm.joint_positions();
+        let re = self.solve_path_internal(arm, poses, constraints);
+        if re.is_err() {
+            arm.set_joint_positions_unchecked(&orig_positions);
+        }
+        re
+    }
+
+    fn solve_path_internal(
+        &self,
+        arm: &SerialChain<T>,
+        poses: &[Isometry3<T>],
+        constraints: &Constraints<T>,
+    ) -> Result<Vec<Vec<T>>, CartesianPathError> {
+        let mut previous = arm.joint_positions();
+        let mut trajectory = Vec::with_capacity(poses.len());
+        for (index, pose) in poses.iter().enumerate() {
+            self.solver
+                .solve_with_constraints(arm, pose, constraints)
+                .map_err(|error| CartesianPathError::IKError { index, error })?;
+            let positions = arm.joint_positions();
+            for ((joint, current), prev) in
+                arm.iter_joints().zip(positions.iter()).zip(previous.iter())
+            {
+                if (*current - *prev).abs() > self.max_joint_step {
+                    return Err(CartesianPathError::JointJumpError {
+                        index,
+                        error: format!("{} moved from {} to {}", joint.name, prev, current),
+                    });
+                }
+            }
+            if self.min_manipulability > T::zero() {
+                // manipulability of the constrained coordinates
+                let value =
+                    jacobian_with_constraints(jacobian(arm), &arm.end_transform(), constraints)
+                        .singular_values()
+                        .iter()
+                        .fold(T::one(), |product, value| product * *value);
+                if value < self.min_manipulability {
+                    return Err(CartesianPathError::SingularityError {
+                        index,
+                        error: format!("manipulability = {}", value),
+                    });
+                }
+            }
+            trajectory.push(positions.clone());
+            previous = positions;
+        }
+        Ok(trajectory)
+    }
+
+    /// Solve IK along the parametric `path` which is sampled at `num_steps + 1` points
+    ///
+    /// `path` maps the parameter in [0, 1] to the pose of the end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let start = arm.end_transform();
+    /// // arc in the x-z plane
+    /// let path = |t: f64| {
+    ///     let mut pose = start.clone();
+    ///     let angle = t * std::f64::consts::PI;
+    ///     pose.translation.vector.x += 0.05 * (1.0 - angle.cos());
+    ///     pose.translation.vector.z += 0.05 * angle.sin();
+    ///     pose
+    /// };
+    ///
+    /// let solver = k::CartesianPathSolver::new(k::JacobianIKSolver::default(), 0.1);
+    /// let trajectory = solver
+    ///     .solve_parametric(&arm, path, 20, &k::Constraints::default())
+    ///     .unwrap();
+    /// assert_eq!(trajectory.len(), 21);
+    /// ```
+    pub fn solve_parametric<F>(
+        &self,
+        arm: &SerialChain<T>,
+        path: F,
+        num_steps: usize,
+        constraints: &Constraints<T>,
+    ) -> Result<Vec<Vec<T>>, CartesianPathError>
+    where
+        F: Fn(T) -> Isometry3<T>,
+    {
+        let poses = (0..=num_steps)
+            .map(|i| path(na::convert(i as f64 / num_steps.max(1) as f64)))
+            .collect::<Vec<_>>();
+        self.solve_path(arm, &poses, constraints)
+    }
+}
+
+#[test]
+fn test_cartesian_path_singularity() {
+    use joint::*;
+    use na::{Translation3, Vector3};
+    use node::*;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    end.set_parent(&j1);
+    let arm = SerialChain::<f64>::from_end(&end);
+    arm.set_joint_positions(&[0.0, 1.0]).unwrap();
+    let start = arm.end_transform().translation.vector;
+    let constraints = Constraints {
+        position_z: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    // straight line toward the boundary of the workspace, which ends at distance 1.98
+    // where the manipulability (= |sin(j1)|) is 0.28, but it is still reachable
+    let scale = 1.98 / start.norm();
+    let path = |t: f64| {
+        let mut pose = Isometry3::identity();
+        pose.translation.vector = start * (1.0 + t * (scale - 1.0));
+        pose
+    };
+    let mut solver = CartesianPathSolver::new(JacobianIKSolver::new(0.0001, 0.001, 0.5, 100), 0.3);
+    // all the poses are solved without the check of the singularity
+    assert_eq!(
+        solver
+            .solve_parametric(&arm, path, 10, &constraints)
+            .unwrap()
+            .len(),
+        11
+    );
+    arm.set_joint_positions(&[0.0, 1.0]).unwrap();
+    solver.min_manipulability = 0.3;
+    let error = solver
+        .solve_parametric(&arm, path, 10, &constraints)
+        .unwrap_err();
+    match error {
+        CartesianPathError::SingularityError { index, .. } => assert_eq!(index, 10),
+        _ => panic!("unexpected error {}", error),
+    }
+    assert!(error.index() > 0);
+    // restored
+    assert_eq!(arm.joint_positions(), vec![0.0, 1.0]);
+}
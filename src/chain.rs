@@ -172,6 +172,21 @@ impl<T: RealField> Chain<T> {
             contained_joints,
         }
     }
+    /// Create `Chain` from the nodes which are sorted from parent to children
+    ///
+    /// The nodes are shared with the original chain.
+    pub(crate) fn from_nodes(contained_joints: Vec<Node<T>>) -> Chain<T> {
+        let movable_joints = contained_joints
+            .iter()
+            .filter(|joint| joint.joint().is_movable())
+            .cloned()
+            .collect::<Vec<_>>();
+        Chain {
+            dof: movable_joints.len(),
+            movable_joints,
+            contained_joints,
+        }
+    }
     /// Iterate for all joint nodes
     ///
     /// The order is from parent to children. You can assume that parent is already iterated.
@@ -275,6 +290,8 @@ impl<T: RealField> Chain<T> {
     /// Set the positions of the joints
     ///
     /// `FixedJoints` are ignored. the input number must be equal with `dof()`
+    /// The positions of the mimic joints are ignored, because they are
+    /// determined by their driving joints.
     pub fn set_joint_positions(&self, positions_vec: &[T]) -> Result<(), JointError> {
         if positions_vec.len() != self.dof {
             return Err(JointError::SizeMismatchError {
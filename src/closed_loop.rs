@@ -0,0 +1,446 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, DVector, Isometry3, RealField, Vector3};
+
+use chain::*;
+use errors::*;
+use funcs::*;
+use multi_ik::*;
+use node::*;
+
+/// Type of the loop-closure constraint
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopConstraintType {
+    /// The origins of the two frames coincide (3 equations), like a spherical joint
+    Point,
+    /// The two frames coincide (6 equations), like a rigid connection
+    Frame,
+}
+
+/// Loop-closure constraint between two nodes of a `Chain`
+///
+/// `Node`s form a tree, so a closed kinematic loop (for example a four-bar linkage)
+/// is represented by two branches of the tree and this constraint which connects
+/// their ends. The constrained frames are `offset_a` and `offset_b` from the nodes.
+#[derive(Debug, Clone)]
+pub struct LoopConstraint<T: RealField> {
+    /// The node of one side of the loop
+    pub node_a: Node<T>,
+    /// The node of the other side of the loop
+    pub node_b: Node<T>,
+    /// The constrained frame in the frame of `node_a`
+    pub offset_a: Isometry3<T>,
+    /// The constrained frame in the frame of `node_b`
+    pub offset_b: Isometry3<T>,
+    /// Which coordinates are constrained
+    pub constraint_type: LoopConstraintType,
+}
+
+impl<T> LoopConstraint<T>
+where
+    T: RealField,
+{
+    /// Create the constraint between the origins of the nodes
+    pub fn new(node_a: Node<T>, node_b: Node<T>, constraint_type: LoopConstraintType) -> Self {
+        Self::with_offsets(
+            node_a,
+            Isometry3::identity(),
+            node_b,
+            Isometry3::identity(),
+            constraint_type,
+        )
+    }
+
+    /// Create the constraint between the frames which are attached to the nodes
+    pub fn with_offsets(
+        node_a: Node<T>,
+        offset_a: Isometry3<T>,
+        node_b: Node<T>,
+        offset_b: Isometry3<T>,
+        constraint_type: LoopConstraintType,
+    ) -> Self {
+        Self {
+            node_a,
+            node_b,
+            offset_a,
+            offset_b,
+            constraint_type,
+        }
+    }
+
+    fn num_rows(&self) -> usize {
+        match self.constraint_type {
+            LoopConstraintType::Point => 3,
+            LoopConstraintType::Frame => 6,
+        }
+    }
+
+    fn world_frame(node: &Node<T>, offset: &Isometry3<T>) -> Isometry3<T> {
+        node.world_transform().expect("cache must exist") * offset
+    }
+
+    /// The error from the frame of `a` to the frame of `b`
+    ///
+    /// Call `Chain::update_transforms()` before using this method.
+    pub fn error(&self) -> DVector<T> {
+        let frame_a = Self::world_frame(&self.node_a, &self.offset_a);
+        let frame_b = Self::world_frame(&self.node_b, &self.offset_b);
+        let p_diff = frame_b.translation.vector - frame_a.translation.vector;
+        match self.constraint_type {
+            LoopConstraintType::Point => DVector::from_column_slice(p_diff.as_slice()),
+            LoopConstraintType::Frame => {
+                let w_diff = frame_a
+                    .rotation
+                    .rotation_to(&frame_b.rotation)
+                    .scaled_axis();
+                DVector::from_column_slice(&[
+                    p_diff[0], p_diff[1], p_diff[2], w_diff[0], w_diff[1], w_diff[2],
+                ])
+            }
+        }
+    }
+
+    /// The Jacobian of the frame which is attached to `node` (6 x dof)
+    fn frame_jacobian(chain: &Chain<T>, node: &Node<T>, offset: &Isometry3<T>) -> DMatrix<T> {
+        let mut jacobi = jacobian_for_node(chain, node);
+        // v_p = v_o + w x (p - o)
+        let r: Vector3<T> = Self::world_frame(node, offset).translation.vector
+            - node
+                .world_transform()
+                .expect("cache must exist")
+                .translation
+                .vector;
+        let linear = r.cross_matrix() * jacobi.rows(3, 3);
+        let mut top = jacobi.rows_mut(0, 3);
+        top -= linear;
+        jacobi
+    }
+
+    /// The Jacobian of the error (rows x `chain.dof()`)
+    ///
+    /// The joint displacement `dq` which satisfies `jacobian * dq = error` closes the loop.
+    /// Both nodes must be contained in `chain`.
+    pub fn jacobian(&self, chain: &Chain<T>) -> DMatrix<T> {
+        let jacobi = Self::frame_jacobian(chain, &self.node_a, &self.offset_a)
+            - Self::frame_jacobian(chain, &self.node_b, &self.offset_b);
+        jacobi.rows(0, self.num_rows()).into_owned()
+    }
+}
+
+/// Solver for the mechanisms which have closed kinematic loops
+///
+/// The joint positions are projected onto the constraint manifold by
+/// the Gauss-Newton method, and the targets are solved in the nullspace of
+/// the loop constraints.
+pub struct ClosedLoopSolver<T: RealField> {
+    /// The loop-closure constraints
+    pub constraints: Vec<LoopConstraint<T>>,
+    /// If the errors of all the constraints are smaller than this value, they are satisfied.
+    pub allowable_constraint_error: T,
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, it is reached.
+    pub allowable_target_angle: T,
+    /// multiplier for jacobian
+    pub jacobian_multiplier: T,
+    /// How many times the joints are tried to be moved
+    pub num_max_try: usize,
+}
+
+impl<T> ClosedLoopSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `ClosedLoopSolver`
+    pub fn new(constraints: Vec<LoopConstraint<T>>) -> Self {
+        Self {
+            constraints,
+            allowable_constraint_error: na::convert(0.0001),
+            allowable_target_distance: na::convert(0.001),
+            allowable_target_angle: na::convert(0.005),
+            jacobian_multiplier: na::convert(0.5),
+            num_max_try: 100,
+        }
+    }
+
+    /// Stack the Jacobians and the errors of all the constraints vertically
+    pub fn constraints_jacobian_and_error(&self, chain: &Chain<T>) -> (DMatrix<T>, DVector<T>) {
+        chain.update_transforms();
+        let rows = self.constraints.iter().map(|c| c.num_rows()).sum();
+        let mut jacobi = DMatrix::zeros(rows, chain.dof());
+        let mut err = DVector::zeros(rows);
+        let mut index = 0;
+        for constraint in &self.constraints {
+            let num = constraint.num_rows();
+            jacobi
+                .rows_mut(index, num)
+                .copy_from(&constraint.jacobian(chain));
+            err.rows_mut(index, num).copy_from(&constraint.error());
+            index += num;
+        }
+        (jacobi, err)
+    }
+
+    /// Check if all the constraints are satisfied
+    pub fn is_satisfied(&self, chain: &Chain<T>) -> bool {
+        chain.update_transforms();
+        self.constraints
+            .iter()
+            .all(|constraint| constraint.error().amax() < self.allowable_constraint_error)
+    }
+
+    /// Multiply the projector to the nullspace of the constraints to `jacobi` (rows x dof)
+    ///
+    /// The joint displacement which is calculated with the returned Jacobian
+    /// keeps the constraints (to the first order).
+    pub fn constrained_jacobian(
+        &self,
+        chain: &Chain<T>,
+        jacobi: &DMatrix<T>,
+    ) -> Result<DMatrix<T>, IKError> {
+        const EPS: f64 = 0.0001;
+        let (constraints_jacobi, _) = self.constraints_jacobian_and_error(chain);
+        let dof = chain.dof();
+        let constraints_jacobi_inv = constraints_jacobi
+            .clone()
+            .pseudo_inverse(na::convert(EPS))
+            .map_err(|error| IKError::InvalidArgumentsError {
+                error: error.to_owned(),
+            })?;
+        Ok(jacobi * (DMatrix::identity(dof, dof) - constraints_jacobi_inv * constraints_jacobi))
+    }
+
+    fn project_internal(&self, chain: &Chain<T>) -> Result<(), IKError> {
+        for _ in 0..self.num_max_try {
+            if self.is_satisfied(chain) {
+                let positions = chain.joint_positions();
+                chain.set_joint_positions(&positions)?;
+                return Ok(());
+            }
+            let task = self.constraints_jacobian_and_error(chain);
+            let d_q = prioritized_displacement(&[task], chain.dof(), None)?;
+            let positions = chain
+                .joint_positions()
+                .iter()
+                .zip(d_q.iter())
+                .map(|(position, d)| *position + *d)
+                .collect::<Vec<_>>();
+            chain.set_joint_positions_unchecked(&positions);
+        }
+        Err(IKError::NotConvergedError {
+            error: format!(
+                "projection to the loop constraints has not converged: tried {} times",
+                self.num_max_try
+            ),
+        })
+    }
+
+    /// Move the joints of `chain` to satisfy the loop constraints
+    ///
+    /// The joints are moved as little as possible (minimum norm).
+    /// The joint positions of `chain` are restored if it fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// // parallelogram linkage
+    /// let base = JointBuilder::<f64>::new().into_node();
+    /// let rotational = |name: &str, x: f64, y: f64| {
+    ///     JointBuilder::new()
+    ///         .name(name)
+    ///         .translation(Translation3::new(x, y, 0.0))
+    ///         .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+    ///         .into_node()
+    /// };
+    /// let crank = rotational("crank", 0.0, 0.0);
+    /// let coupler = rotational("coupler", 0.0, 1.0);
+    /// let coupler_end = JointBuilder::new().translation(Translation3::new(1.0, 0.0, 0.0)).into_node();
+    /// let rocker = rotational("rocker", 1.0, 0.0);
+    /// let rocker_end = JointBuilder::new().translation(Translation3::new(0.0, 1.0, 0.0)).into_node();
+    /// crank.set_parent(&base);
+    /// coupler.set_parent(&crank);
+    /// coupler_end.set_parent(&coupler);
+    /// rocker.set_parent(&base);
+    /// rocker_end.set_parent(&rocker);
+    /// let chain = Chain::from_root(base);
+    ///
+    /// let solver = ClosedLoopSolver::new(vec![LoopConstraint::new(
+    ///     coupler_end.clone(),
+    ///     rocker_end.clone(),
+    ///     LoopConstraintType::Point,
+    /// )]);
+    /// crank.set_joint_position(0.3).unwrap();
+    /// assert!(!solver.is_satisfied(&chain));
+    /// solver.project(&chain).unwrap();
+    /// assert!(solver.is_satisfied(&chain));
+    /// ```
+    pub fn project(&self, chain: &Chain<T>) -> Result<(), IKError> {
+        let orig_positions = chain.joint_positions();
+        let re = self.project_internal(chain);
+        if re.is_err() {
+            chain.set_joint_positions(&orig_positions)?;
+            chain.update_transforms();
+        }
+        re
+    }
+
+    fn is_reached(&self, chain: &Chain<T>, targets: &[IKTarget<T>]) -> bool {
+        self.is_satisfied(chain)
+            && targets.iter().all(|target| {
+                target.is_reached(self.allowable_target_distance, self.allowable_target_angle)
+            })
+    }
+
+    fn solve_internal(&self, chain: &Chain<T>, targets: &[IKTarget<T>]) -> Result<(), IKError> {
+        for _ in 0..self.num_max_try {
+            let tasks = [
+                self.constraints_jacobian_and_error(chain),
+                stack_jacobians_and_errors(chain, targets),
+            ];
+            let d_q = prioritized_displacement(&tasks, chain.dof(), None)?;
+            let positions = chain
+                .joint_positions()
+                .iter()
+                .zip(d_q.iter())
+                .map(|(position, d)| *position + self.jacobian_multiplier * *d)
+                .collect::<Vec<_>>();
+            chain.set_joint_positions_unchecked(&positions);
+            if self.is_reached(chain, targets) {
+                let positions = chain.joint_positions();
+                chain.set_joint_positions(&positions)?;
+                return Ok(());
+            }
+        }
+        Err(IKError::NotConvergedError {
+            error: format!(
+                "iteration has not converged for {} targets with {} loop constraints: tried {} times",
+                targets.len(),
+                self.constraints.len(),
+                self.num_max_try
+            ),
+        })
+    }
+
+    /// Move the nodes to the targets keeping the loop constraints
+    ///
+    /// The loop constraints have the higher priority than the targets.
+    /// The joint positions of `chain` are restored if it fails.
+    pub fn solve(&self, chain: &Chain<T>, targets: &[IKTarget<T>]) -> Result<(), IKError> {
+        let orig_positions = chain.joint_positions();
+        let re = self.solve_internal(chain, targets);
+        if re.is_err() {
+            chain.set_joint_positions(&orig_positions)?;
+            chain.update_transforms();
+        }
+        re
+    }
+}
+
+impl<T> Default for ClosedLoopSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+#[test]
+fn test_four_bar_linkage_ik() {
+    use ik::Constraints;
+    use joint::*;
+    use na::Translation3;
+
+    let base = JointBuilder::<f64>::new().into_node();
+    let rotational = |name: &str, x: f64, y: f64| {
+        JointBuilder::new()
+            .name(name)
+            .translation(Translation3::new(x, y, 0.0))
+            .joint_type(JointType::Rotational {
+                axis: Vector3::z_axis(),
+            })
+            .into_node()
+    };
+    let crank = rotational("crank", 0.0, 0.0);
+    let coupler = rotational("coupler", 0.0, 1.0);
+    // the end is at the middle of the coupler, the other side is closed by the constraint
+    let coupler_middle = JointBuilder::new()
+        .translation(Translation3::new(0.5, 0.0, 0.0))
+        .into_node();
+    let rocker = rotational("rocker", 1.0, 0.0);
+    let rocker_end = JointBuilder::new()
+        .translation(Translation3::new(0.0, 1.0, 0.0))
+        .into_node();
+    crank.set_parent(&base);
+    coupler.set_parent(&crank);
+    coupler_middle.set_parent(&coupler);
+    rocker.set_parent(&base);
+    rocker_end.set_parent(&rocker);
+    let chain = Chain::from_root(base);
+    let constraint = LoopConstraint::with_offsets(
+        coupler_middle.clone(),
+        Isometry3::translation(0.5, 0.0, 0.0),
+        rocker_end.clone(),
+        Isometry3::identity(),
+        LoopConstraintType::Point,
+    );
+    let mut solver = ClosedLoopSolver::new(vec![constraint]);
+    assert!(solver.is_satisfied(&chain));
+
+    // parallelogram: the coupler keeps horizontal
+    crank.set_joint_position(0.4).unwrap();
+    coupler.set_joint_position(-0.4).unwrap();
+    rocker.set_joint_position(0.4).unwrap();
+    chain.update_transforms();
+    assert!(solver.is_satisfied(&chain));
+    let target_pose = coupler_middle.world_transform().unwrap();
+    chain.set_joint_positions(&[0.0, 0.0, 0.0]).unwrap();
+
+    let constraints = Constraints {
+        position_z: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    let target = IKTarget::with_constraints(coupler_middle.clone(), target_pose, constraints);
+    solver.solve(&chain, &[target]).unwrap();
+    assert!(solver.is_satisfied(&chain));
+    let diff = coupler_middle.world_transform().unwrap().translation.vector
+        - target_pose.translation.vector;
+    assert!(diff.norm() < 0.001);
+    assert!((crank.joint_position().unwrap() - 0.4).abs() < 0.01);
+
+    // the transforms are restored with the positions after the failures
+    let solved = coupler_middle.world_transform().unwrap().translation.vector;
+    let unreachable = IKTarget::with_constraints(
+        coupler_middle.clone(),
+        Isometry3::translation(5.0, 0.0, 0.0),
+        constraints,
+    );
+    assert!(solver.solve(&chain, &[unreachable]).is_err());
+    let restored = coupler_middle.world_transform().unwrap().translation.vector;
+    assert!((restored - solved).norm() < 1e-9);
+    solver.num_max_try = 2;
+    crank.set_joint_position(1.0).unwrap();
+    chain.update_transforms();
+    let moved = coupler_middle.world_transform().unwrap().translation.vector;
+    assert!(solver.project(&chain).is_err());
+    let restored = coupler_middle.world_transform().unwrap().translation.vector;
+    assert!((restored - moved).norm() < 1e-9);
+}
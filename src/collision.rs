@@ -0,0 +1,1330 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, Isometry3, Point3, RealField, Vector3};
+
+use chain::*;
+use funcs::*;
+use link::*;
+use node::*;
+use planner::*;
+
+/// Maximum number of the iterations of GJK
+const GJK_MAX_ITERATIONS: usize = 64;
+
+/// Convex shape of a collision geometry in the world frame
+///
+/// The shape is inflated by `margin` in all directions.
+pub(crate) struct ConvexShape<'a, T: RealField> {
+    geometry: &'a Geometry<T>,
+    pose: Isometry3<T>,
+    margin: T,
+}
+
+impl<'a, T> ConvexShape<'a, T>
+where
+    T: RealField,
+{
+    /// Returns `None` for `Geometry::Mesh`, which is not supported
+    pub(crate) fn new(geometry: &'a Geometry<T>, pose: Isometry3<T>, margin: T) -> Option<Self> {
+        match *geometry {
+            Geometry::Mesh { .. } => None,
+            _ => Some(Self {
+                geometry,
+                pose,
+                margin,
+            }),
+        }
+    }
+
+    /// Farthest point of the shape in `direction` (in the world frame)
+    pub(crate) fn support(&self, direction: &Vector3<T>) -> Vector3<T> {
+        let half: T = na::convert(0.5);
+        let sign = |value: T| {
+            if value < T::zero() {
+                -T::one()
+            } else {
+                T::one()
+            }
+        };
+        let local = self.pose.rotation.inverse() * direction;
+        let norm = local.norm();
+        let unit = if norm > T::default_epsilon() {
+            local / norm
+        } else {
+            Vector3::x()
+        };
+        // radius of the rounded shapes is added to the margin
+        let (core, radius) = match *self.geometry {
+            Geometry::Box {
+                depth,
+                width,
+                height,
+            } => (
+                Vector3::new(
+                    sign(local.x) * depth * half,
+                    sign(local.y) * width * half,
+                    sign(local.z) * height * half,
+                ),
+                T::zero(),
+            ),
+            Geometry::Cylinder { radius, length } => {
+                let radial = Vector3::new(local.x, local.y, T::zero());
+                let radial_norm = radial.norm();
+                let radial = if radial_norm > T::default_epsilon() {
+                    radial * (radius / radial_norm)
+                } else {
+                    Vector3::zeros()
+                };
+                (
+                    radial + Vector3::new(T::zero(), T::zero(), sign(local.z) * length * half),
+                    T::zero(),
+                )
+            }
+            Geometry::Capsule { radius, length } => (
+                Vector3::new(T::zero(), T::zero(), sign(local.z) * length * half),
+                radius,
+            ),
+            Geometry::Sphere { radius } => (Vector3::zeros(), radius),
+            Geometry::Mesh { .. } => unreachable!("mesh is not a convex shape"),
+        };
+        (self.pose * Point3::from(core + unit * (radius + self.margin))).coords
+    }
+
+    /// Radius of the rounded shape including the margin
+    fn radius(&self) -> T {
+        match *self.geometry {
+            Geometry::Capsule { radius, .. } | Geometry::Sphere { radius } => radius + self.margin,
+            _ => self.margin,
+        }
+    }
+
+    /// The shape without `radius()`: a point of a sphere or a segment of a capsule
+    fn core(&self) -> Self {
+        Self {
+            geometry: self.geometry,
+            pose: self.pose,
+            margin: self.margin - self.radius(),
+        }
+    }
+}
+
+/// Point of the Minkowski difference A - B with the points of A and B
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SupportPoint<T: RealField> {
+    pub(crate) point: Vector3<T>,
+    pub(crate) a: Vector3<T>,
+    pub(crate) b: Vector3<T>,
+}
+
+impl<T> SupportPoint<T>
+where
+    T: RealField,
+{
+    pub(crate) fn new(a: &ConvexShape<T>, b: &ConvexShape<T>, direction: &Vector3<T>) -> Self {
+        let a = a.support(direction);
+        let b = b.support(&-direction);
+        Self { point: a - b, a, b }
+    }
+}
+
+/// Closest point of the simplex to the origin
+///
+/// The simplex is reduced to the smallest sub-simplex which contains the closest point,
+/// and the barycentric coordinates of the point are returned. If the origin is inside
+/// the tetrahedron, the simplex is not reduced.
+pub(crate) fn closest_point_of_simplex<T>(simplex: &mut Vec<SupportPoint<T>>) -> Vec<T>
+where
+    T: RealField,
+{
+    match simplex.len() {
+        1 => vec![T::one()],
+        2 => closest_point_of_segment(simplex),
+        3 => closest_point_of_triangle(simplex),
+        _ => closest_point_of_tetrahedron(simplex),
+    }
+}
+
+fn closest_point_of_segment<T>(simplex: &mut Vec<SupportPoint<T>>) -> Vec<T>
+where
+    T: RealField,
+{
+    let a = simplex[0].point;
+    let ab = simplex[1].point - a;
+    let length_squared = ab.norm_squared();
+    let t = if length_squared > T::default_epsilon() {
+        -a.dot(&ab) / length_squared
+    } else {
+        T::zero()
+    };
+    if t <= T::zero() {
+        simplex.truncate(1);
+        vec![T::one()]
+    } else if t >= T::one() {
+        simplex.remove(0);
+        vec![T::one()]
+    } else {
+        vec![T::one() - t, t]
+    }
+}
+
+fn closest_point_of_triangle<T>(simplex: &mut Vec<SupportPoint<T>>) -> Vec<T>
+where
+    T: RealField,
+{
+    let (a, b, c) = (simplex[0], simplex[1], simplex[2]);
+    let ab = b.point - a.point;
+    let ac = c.point - a.point;
+    let ap = -a.point;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= T::zero() && d2 <= T::zero() {
+        *simplex = vec![a];
+        return vec![T::one()];
+    }
+    let bp = -b.point;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= T::zero() && d4 <= d3 {
+        *simplex = vec![b];
+        return vec![T::one()];
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= T::zero() && d1 >= T::zero() && d3 <= T::zero() {
+        let v = d1 / (d1 - d3);
+        *simplex = vec![a, b];
+        return vec![T::one() - v, v];
+    }
+    let cp = -c.point;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= T::zero() && d5 <= d6 {
+        *simplex = vec![c];
+        return vec![T::one()];
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= T::zero() && d2 >= T::zero() && d6 <= T::zero() {
+        let w = d2 / (d2 - d6);
+        *simplex = vec![a, c];
+        return vec![T::one() - w, w];
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= T::zero() && (d4 - d3) >= T::zero() && (d5 - d6) >= T::zero() {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        *simplex = vec![b, c];
+        return vec![T::one() - w, w];
+    }
+    let sum = va + vb + vc;
+    if sum.abs() <= T::default_epsilon() {
+        // degenerated triangle
+        simplex.truncate(2);
+        return closest_point_of_segment(simplex);
+    }
+    vec![va / sum, vb / sum, vc / sum]
+}
+
+fn closest_point_of_tetrahedron<T>(simplex: &mut Vec<SupportPoint<T>>) -> Vec<T>
+where
+    T: RealField,
+{
+    const FACES: [[usize; 4]; 4] = [[0, 1, 2, 3], [0, 1, 3, 2], [0, 2, 3, 1], [1, 2, 3, 0]];
+    let mut best: Option<(T, Vec<SupportPoint<T>>, Vec<T>)> = None;
+    for face in &FACES {
+        let p0 = simplex[face[0]].point;
+        let normal = (simplex[face[1]].point - p0).cross(&(simplex[face[2]].point - p0));
+        let origin_side = -p0.dot(&normal);
+        let opposite_side = (simplex[face[3]].point - p0).dot(&normal);
+        // the origin is on the other side of the face from the opposite vertex,
+        // or the tetrahedron is flat
+        if origin_side * opposite_side <= T::zero()
+            || opposite_side.abs() <= T::default_epsilon() * normal.norm()
+        {
+            let mut sub = vec![simplex[face[0]], simplex[face[1]], simplex[face[2]]];
+            let weights = closest_point_of_triangle(&mut sub);
+            let closest = sub
+                .iter()
+                .zip(weights.iter())
+                .fold(Vector3::zeros(), |sum, (s, w)| sum + s.point * *w);
+            let distance = closest.norm_squared();
+            match best {
+                Some((min_distance, ..)) if min_distance <= distance => {}
+                _ => best = Some((distance, sub, weights)),
+            }
+        }
+    }
+    match best {
+        Some((_, sub, weights)) => {
+            *simplex = sub;
+            weights
+        }
+        None => {
+            // the origin is inside
+            let quarter: T = na::convert(0.25);
+            vec![quarter; 4]
+        }
+    }
+}
+
+/// Check the intersection of two convex shapes by GJK
+pub(crate) fn gjk_intersect<T>(a: &ConvexShape<T>, b: &ConvexShape<T>) -> bool
+where
+    T: RealField,
+{
+    let initial = b.pose.translation.vector - a.pose.translation.vector;
+    let initial = if initial.norm_squared() > T::default_epsilon() {
+        initial
+    } else {
+        Vector3::x()
+    };
+    let mut simplex = vec![SupportPoint::new(a, b, &initial)];
+    let mut closest = simplex[0].point;
+    for _ in 0..GJK_MAX_ITERATIONS {
+        if closest.norm_squared() <= T::default_epsilon() {
+            return true;
+        }
+        let new_point = SupportPoint::new(a, b, &-closest);
+        // the origin is separated by the plane perpendicular to `closest`
+        if new_point.point.dot(&closest) > T::zero() {
+            return false;
+        }
+        // no progress: the origin is on the boundary
+        if closest.norm_squared() - closest.dot(&new_point.point)
+            <= closest.norm_squared() * na::convert(1e-10)
+        {
+            return true;
+        }
+        simplex.push(new_point);
+        let weights = closest_point_of_simplex(&mut simplex);
+        if simplex.len() == 4 {
+            return true;
+        }
+        closest = simplex
+            .iter()
+            .zip(weights.iter())
+            .fold(Vector3::zeros(), |sum, (s, w)| sum + s.point * *w);
+    }
+    true
+}
+
+/// Result of GJK for the closest points
+pub(crate) enum GjkResult<T: RealField> {
+    /// The shapes are separated: the reduced simplex and the barycentric coordinates
+    /// of the closest point of the Minkowski difference
+    Separated(Vec<SupportPoint<T>>, Vec<T>),
+    /// The shapes intersect: the simplex which contains the origin
+    Intersecting(Vec<SupportPoint<T>>),
+}
+
+fn combine<T, F>(simplex: &[SupportPoint<T>], weights: &[T], get: F) -> Vector3<T>
+where
+    T: RealField,
+    F: Fn(&SupportPoint<T>) -> Vector3<T>,
+{
+    simplex
+        .iter()
+        .zip(weights.iter())
+        .fold(Vector3::zeros(), |sum, (s, w)| sum + get(s) * *w)
+}
+
+/// Find the closest points of two convex shapes by GJK
+pub(crate) fn gjk_closest<T>(a: &ConvexShape<T>, b: &ConvexShape<T>) -> GjkResult<T>
+where
+    T: RealField,
+{
+    let tolerance: T = na::convert(1e-10);
+    let initial = b.pose.translation.vector - a.pose.translation.vector;
+    let initial = if initial.norm_squared() > T::default_epsilon() {
+        initial
+    } else {
+        Vector3::x()
+    };
+    let mut simplex = vec![SupportPoint::new(a, b, &initial)];
+    let mut weights = vec![T::one()];
+    let mut closest = simplex[0].point;
+    for _ in 0..GJK_MAX_ITERATIONS {
+        let distance_squared = closest.norm_squared();
+        // touching is also regarded as intersecting
+        if distance_squared <= T::default_epsilon() {
+            return GjkResult::Intersecting(simplex);
+        }
+        let new_point = SupportPoint::new(a, b, &-closest);
+        // converged: the new point does not move the closest point
+        if distance_squared - closest.dot(&new_point.point) <= distance_squared * tolerance {
+            return GjkResult::Separated(simplex, weights);
+        }
+        // `-closest` is a separating axis, so the shapes never intersect
+        let is_separated = closest.dot(&new_point.point) > T::zero();
+        let previous = (simplex.clone(), weights.clone());
+        simplex.push(new_point);
+        weights = closest_point_of_simplex(&mut simplex);
+        let new_closest = combine(&simplex, &weights, |s| s.point);
+        if simplex.len() == 4 || new_closest.norm_squared() <= T::default_epsilon() {
+            if is_separated {
+                return GjkResult::Separated(previous.0, previous.1);
+            }
+            return GjkResult::Intersecting(simplex);
+        }
+        if new_closest.norm_squared() >= distance_squared {
+            // no progress by the numerical error
+            return GjkResult::Separated(previous.0, previous.1);
+        }
+        closest = new_closest;
+    }
+    GjkResult::Separated(simplex, weights)
+}
+
+/// Expand the simplex which contains the origin to a tetrahedron
+fn expand_to_tetrahedron<T>(
+    a: &ConvexShape<T>,
+    b: &ConvexShape<T>,
+    simplex: &mut Vec<SupportPoint<T>>,
+) -> bool
+where
+    T: RealField,
+{
+    let directions = [
+        Vector3::x(),
+        -Vector3::x(),
+        Vector3::y(),
+        -Vector3::y(),
+        Vector3::z(),
+        -Vector3::z(),
+    ];
+    let epsilon: T = na::convert(1e-10);
+    while simplex.len() < 4 {
+        let mut candidates = directions.to_vec();
+        // the normal of the triangle is the best direction
+        if simplex.len() == 3 {
+            let normal =
+                (simplex[1].point - simplex[0].point).cross(&(simplex[2].point - simplex[0].point));
+            candidates.insert(0, -normal);
+            candidates.insert(0, normal);
+        }
+        let added = candidates.iter().any(|direction| {
+            let new_point = SupportPoint::new(a, b, direction);
+            let p0 = simplex[0].point;
+            let is_independent = match simplex.len() {
+                1 => (new_point.point - p0).norm() > epsilon,
+                2 => {
+                    (simplex[1].point - p0)
+                        .cross(&(new_point.point - p0))
+                        .norm()
+                        > epsilon
+                }
+                _ => {
+                    (simplex[1].point - p0)
+                        .cross(&(simplex[2].point - p0))
+                        .dot(&(new_point.point - p0))
+                        .abs()
+                        > epsilon
+                }
+            };
+            if is_independent {
+                simplex.push(new_point);
+            }
+            is_independent
+        });
+        if !added {
+            return false;
+        }
+    }
+    true
+}
+
+/// Calculate the penetration by EPA from the simplex of GJK which contains the origin
+///
+/// Returns the depth and the barycentric coordinates of the deepest point on the triangle.
+fn epa<T>(
+    a: &ConvexShape<T>,
+    b: &ConvexShape<T>,
+    mut simplex: Vec<SupportPoint<T>>,
+) -> Option<(T, Vec<SupportPoint<T>>, Vec<T>)>
+where
+    T: RealField,
+{
+    const EPA_MAX_ITERATIONS: usize = 128;
+    let tolerance: T = na::convert(1e-6);
+    if !expand_to_tetrahedron(a, b, &mut simplex) {
+        return None;
+    }
+    let mut vertices = simplex;
+    let center = combine(&vertices, &[na::convert(0.25); 4], |s| s.point);
+    // outward normal and distance from the origin of the face
+    let face_plane = |vertices: &[SupportPoint<T>], face: &[usize; 3]| {
+        let p0 = vertices[face[0]].point;
+        let normal = (vertices[face[1]].point - p0).cross(&(vertices[face[2]].point - p0));
+        let norm = normal.norm();
+        if norm <= T::default_epsilon() {
+            return None;
+        }
+        let normal = normal / norm;
+        Some((normal, normal.dot(&p0)))
+    };
+    let mut faces = Vec::new();
+    for face in &[[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]] {
+        let p0 = vertices[face[0]].point;
+        let normal = (vertices[face[1]].point - p0).cross(&(vertices[face[2]].point - p0));
+        if normal.dot(&(p0 - center)) < T::zero() {
+            faces.push([face[0], face[2], face[1]]);
+        } else {
+            faces.push(*face);
+        }
+    }
+    // the deepest point on the nearest faces
+    let deepest = |vertices: &[SupportPoint<T>], faces: &[[usize; 3]], nearest_distance: T| {
+        let mut deepest = None;
+        for face in faces {
+            if let Some((normal, distance)) = face_plane(vertices, face) {
+                if distance > nearest_distance + tolerance {
+                    continue;
+                }
+                // project the origin to the face
+                let mut triangle = face
+                    .iter()
+                    .map(|i| {
+                        let mut vertex = vertices[*i];
+                        vertex.point -= normal * distance;
+                        vertex
+                    })
+                    .collect::<Vec<_>>();
+                let weights = closest_point_of_triangle(&mut triangle);
+                for vertex in &mut triangle {
+                    vertex.point += normal * distance;
+                }
+                // coplanar faces: the projection must be inside of the triangle
+                let is_inside = triangle.len() == 3;
+                deepest = Some((distance, triangle, weights));
+                if is_inside {
+                    break;
+                }
+            }
+        }
+        deepest
+    };
+    let nearest_face = |vertices: &[SupportPoint<T>], faces: &[[usize; 3]]| {
+        let mut nearest: Option<(Vector3<T>, T)> = None;
+        for face in faces {
+            if let Some((normal, distance)) = face_plane(vertices, face) {
+                match nearest {
+                    Some((_, nearest_distance)) if nearest_distance <= distance => {}
+                    _ => nearest = Some((normal, distance)),
+                }
+            }
+        }
+        nearest
+    };
+    let mut last: Option<(T, Vec<SupportPoint<T>>, Vec<T>)> = None;
+    for _ in 0..EPA_MAX_ITERATIONS {
+        let (normal, distance) = nearest_face(&vertices, &faces)?;
+        // the distance never decreases unless the polytope is broken by the numerical error
+        // (for example, the thin faces on the curved surfaces)
+        if let Some((last_distance, ..)) = last {
+            if distance < last_distance - tolerance {
+                return last;
+            }
+        }
+        let new_point = SupportPoint::new(a, b, &normal);
+        last = deepest(&vertices, &faces, distance);
+        if new_point.point.dot(&normal) - distance <= tolerance {
+            return last;
+        }
+        vertices.push(new_point);
+        let new_index = vertices.len() - 1;
+        // remove the faces which can be seen from the new point, and keep the horizon
+        let mut horizon: Vec<(usize, usize)> = Vec::new();
+        faces.retain(|face| {
+            let is_visible = face_plane(&vertices, face)
+                .map(|(normal, distance)| new_point.point.dot(&normal) - distance > tolerance)
+                .unwrap_or(true);
+            if is_visible {
+                for edge in &[(face[0], face[1]), (face[1], face[2]), (face[2], face[0])] {
+                    if let Some(position) = horizon
+                        .iter()
+                        .position(|other| other.0 == edge.1 && other.1 == edge.0)
+                    {
+                        horizon.remove(position);
+                    } else {
+                        horizon.push(*edge);
+                    }
+                }
+            }
+            !is_visible
+        });
+        for (from, to) in horizon {
+            faces.push([from, to, new_index]);
+        }
+    }
+    // not converged: use the current approximation
+    last
+}
+
+/// Check if the two collision geometries intersect
+///
+/// `Geometry::Mesh` never intersects because it is not supported.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+/// use k::link::Geometry;
+///
+/// let sphere = Geometry::Sphere { radius: 0.5 };
+/// let capsule = Geometry::Capsule { radius: 0.2, length: 1.0 };
+/// let pose = Isometry3::translation(0.6, 0.0, 0.0);
+/// assert!(geometries_intersect(&sphere, &Isometry3::identity(), &capsule, &pose));
+/// let pose = Isometry3::translation(0.8, 0.0, 0.0);
+/// assert!(!geometries_intersect(&sphere, &Isometry3::identity(), &capsule, &pose));
+/// ```
+pub fn geometries_intersect<T>(
+    geometry_a: &Geometry<T>,
+    pose_a: &Isometry3<T>,
+    geometry_b: &Geometry<T>,
+    pose_b: &Isometry3<T>,
+) -> bool
+where
+    T: RealField,
+{
+    match (
+        ConvexShape::new(geometry_a, *pose_a, T::zero()),
+        ConvexShape::new(geometry_b, *pose_b, T::zero()),
+    ) {
+        (Some(a), Some(b)) => gjk_intersect(&a, &b),
+        _ => false,
+    }
+}
+
+/// Result of the distance query between two shapes
+#[derive(Debug, Clone)]
+pub struct DistanceResult<T: RealField> {
+    /// Distance between the shapes, or negative penetration depth if they intersect
+    pub distance: T,
+    /// The closest (or the deepest) point on the shape A in the world frame
+    pub point_a: Vector3<T>,
+    /// The closest (or the deepest) point on the shape B in the world frame
+    pub point_b: Vector3<T>,
+    /// Unit vector from A to B: the distance increases if B moves along it
+    pub normal: Vector3<T>,
+}
+
+fn shape_distance<T>(a: &ConvexShape<T>, b: &ConvexShape<T>) -> Option<DistanceResult<T>>
+where
+    T: RealField,
+{
+    let separated = |simplex: Vec<SupportPoint<T>>, weights: Vec<T>| {
+        let point_a = combine(&simplex, &weights, |s| s.a);
+        let point_b = combine(&simplex, &weights, |s| s.b);
+        let distance = (point_b - point_a).norm();
+        DistanceResult {
+            distance,
+            point_a,
+            point_b,
+            normal: (point_b - point_a) / distance,
+        }
+    };
+    // the distance between the cores of the rounded shapes is exact
+    // while EPA approximates the curved surfaces
+    let (radius_a, radius_b) = (a.radius(), b.radius());
+    if radius_a > T::zero() || radius_b > T::zero() {
+        if let GjkResult::Separated(simplex, weights) = gjk_closest(&a.core(), &b.core()) {
+            let core = separated(simplex, weights);
+            if core.distance > T::default_epsilon() {
+                return Some(DistanceResult {
+                    distance: core.distance - radius_a - radius_b,
+                    point_a: core.point_a + core.normal * radius_a,
+                    point_b: core.point_b - core.normal * radius_b,
+                    normal: core.normal,
+                });
+            }
+        }
+    }
+    match gjk_closest(a, b) {
+        GjkResult::Separated(simplex, weights) => Some(separated(simplex, weights)),
+        GjkResult::Intersecting(simplex) => {
+            let (depth, triangle, weights) = epa(a, b, simplex)?;
+            let point_a = combine(&triangle, &weights, |s| s.a);
+            let point_b = combine(&triangle, &weights, |s| s.b);
+            // the outward normal of the Minkowski difference at the deepest point,
+            // which is point_a - point_b
+            let normal = combine(&triangle, &weights, |s| s.point);
+            let normal = if normal.norm() > T::default_epsilon() {
+                normal.normalize()
+            } else {
+                let p0 = triangle[0].point;
+                let face_normal = (triangle[1].point - p0).cross(&(triangle[2].point - p0));
+                if face_normal.dot(&p0) < T::zero() {
+                    -face_normal.normalize()
+                } else {
+                    face_normal.normalize()
+                }
+            };
+            Some(DistanceResult {
+                distance: -depth,
+                point_a,
+                point_b,
+                normal,
+            })
+        }
+    }
+}
+
+/// Calculate the signed distance and the closest points of two collision geometries
+///
+/// Returns `None` for `Geometry::Mesh` or if it fails.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+/// use k::link::Geometry;
+///
+/// let sphere = Geometry::Sphere { radius: 0.5 };
+/// let pose = Isometry3::<f64>::translation(2.0, 0.0, 0.0);
+/// let result = geometry_distance(&sphere, &Isometry3::identity(), &sphere, &pose).unwrap();
+/// assert!((result.distance - 1.0).abs() < 1e-6);
+/// assert!((result.point_a - Vector3::new(0.5, 0.0, 0.0)).norm() < 1e-6);
+/// assert!((result.normal - Vector3::x()).norm() < 1e-6);
+/// // penetration
+/// let pose = Isometry3::translation(0.8, 0.0, 0.0);
+/// let result = geometry_distance(&sphere, &Isometry3::identity(), &sphere, &pose).unwrap();
+/// assert!((result.distance + 0.2).abs() < 1e-9);
+/// ```
+pub fn geometry_distance<T>(
+    geometry_a: &Geometry<T>,
+    pose_a: &Isometry3<T>,
+    geometry_b: &Geometry<T>,
+    pose_b: &Isometry3<T>,
+) -> Option<DistanceResult<T>>
+where
+    T: RealField,
+{
+    shape_distance(
+        &ConvexShape::new(geometry_a, *pose_a, T::zero())?,
+        &ConvexShape::new(geometry_b, *pose_b, T::zero())?,
+    )
+}
+
+/// Collision geometries of a link in the world frame
+pub(crate) struct LinkGeometries<T: RealField> {
+    pub(crate) name: String,
+    node: Node<T>,
+    pub(crate) collisions: Vec<(Geometry<T>, Isometry3<T>)>,
+}
+
+/// Collect the collision geometries of all the links and update their transforms
+pub(crate) fn link_geometries<T>(chain: &Chain<T>) -> Vec<LinkGeometries<T>>
+where
+    T: RealField,
+{
+    chain.update_link_transforms();
+    chain
+        .iter()
+        .filter_map(|node| {
+            let link = node.link();
+            link.as_ref().map(|link| LinkGeometries {
+                name: link.name.clone(),
+                node: node.clone(),
+                collisions: link
+                    .collisions
+                    .iter()
+                    .map(|collision| {
+                        (
+                            collision.geometry.clone(),
+                            collision.world_transform().expect("cache must exist"),
+                        )
+                    })
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// Collision geometry which is not a part of the chain
+#[derive(Debug, Clone)]
+pub struct Obstacle<T: RealField> {
+    /// Name of the obstacle
+    pub name: String,
+    /// Shape of the obstacle
+    pub geometry: Geometry<T>,
+    /// Pose of the obstacle in the world frame
+    pub pose: Isometry3<T>,
+}
+
+impl<T> Obstacle<T>
+where
+    T: RealField,
+{
+    /// Create instance of `Obstacle`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    /// use k::link::Geometry;
+    ///
+    /// let obstacle = Obstacle::new(
+    ///     "ball",
+    ///     Geometry::Sphere { radius: 0.1 },
+    ///     Isometry3::<f64>::translation(1.0, 0.0, 0.0),
+    /// );
+    /// ```
+    pub fn new(name: &str, geometry: Geometry<T>, pose: Isometry3<T>) -> Self {
+        Self {
+            name: name.to_owned(),
+            geometry,
+            pose,
+        }
+    }
+}
+
+/// Jacobian of the world position of `point` which moves with `frame` (3 x dof)
+fn point_jacobian<T>(chain: &Chain<T>, frame: &Option<Node<T>>, point: &Vector3<T>) -> DMatrix<T>
+where
+    T: RealField,
+{
+    match *frame {
+        Some(ref node) if chain.iter().any(|n| n == node) => {
+            let jacobi = jacobian_for_node(chain, node);
+            // v_p = v_o + w x (p - o)
+            let r = point
+                - node
+                    .world_transform()
+                    .expect("cache must exist")
+                    .translation
+                    .vector;
+            let mut linear = jacobi.rows(0, 3).into_owned();
+            linear -= r.cross_matrix() * jacobi.rows(3, 3);
+            linear
+        }
+        _ => DMatrix::zeros(3, chain.dof()),
+    }
+}
+
+/// Minimum distance between a link and another link or an obstacle
+#[derive(Debug, Clone)]
+pub struct LinkDistance<T: RealField> {
+    /// Name of the link, which is the shape A of `result`
+    pub link_name: String,
+    /// Name of the other link or the obstacle, which is the shape B of `result`
+    pub other_name: String,
+    /// The signed distance and the closest points
+    pub result: DistanceResult<T>,
+    /// The nodes which move the shapes, `None` if it is fixed
+    frame_a: Option<Node<T>>,
+    frame_b: Option<Node<T>>,
+}
+
+impl<T> LinkDistance<T>
+where
+    T: RealField,
+{
+    /// Jacobian of the distance with respect to the joint positions of `chain` (1 x dof)
+    ///
+    /// The closest points are assumed to be fixed on the shapes. It can be used as
+    /// a row of the constraints of IK to keep the distance.
+    pub fn jacobian(&self, chain: &Chain<T>) -> DMatrix<T> {
+        let relative = point_jacobian(chain, &self.frame_b, &self.result.point_b)
+            - point_jacobian(chain, &self.frame_a, &self.result.point_a);
+        DMatrix::from_row_slice(1, 3, self.result.normal.as_slice()) * relative
+    }
+}
+
+/// Minimum distance of the pairs of the geometries
+fn min_distance<T>(
+    collisions_a: &[(Geometry<T>, Isometry3<T>)],
+    collisions_b: &[(Geometry<T>, Isometry3<T>)],
+    margin: T,
+) -> Option<DistanceResult<T>>
+where
+    T: RealField,
+{
+    let mut min: Option<DistanceResult<T>> = None;
+    for (geometry_a, pose_a) in collisions_a {
+        for (geometry_b, pose_b) in collisions_b {
+            let result = match (
+                ConvexShape::new(geometry_a, *pose_a, margin),
+                ConvexShape::new(geometry_b, *pose_b, margin),
+            ) {
+                (Some(a), Some(b)) => shape_distance(&a, &b),
+                _ => None,
+            };
+            if let Some(result) = result {
+                match min {
+                    Some(ref current) if current.distance <= result.distance => {}
+                    _ => min = Some(result),
+                }
+            }
+        }
+    }
+    min
+}
+
+/// Calculate the minimum distances between all the links of `chain` and `obstacles`
+///
+/// A result is returned for each pair of a link and an obstacle which have supported
+/// geometries. The transforms of the links of `chain` are updated.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+/// use k::link::*;
+///
+/// let j0 = JointBuilder::new()
+///     .name("j0")
+///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+///     .into_node();
+/// let end = JointBuilder::new()
+///     .name("end")
+///     .translation(Translation3::new(1.0, 0.0, 0.0))
+///     .into_node();
+/// end.set_parent(&j0);
+/// // the link of a node is in the frame of its parent
+/// end.set_link(Some(LinkBuilder::new()
+///     .name("arm")
+///     .add_collision(Collision::new(
+///         "ball".to_owned(),
+///         Isometry3::translation(1.0, 0.0, 0.0),
+///         Geometry::Sphere { radius: 0.1 },
+///     ))
+///     .finalize()));
+/// let chain = Chain::<f64>::from_root(j0);
+/// let obstacles = [Obstacle::new(
+///     "wall",
+///     Geometry::Box { depth: 0.1, width: 2.0, height: 2.0 },
+///     Isometry3::translation(1.5, 0.0, 0.0),
+/// )];
+/// let distances = obstacle_distances(&chain, &obstacles);
+/// assert_eq!(distances[0].other_name, "wall");
+/// assert!((distances[0].result.distance - 0.35).abs() < 1e-6);
+/// // rotating j0 does not change the distance at this point
+/// assert!(distances[0].jacobian(&chain)[(0, 0)].abs() < 1e-6);
+/// ```
+pub fn obstacle_distances<T>(chain: &Chain<T>, obstacles: &[Obstacle<T>]) -> Vec<LinkDistance<T>>
+where
+    T: RealField,
+{
+    let links = link_geometries(chain);
+    let mut distances = Vec::new();
+    for link in &links {
+        for obstacle in obstacles {
+            let obstacle_collisions = [(obstacle.geometry.clone(), obstacle.pose)];
+            if let Some(result) = min_distance(&link.collisions, &obstacle_collisions, T::zero()) {
+                distances.push(LinkDistance {
+                    link_name: link.name.clone(),
+                    other_name: obstacle.name.clone(),
+                    result,
+                    frame_a: link.node.parent(),
+                    frame_b: None,
+                });
+            }
+        }
+    }
+    distances
+}
+
+/// Self-collision checker of the links of a chain
+///
+/// All the pairs of the collision geometries of different links are checked by GJK,
+/// except the pairs of the adjacent links (the parent link and the child link) and
+/// `ignored_pairs`. `Geometry::Mesh` is not supported and ignored.
+pub struct SelfCollisionChecker<T: RealField> {
+    /// Pairs of the names of the links which are not checked
+    pub ignored_pairs: Vec<(String, String)>,
+    /// If true, the adjacent links are not checked
+    pub ignore_adjacent_links: bool,
+    /// All the geometries are inflated by this margin
+    pub margin: T,
+}
+
+impl<T> SelfCollisionChecker<T>
+where
+    T: RealField,
+{
+    /// Create instance of `SelfCollisionChecker` which ignores the adjacent links
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let checker = k::SelfCollisionChecker::<f64>::new();
+    /// ```
+    pub fn new() -> Self {
+        Self {
+            ignored_pairs: Vec::new(),
+            ignore_adjacent_links: true,
+            margin: T::zero(),
+        }
+    }
+
+    /// Returns true if the pair of the links is ignored
+    pub fn is_ignored(&self, name_a: &str, name_b: &str) -> bool {
+        self.ignored_pairs
+            .iter()
+            .any(|(a, b)| (a == name_a && b == name_b) || (a == name_b && b == name_a))
+    }
+
+    /// Indices of the pairs of the links which should be checked
+    pub(crate) fn relevant_pairs(&self, links: &[LinkGeometries<T>]) -> Vec<(usize, usize)> {
+        // index of the nearest ancestor link
+        let parents = links
+            .iter()
+            .map(|link| {
+                let mut current = link.node.parent();
+                while let Some(node) = current {
+                    if let Some(index) = links.iter().position(|l| l.node == node) {
+                        return Some(index);
+                    }
+                    current = node.parent();
+                }
+                None
+            })
+            .collect::<Vec<_>>();
+        let mut pairs = Vec::new();
+        for i in 0..links.len() {
+            for j in (i + 1)..links.len() {
+                if self.ignore_adjacent_links && (parents[i] == Some(j) || parents[j] == Some(i)) {
+                    continue;
+                }
+                if self.is_ignored(&links[i].name, &links[j].name) {
+                    continue;
+                }
+                pairs.push((i, j));
+            }
+        }
+        pairs
+    }
+
+    /// Returns the pairs of the names of the colliding links at the current positions
+    ///
+    /// The transforms of the links of `chain` are updated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    /// use k::link::*;
+    ///
+    /// let capsule = |name: &str| {
+    ///     LinkBuilder::new()
+    ///         .name(name)
+    ///         .add_collision(Collision::new(
+    ///             name.to_owned(),
+    ///             // along the x axis
+    ///             Isometry3::new(Vector3::new(0.5, 0.0, 0.0), Vector3::new(0.0, 1.57, 0.0)),
+    ///             Geometry::Capsule { radius: 0.1, length: 1.0 },
+    ///         ))
+    ///         .finalize()
+    /// };
+    /// let mut nodes: Vec<Node<f64>> = Vec::new();
+    /// for i in 0..3 {
+    ///     let node = JointBuilder::new()
+    ///         .name(&format!("j{}", i))
+    ///         .translation(Translation3::new(if i == 0 { 0.0 } else { 1.0 }, 0.0, 0.0))
+    ///         .joint_type(JointType::Rotational { axis: Vector3::y_axis() })
+    ///         .into_node();
+    ///     if let Some(parent) = nodes.last() {
+    ///         node.set_parent(parent);
+    ///     }
+    ///     nodes.push(node);
+    /// }
+    /// let end = JointBuilder::new()
+    ///     .translation(Translation3::new(1.0, 0.0, 0.0))
+    ///     .into_node();
+    /// end.set_parent(&nodes[2]);
+    /// // the link of a node is in the frame of its parent
+    /// for (i, node) in nodes.iter().skip(1).chain(Some(&end)).enumerate() {
+    ///     node.set_link(Some(capsule(&format!("link{}", i))));
+    /// }
+    /// let chain = Chain::from_root(nodes[0].clone());
+    /// let checker = SelfCollisionChecker::new();
+    /// assert!(checker.colliding_link_pairs(&chain).is_empty());
+    /// // fold the arm
+    /// chain.set_joint_positions(&[0.0, 2.5, 2.5]).unwrap();
+    /// assert_eq!(
+    ///     checker.colliding_link_pairs(&chain),
+    ///     vec![("link0".to_owned(), "link2".to_owned())]
+    /// );
+    /// ```
+    pub fn colliding_link_pairs(&self, chain: &Chain<T>) -> Vec<(String, String)> {
+        let links = link_geometries(chain);
+        self.relevant_pairs(&links)
+            .into_iter()
+            .filter(|&(i, j)| {
+                links[i].collisions.iter().any(|(geometry_a, pose_a)| {
+                    links[j].collisions.iter().any(|(geometry_b, pose_b)| {
+                        match (
+                            ConvexShape::new(geometry_a, *pose_a, self.margin),
+                            ConvexShape::new(geometry_b, *pose_b, self.margin),
+                        ) {
+                            (Some(a), Some(b)) => gjk_intersect(&a, &b),
+                            _ => false,
+                        }
+                    })
+                })
+            })
+            .map(|(i, j)| (links[i].name.clone(), links[j].name.clone()))
+            .collect()
+    }
+
+    /// Calculate the minimum distances of all the relevant pairs of the links
+    ///
+    /// The geometries are inflated by `margin`. The transforms of the links of `chain`
+    /// are updated.
+    pub fn link_distances(&self, chain: &Chain<T>) -> Vec<LinkDistance<T>> {
+        let links = link_geometries(chain);
+        self.relevant_pairs(&links)
+            .into_iter()
+            .filter_map(|(i, j)| {
+                min_distance(&links[i].collisions, &links[j].collisions, self.margin).map(
+                    |result| LinkDistance {
+                        link_name: links[i].name.clone(),
+                        other_name: links[j].name.clone(),
+                        result,
+                        frame_a: links[i].node.parent(),
+                        frame_b: links[j].node.parent(),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+impl<T> Default for SelfCollisionChecker<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ValidityChecker<T> for SelfCollisionChecker<T>
+where
+    T: RealField,
+{
+    fn is_valid(&self, chain: &Chain<T>) -> bool {
+        self.colliding_link_pairs(chain).is_empty()
+    }
+}
+
+#[test]
+fn test_gjk_primitive_pairs() {
+    use na::UnitQuaternion;
+
+    let rotation = UnitQuaternion::from_euler_angles(0.3, -0.2, 0.7);
+    let box_geometry = Geometry::Box {
+        depth: 1.0,
+        width: 0.5,
+        height: 0.2,
+    };
+    let cylinder = Geometry::Cylinder {
+        radius: 0.3,
+        length: 1.0,
+    };
+    let sphere = Geometry::Sphere { radius: 0.25 };
+    let identity = Isometry3::identity();
+    // sphere around the corner of the box
+    let corner = Vector3::new(0.5, 0.25, 0.1);
+    let direction = corner.normalize();
+    for (distance, expected) in &[(0.2, true), (0.3, false)] {
+        let pose = Isometry3::from_parts(
+            (rotation * (corner + direction * *distance)).into(),
+            UnitQuaternion::identity(),
+        );
+        let box_pose = Isometry3::from_parts(na::Translation3::identity(), rotation);
+        assert_eq!(
+            geometries_intersect(&box_geometry, &box_pose, &sphere, &pose),
+            *expected
+        );
+    }
+    // cylinders side by side and on top of each other
+    for (pose, expected) in &[
+        (Isometry3::translation(0.59, 0.0, 0.0), true),
+        (Isometry3::translation(0.61, 0.0, 0.0), false),
+        (Isometry3::translation(0.0, 0.2, 0.99), true),
+        (Isometry3::translation(0.0, 0.2, 1.01), false),
+    ] {
+        assert_eq!(
+            geometries_intersect(&cylinder, &identity, &cylinder, pose),
+            *expected
+        );
+    }
+    let mesh = Geometry::Mesh {
+        filename: "a.stl".to_owned(),
+        scale: Vector3::new(1.0, 1.0, 1.0),
+    };
+    assert!(!geometries_intersect(&mesh, &identity, &sphere, &identity));
+}
+
+#[test]
+fn test_distance_queries_and_jacobian() {
+    use joint::*;
+    use na::Translation3;
+
+    let cube = Geometry::Box {
+        depth: 1.0,
+        width: 1.0,
+        height: 1.0,
+    };
+    let identity = Isometry3::<f64>::identity();
+    // penetration of the boxes
+    let result = geometry_distance(
+        &cube,
+        &identity,
+        &cube,
+        &Isometry3::translation(0.9, 0.2, 0.1),
+    )
+    .unwrap();
+    assert!((result.distance + 0.1).abs() < 1e-6);
+    assert!((result.normal - Vector3::x()).norm() < 1e-6);
+    assert!((result.point_a.x - 0.5).abs() < 1e-6);
+    assert!((result.point_b.x - 0.4).abs() < 1e-6);
+    // separated capsule
+    let capsule = Geometry::Capsule {
+        radius: 0.2,
+        length: 1.0,
+    };
+    let result = geometry_distance(
+        &cube,
+        &identity,
+        &capsule,
+        &Isometry3::translation(2.0, 0.0, 0.3),
+    )
+    .unwrap();
+    assert!((result.distance - 1.3).abs() < 1e-6);
+    assert!((result.normal - Vector3::x()).norm() < 1e-6);
+
+    // the Jacobian of the distance matches the numerical differentiation
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(0.0, 0.0, 0.5))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::y_axis(),
+        })
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    end.set_parent(&j1);
+    end.set_link(Some(
+        LinkBuilder::new()
+            .name("arm")
+            .add_collision(Collision::new(
+                "arm".to_owned(),
+                Isometry3::new(Vector3::new(0.5, 0.0, 0.0), Vector3::new(0.0, 1.57, 0.0)),
+                capsule.clone(),
+            ))
+            .finalize(),
+    ));
+    let chain = Chain::<f64>::from_root(j0);
+    let obstacles = [Obstacle::new(
+        "ball",
+        Geometry::Sphere { radius: 0.3 },
+        Isometry3::translation(0.6, 0.4, 1.0),
+    )];
+    let positions = vec![0.2, -0.3];
+    chain.set_joint_positions(&positions).unwrap();
+    let distances = obstacle_distances(&chain, &obstacles);
+    assert_eq!(distances.len(), 1);
+    let jacobi = distances[0].jacobian(&chain);
+    let step = 1e-6;
+    for i in 0..2 {
+        let mut moved = positions.clone();
+        moved[i] += step;
+        chain.set_joint_positions(&moved).unwrap();
+        let moved_distance = obstacle_distances(&chain, &obstacles)[0].result.distance;
+        let numerical = (moved_distance - distances[0].result.distance) / step;
+        assert!((numerical - jacobi[(0, i)]).abs() < 1e-4);
+    }
+    // no distances for the link without the other links
+    assert!(SelfCollisionChecker::new()
+        .link_distances(&chain)
+        .is_empty());
+
+    // the ball moves with j0, and the arm moves with j0, j1 and j2
+    let joints = [
+        (Vector3::z_axis(), Translation3::new(0.0, 0.0, 0.0)),
+        (Vector3::y_axis(), Translation3::new(0.0, 0.0, 0.5)),
+        (Vector3::z_axis(), Translation3::new(1.0, 0.0, 0.0)),
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, (axis, translation))| {
+        JointBuilder::new()
+            .name(&format!("j{}", i))
+            .translation(*translation)
+            .joint_type(JointType::Rotational { axis: *axis })
+            .into_node()
+    })
+    .collect::<Vec<_>>();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    joints[1].set_parent(&joints[0]);
+    joints[2].set_parent(&joints[1]);
+    end.set_parent(&joints[2]);
+    // the link of a node is in the frame of its parent
+    joints[1].set_link(Some(
+        LinkBuilder::new()
+            .name("ball")
+            .add_collision(Collision::new(
+                "ball".to_owned(),
+                Isometry3::translation(1.2, 0.8, 0.6),
+                Geometry::Sphere { radius: 0.2 },
+            ))
+            .finalize(),
+    ));
+    end.set_link(Some(
+        LinkBuilder::new()
+            .name("arm")
+            .add_collision(Collision::new(
+                "arm".to_owned(),
+                Isometry3::new(Vector3::new(0.5, 0.0, 0.0), Vector3::new(0.0, 1.57, 0.0)),
+                capsule,
+            ))
+            .finalize(),
+    ));
+    let chain = Chain::<f64>::from_root(joints[0].clone());
+    let mut checker = SelfCollisionChecker::new();
+    // the ball and the arm are adjacent links
+    checker.ignore_adjacent_links = false;
+    let positions = vec![0.2, -0.3, 0.4];
+    chain.set_joint_positions(&positions).unwrap();
+    let distances = checker.link_distances(&chain);
+    assert_eq!(distances.len(), 1);
+    assert_eq!(distances[0].link_name, "ball");
+    assert_eq!(distances[0].other_name, "arm");
+    assert!(distances[0].result.distance > 0.0);
+    let jacobi = distances[0].jacobian(&chain);
+    for i in 0..3 {
+        let mut moved = positions.clone();
+        moved[i] += step;
+        chain.set_joint_positions(&moved).unwrap();
+        let moved_distance = checker.link_distances(&chain)[0].result.distance;
+        let numerical = (moved_distance - distances[0].result.distance) / step;
+        assert!((numerical - jacobi[(0, i)]).abs() < 1e-4);
+    }
+    // j1 and j2 move the arm only
+    assert!(jacobi[(0, 1)].abs() > 1e-3);
+    assert!(jacobi[(0, 2)].abs() > 1e-3);
+}
@@ -0,0 +1,253 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, DVector, RealField};
+
+use chain::*;
+use errors::*;
+use funcs::*;
+use ik::*;
+use joint::Velocity;
+
+/// Resolved-rate (differential) inverse kinematics
+///
+/// It calculates the joint velocities which realize the twist of the end of the arm
+/// by the damped least squares: dq = J^T (J J^T + damping^2 I)^-1 v
+pub struct DifferentialIKSolver<T: RealField> {
+    /// Damping factor to avoid large velocities around the singular configurations
+    pub damping: T,
+    /// Maximum absolute velocities of the joints, which must be positive.
+    ///
+    /// If any velocity exceeds its limit, all the velocities are scaled down
+    /// to keep the direction of the motion.
+    pub velocity_limits: Option<Vec<T>>,
+    /// Nullspace function for a redundant system
+    nullspace_function: Option<Box<dyn Fn(&[T]) -> Vec<T>>>,
+}
+
+impl<T> DifferentialIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `DifferentialIKSolver`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::DifferentialIKSolver::new(0.01);
+    /// ```
+    pub fn new(damping: T) -> Self {
+        Self {
+            damping,
+            velocity_limits: None,
+            nullspace_function: None,
+        }
+    }
+
+    /// Set a null space function for redundant manipulator.
+    ///
+    /// The returned values are used as the joint velocities which are projected to the null space.
+    pub fn set_nullspace_function(&mut self, func: Box<dyn Fn(&[T]) -> Vec<T>>) {
+        self.nullspace_function = Some(func);
+    }
+
+    /// Clear the null function which is set by `set_nullspace_funtion`.
+    pub fn clear_nullspace_function(&mut self) {
+        self.nullspace_function = None;
+    }
+
+    /// Calculate the joint velocities to realize `twist` of the end of `arm`
+    ///
+    /// `twist` is expressed in `constraints.frame`, and only the constrained coordinates are used.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    ///
+    /// let solver = k::DifferentialIKSolver::new(0.001);
+    /// // move to x direction with 0.1 [m/s]
+    /// let twist = k::joint::Velocity::from_parts(k::Vector3::new(0.1, 0.0, 0.0), k::Vector3::zeros());
+    /// let velocities = solver
+    ///     .joint_velocities(&arm, &twist, &k::Constraints::default())
+    ///     .unwrap();
+    /// assert_eq!(velocities.len(), 6);
+    /// ```
+    pub fn joint_velocities(
+        &self,
+        arm: &SerialChain<T>,
+        twist: &Velocity<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<Vec<T>, IKError> {
+        let dof = arm.dof();
+        if let Some(ref limits) = self.velocity_limits {
+            if limits.len() != dof {
+                return Err(IKError::PreconditionError {
+                    error: format!("velocity_limits size={} must be dof={}", limits.len(), dof),
+                });
+            }
+            if limits.iter().any(|limit| *limit <= T::zero()) {
+                return Err(IKError::InvalidArgumentsError {
+                    error: format!("velocity_limits {:?} must be positive", limits),
+                });
+            }
+        }
+        let constraints_array = constraints_to_bool_array(*constraints);
+        let full_twist = [
+            twist.translation[0],
+            twist.translation[1],
+            twist.translation[2],
+            twist.rotation[0],
+            twist.rotation[1],
+            twist.rotation[2],
+        ];
+        let v = DVector::from_iterator(
+            constraints_array.iter().filter(|use_i| **use_i).count(),
+            full_twist
+                .iter()
+                .zip(constraints_array.iter())
+                .filter(|(_, use_i)| **use_i)
+                .map(|(value, _)| *value),
+        );
+        let jacobi = jacobian_with_constraints(jacobian(arm), &arm.end_transform(), constraints);
+        let (jacobi, v) = apply_constraints_weights(jacobi, v, constraints);
+        let rows = v.len();
+        let damped = &jacobi * jacobi.transpose()
+            + DMatrix::<T>::identity(rows, rows) * (self.damping * self.damping);
+        let jacobi_inv =
+            jacobi.transpose() * damped.try_inverse().ok_or(IKError::InverseMatrixError)?;
+        let mut d_q = &jacobi_inv * v;
+        if let Some(ref f) = self.nullspace_function {
+            d_q += (DMatrix::identity(dof, dof) - jacobi_inv * jacobi)
+                * DVector::from_vec(f(&arm.joint_positions()));
+        }
+        if let Some(ref limits) = self.velocity_limits {
+            let scale = d_q
+                .iter()
+                .zip(limits.iter())
+                .fold(T::one(), |scale, (velocity, limit)| {
+                    scale.max(velocity.abs() / *limit)
+                });
+            d_q /= scale;
+        }
+        Ok(d_q.as_slice().to_vec())
+    }
+
+    /// Move the joints of `arm` by the velocities for `twist` during `dt` [s]
+    ///
+    /// The joint positions are clamped within the limits. Returns the velocities.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let x = arm.end_transform().translation.vector.x;
+    ///
+    /// let solver = k::DifferentialIKSolver::new(0.001);
+    /// let twist = k::joint::Velocity::from_parts(k::Vector3::new(0.1, 0.0, 0.0), k::Vector3::zeros());
+    /// for _ in 0..10 {
+    ///     solver.step(&arm, &twist, &k::Constraints::default(), 0.01).unwrap();
+    /// }
+    /// assert!((arm.end_transform().translation.vector.x - x - 0.01).abs() < 0.001);
+    /// ```
+    pub fn step(
+        &self,
+        arm: &SerialChain<T>,
+        twist: &Velocity<T>,
+        constraints: &Constraints<T>,
+        dt: T,
+    ) -> Result<Vec<T>, IKError> {
+        let velocities = self.joint_velocities(arm, twist, constraints)?;
+        let positions = arm
+            .iter_joints()
+            .zip(arm.joint_positions().iter().zip(velocities.iter()))
+            .map(|(joint, (position, velocity))| {
+                let next = *position + *velocity * dt;
+                match joint.limits {
+                    Some(ref range) => na::clamp(next, range.min, range.max),
+                    None => next,
+                }
+            })
+            .collect::<Vec<_>>();
+        arm.set_joint_positions(&positions)?;
+        Ok(velocities)
+    }
+}
+
+impl<T> Default for DifferentialIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(na::convert(0.01))
+    }
+}
+
+#[test]
+fn test_differential_ik_velocity_limits() {
+    use joint::*;
+    use na::{Translation3, Vector3};
+    use node::*;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    end.set_parent(&j1);
+    let arm = SerialChain::<f64>::from_end(&end);
+    arm.set_joint_positions(&[0.0, 1.0]).unwrap();
+    let constraints = Constraints {
+        position_z: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    let twist = Velocity::from_parts(Vector3::new(0.0, 1.0, 0.0), Vector3::zeros());
+    let mut solver = DifferentialIKSolver::new(0.0);
+    let velocities = solver.joint_velocities(&arm, &twist, &constraints).unwrap();
+    arm.update_transforms();
+    let v = jacobian(&arm).rows(0, 2) * DVector::from_vec(velocities.clone());
+    assert!(v[0].abs() < 1e-9);
+    assert!((v[1] - 1.0).abs() < 1e-9);
+
+    solver.velocity_limits = Some(vec![0.1, 0.1]);
+    let limited = solver.joint_velocities(&arm, &twist, &constraints).unwrap();
+    assert!(limited.iter().all(|v| v.abs() <= 0.1 + 1e-9));
+    // same direction
+    let ratio = limited[0] / velocities[0];
+    assert!((limited[1] / velocities[1] - ratio).abs() < 1e-9);
+
+    solver.velocity_limits = Some(vec![0.1, 0.0]);
+    assert!(solver.joint_velocities(&arm, &twist, &constraints).is_err());
+}
@@ -45,6 +45,46 @@ pub enum JointError {
     },
     #[fail(display = "invalid arguments {:?}", error)]
     InvalidArgumentsError { error: String },
+    /// The trajectory violates the limits of the model
+    #[fail(
+        display = "trajectory violation at time {} by {:?}: {:?}",
+        time, name, violation
+    )]
+    TrajectoryViolationError {
+        /// time of the first violating sample
+        time: f64,
+        /// name of the joint, or the node which is out of the workspace
+        name: Option<String>,
+        /// what is violated
+        violation: TrajectoryViolation,
+    },
+}
+
+/// The kind of the violation of a trajectory
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrajectoryViolation {
+    /// The position is out of `Joint::limits`
+    PositionLimit {
+        /// position of the joint
+        position: f64,
+    },
+    /// The absolute velocity is larger than `Joint::velocity_limit`
+    VelocityLimit {
+        /// velocity of the joint
+        velocity: f64,
+    },
+    /// The absolute acceleration is larger than `Joint::acceleration_limit`
+    AccelerationLimit {
+        /// acceleration of the joint
+        acceleration: f64,
+    },
+    /// The origin of the node is out of the workspace box
+    Workspace {
+        /// position of the node in the world frame
+        position: [f64; 3],
+    },
+    /// The validity checker (self-collision) rejected the state
+    SelfCollision,
 }
 
 /// The reason of the fail of inverse kinematics
@@ -67,3 +107,66 @@ impl From<JointError> for IKError {
         IKError::JointOutOfLimitError { error }
     }
 }
+
+/// The reason of the fail of following a Cartesian path
+#[derive(Debug, Fail)]
+pub enum CartesianPathError {
+    /// Failed to solve inverse kinematics for the pose
+    #[fail(display = "ik failed at pose {}: {}", index, error)]
+    IKError {
+        /// index of the pose in the path
+        index: usize,
+        /// error of the IK solver
+        error: IKError,
+    },
+    /// The joint positions moved too much from the previous pose
+    #[fail(display = "joint jump at pose {}: {}", index, error)]
+    JointJumpError {
+        /// index of the pose in the path
+        index: usize,
+        /// detail error message
+        error: String,
+    },
+    /// The arm is too close to the singular configuration
+    #[fail(display = "singularity at pose {}: {}", index, error)]
+    SingularityError {
+        /// index of the pose in the path
+        index: usize,
+        /// detail error message
+        error: String,
+    },
+}
+
+impl CartesianPathError {
+    /// Index of the pose in the path where it failed
+    pub fn index(&self) -> usize {
+        match *self {
+            CartesianPathError::IKError { index, .. }
+            | CartesianPathError::JointJumpError { index, .. }
+            | CartesianPathError::SingularityError { index, .. } => index,
+        }
+    }
+}
+
+/// The reason of the fail of motion planning
+#[derive(Debug, Fail)]
+pub enum PlanningError {
+    /// The start positions are invalid for the validity checker
+    #[fail(display = "invalid start positions {:?}", error)]
+    InvalidStartError { error: String },
+    /// The goal positions are invalid for the validity checker
+    #[fail(display = "invalid goal positions {:?}", error)]
+    InvalidGoalError { error: String },
+    /// The path was not found within the number of the tries
+    #[fail(display = "path not found {:?}", error)]
+    NotFoundError { error: String },
+    /// The positions are out of the limits or have wrong size
+    #[fail(display = "joint error: {:?}", error)]
+    JointError { error: JointError },
+}
+
+impl From<JointError> for PlanningError {
+    fn from(error: JointError) -> PlanningError {
+        PlanningError::JointError { error }
+    }
+}
@@ -1,8 +1,96 @@
 use chain::*;
 use joint::*;
 use na::{DMatrix, RealField, Vector3};
+use node::*;
+
+fn jacobian_column<T>(joint: &Joint<T>, p_n: &Vector3<T>) -> [T; 6]
+where
+    T: RealField,
+{
+    let t_i = joint.world_transform().unwrap();
+    match joint.joint_type {
+        JointType::Linear { axis } => {
+            let p_i = t_i.rotation * axis;
+            [p_i[0], p_i[1], p_i[2], na::zero(), na::zero(), na::zero()]
+        }
+        JointType::Rotational { axis } => {
+            let p_i = t_i.translation;
+            let a_i = t_i.rotation * axis;
+            let dp_i = a_i.cross(&(p_n - p_i.vector));
+            [dp_i[0], dp_i[1], dp_i[2], a_i[0], a_i[1], a_i[2]]
+        }
+        JointType::Fixed => panic!("impossible, bug of jacobian"),
+    }
+}
+
+/// Add the columns of the mimic joints to the columns of their driving joints
+///
+/// The nested mimic joints are resolved to the root driving joint (which is not a mimic
+/// joint) with the product of the multipliers. The columns of the mimic joints become
+/// zero, because they can not move independently. If the root driving joint is not
+/// contained in `chain`, the column is just cleared.
+fn collapse_mimic_columns<T>(chain: &Chain<T>, jacobi: &mut DMatrix<T>)
+where
+    T: RealField,
+{
+    let movable_nodes = chain
+        .iter()
+        .filter(|node| node.joint().is_movable())
+        .collect::<Vec<_>>();
+    for (c, node) in movable_nodes.iter().enumerate() {
+        if node.mimic_parent().is_none() {
+            continue;
+        }
+        let mut multiplier = T::one();
+        let mut driver = (*node).clone();
+        while let (Some(parent), Some(mimic)) = (driver.mimic_parent(), driver.mimic()) {
+            multiplier *= mimic.multiplier;
+            driver = parent;
+        }
+        let column = jacobi.column(c) * multiplier;
+        if let Some(driver_index) = movable_nodes.iter().position(|n| **n == driver) {
+            let mut driver_column = jacobi.column_mut(driver_index);
+            driver_column += column;
+        }
+        jacobi.column_mut(c).fill(T::zero());
+    }
+}
 
 /// Calculate Jacobian of the serial chain (manipulator).
+///
+/// The mimic joints are collapsed into their driving joints with the multiplier,
+/// so the columns of the mimic joints are zero.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+///
+/// let j0 = JointBuilder::new()
+///     .joint_type(JointType::Linear { axis: Vector3::x_axis() })
+///     .into_node();
+/// let j1 = JointBuilder::new()
+///     .joint_type(JointType::Linear { axis: Vector3::x_axis() })
+///     .into_node();
+/// j1.set_parent(&j0);
+/// j1.set_mimic_parent(&j0, k::joint::Mimic::new(2.0, 0.0));
+/// let arm = SerialChain::<f64>::from_end(&j1);
+/// let jacobi = jacobian(&arm);
+/// assert_eq!(jacobi[(0, 0)], 3.0);
+/// assert_eq!(jacobi[(0, 1)], 0.0);
+///
+/// // nested mimic: j2 mimics j1, which mimics j0
+/// let j2 = JointBuilder::new()
+///     .joint_type(JointType::Linear { axis: Vector3::x_axis() })
+///     .into_node();
+/// j2.set_parent(&j1);
+/// j2.set_mimic_parent(&j1, k::joint::Mimic::new(3.0, 0.0));
+/// let arm = SerialChain::<f64>::from_end(&j2);
+/// let jacobi = jacobian(&arm);
+/// assert_eq!(jacobi[(0, 0)], 9.0);
+/// assert_eq!(jacobi[(0, 1)], 0.0);
+/// assert_eq!(jacobi[(0, 2)], 0.0);
+/// ```
 pub fn jacobian<T>(arm: &SerialChain<T>) -> DMatrix<T>
 where
     T: RealField,
@@ -13,26 +101,100 @@ where
     let p_n = t_n.translation;
     let jacobi_vec = arm
         .iter_joints()
-        .map(|joint| {
-            let t_i = joint.world_transform().unwrap();
-            match joint.joint_type {
-                JointType::Linear { axis } => {
-                    let p_i = t_i.rotation * axis;
-                    [p_i[0], p_i[1], p_i[2], na::zero(), na::zero(), na::zero()]
-                }
-                JointType::Rotational { axis } => {
-                    let p_i = t_i.translation;
-                    let a_i = t_i.rotation * axis;
-                    let dp_i = a_i.cross(&(p_n.vector - p_i.vector));
-                    [dp_i[0], dp_i[1], dp_i[2], a_i[0], a_i[1], a_i[2]]
-                }
-                JointType::Fixed => panic!("impossible, bug of jacobian"),
-            }
-        })
+        .map(|joint| jacobian_column(&joint, &p_n.vector))
         .collect::<Vec<_>>();
     // Pi: a_i x (p_n - Pi)
     // wi: a_i
-    DMatrix::from_fn(6, dof, |r, c| jacobi_vec[c][r])
+    let mut jacobi = DMatrix::from_fn(6, dof, |r, c| jacobi_vec[c][r]);
+    collapse_mimic_columns(arm, &mut jacobi);
+    jacobi
+}
+
+/// Calculate Jacobian of the `end` node in the (branched) chain.
+///
+/// The size is 6 x `chain.dof()` and the columns are in the order of `chain.iter_joints()`.
+/// The columns of the joints which do not move `end` and the mimic joints are zero.
+/// `end` must be contained in `chain`.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+///
+/// let j0 = JointBuilder::new()
+///     .name("j0")
+///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+///     .into_node();
+/// let j1 = JointBuilder::new()
+///     .name("j1")
+///     .translation(Translation3::new(1.0, 0.0, 0.0))
+///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+///     .into_node();
+/// let j2 = JointBuilder::new()
+///     .name("j2")
+///     .translation(Translation3::new(0.0, 1.0, 0.0))
+///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+///     .into_node();
+/// j1.set_parent(&j0);
+/// j2.set_parent(&j0);
+/// let tree = Chain::<f64>::from_root(j0);
+/// let jacobi = jacobian_for_node(&tree, &j1);
+/// assert_eq!(jacobi.ncols(), 3);
+/// assert_eq!(jacobi[(1, 0)], 1.0);
+/// // j2 does not move j1
+/// let j2_index = tree.iter_joints().position(|j| j.name == "j2").unwrap();
+/// assert_eq!(jacobi.column(j2_index).norm(), 0.0);
+/// ```
+pub fn jacobian_for_node<T>(chain: &Chain<T>, end: &Node<T>) -> DMatrix<T>
+where
+    T: RealField,
+{
+    chain.update_transforms();
+    let p_n = end
+        .world_transform()
+        .expect("end must be contained in the chain")
+        .translation
+        .vector;
+    let ancestors = end.iter_ancestors().collect::<Vec<_>>();
+    let mut jacobi = DMatrix::zeros(6, chain.dof());
+    for (c, node) in chain
+        .iter()
+        .filter(|node| node.joint().is_movable())
+        .enumerate()
+    {
+        if ancestors.contains(node) {
+            let column = jacobian_column(&node.joint(), &p_n);
+            for (r, value) in column.iter().enumerate() {
+                jacobi[(r, c)] = *value;
+            }
+        }
+    }
+    collapse_mimic_columns(chain, &mut jacobi);
+    jacobi
+}
+
+/// Calculate the manipulability (Yoshikawa) of the arm
+///
+/// It is the product of the singular values of the Jacobian, which equals to
+/// sqrt(det(J J^T)) if the dof is larger than or equal to 6.
+/// It becomes zero at the singular configurations.
+///
+/// # Examples
+///
+/// ```
+/// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+/// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+/// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+/// assert!(k::manipulability(&arm) >= 0.0);
+/// ```
+pub fn manipulability<T>(arm: &SerialChain<T>) -> T
+where
+    T: RealField,
+{
+    jacobian(arm)
+        .singular_values()
+        .iter()
+        .fold(T::one(), |product, value| product * *value)
 }
 
 /// Calculate the center of mass of the chain
@@ -73,6 +235,63 @@ where
     com / total_mass
 }
 
+/// Calculate the Jacobian of the center of mass of the chain
+///
+/// The size is 3 x `chain.dof()` and the columns are in the order of `chain.iter_joints()`.
+///
+/// ```
+/// use k::*;
+/// use k::link::*;
+///
+/// let j0 = JointBuilder::new()
+///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+///     .into_node();
+/// let j1 = JointBuilder::new()
+///     .translation(Translation3::new(1.0, 0.0, 0.0))
+///     .into_node();
+/// j1.set_link(Some(LinkBuilder::new().inertial(Inertial::from_mass(1.0)).finalize()));
+/// j1.set_parent(&j0);
+/// let tree = Chain::<f64>::from_root(j0);
+/// let com_jacobi = center_of_mass_jacobian(&tree);
+/// assert_eq!(com_jacobi.nrows(), 3);
+/// assert!((com_jacobi[(1, 0)] - 1.0).abs() < 1e-9);
+/// ```
+pub fn center_of_mass_jacobian<T>(chain: &Chain<T>) -> DMatrix<T>
+where
+    T: RealField,
+{
+    let mut total_mass = T::zero();
+    let mut jacobi = DMatrix::zeros(3, chain.dof());
+
+    chain.update_transforms();
+    let movable_nodes = chain
+        .iter()
+        .filter(|node| node.joint().is_movable())
+        .collect::<Vec<_>>();
+    for node in chain.iter() {
+        if let Some(trans) = node.joint().world_transform() {
+            if let Some(ref link) = *node.link() {
+                let mass = link.inertial.mass;
+                let com_i = (trans * link.inertial.origin().translation)
+                    .translation
+                    .vector;
+                let ancestors = node.iter_ancestors().collect::<Vec<_>>();
+                for (c, movable_node) in movable_nodes.iter().enumerate() {
+                    if ancestors.contains(movable_node) {
+                        let column = jacobian_column(&movable_node.joint(), &com_i);
+                        for r in 0..3 {
+                            jacobi[(r, c)] += column[r] * mass;
+                        }
+                    }
+                }
+                total_mass += mass;
+            }
+        }
+    }
+    collapse_mimic_columns(chain, &mut jacobi);
+    jacobi / total_mass
+}
+
 #[test]
 fn test_update_center_of_mass() {
     use super::joint::*;
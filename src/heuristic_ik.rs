@@ -0,0 +1,497 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, Isometry3, RealField, Vector3};
+
+use chain::*;
+use errors::*;
+use ik::*;
+use joint::*;
+
+/// Check that the constraints are the full pose or the position only in the world frame
+///
+/// Returns true if the rotation is constrained.
+fn check_heuristic_constraints<T>(constraints: &Constraints<T>) -> Result<bool, IKError>
+where
+    T: RealField,
+{
+    let array = constraints_to_bool_array(*constraints);
+    let is_position_full = array[0] && array[1] && array[2];
+    let use_rotation = array[3] || array[4] || array[5];
+    if constraints.frame != ConstraintsFrame::World
+        || !is_position_full
+        || (use_rotation && !(array[3] && array[4] && array[5]))
+    {
+        return Err(IKError::PreconditionError {
+            error: "only the full pose or the position in the world frame is supported".to_owned(),
+        });
+    }
+    Ok(use_rotation)
+}
+
+/// Clamp the position within the limits of the joint
+fn clamp_to_limits<T>(joint: &Joint<T>, position: T) -> T
+where
+    T: RealField,
+{
+    match joint.limits {
+        Some(ref range) => na::clamp(position, range.min, range.max),
+        None => position,
+    }
+}
+
+/// Signed angle around `axis` from `from` to `to` and the weight of the angle
+///
+/// The vectors are projected to the plane which is perpendicular to `axis`.
+/// The returned values are the numerator and the denominator of atan2.
+fn projected_angle_terms<T>(axis: &Vector3<T>, from: &Vector3<T>, to: &Vector3<T>) -> (T, T)
+where
+    T: RealField,
+{
+    let from = from - axis * axis.dot(from);
+    let to = to - axis * axis.dot(to);
+    (axis.dot(&from.cross(&to)), from.dot(&to))
+}
+
+/// Converged if the error of the `arm` is within the allowable errors
+fn is_reached<T>(
+    arm: &SerialChain<T>,
+    target_pose: &Isometry3<T>,
+    constraints: &Constraints<T>,
+    allowable_target_distance: T,
+    allowable_target_angle: T,
+) -> bool
+where
+    T: RealField,
+{
+    let diff = calc_pose_diff_with_constraints(target_pose, &arm.end_transform(), constraints);
+    is_target_reached(
+        &diff,
+        constraints,
+        allowable_target_distance,
+        allowable_target_angle,
+    )
+}
+
+/// Inverse Kinematics Solver using Cyclic Coordinate Descent (CCD)
+///
+/// Each joint is moved from the end to the root to minimize the error of the end
+/// with the closed-form solution of the single joint, which is clamped within the limits.
+/// It is fast and stable for long chains, but the motion is not minimal.
+/// Only the full pose or the position in the world frame can be constrained.
+pub struct CCDIKSolver<T: RealField> {
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, it is reached.
+    pub allowable_target_angle: T,
+    /// Weight of the orientation error against the position error [m/rad]
+    pub orientation_weight: T,
+    /// How many times all the joints are tried to be moved
+    pub num_max_try: usize,
+}
+
+impl<T> CCDIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `CCDIKSolver`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::CCDIKSolver::new(0.001, 0.005, 100);
+    /// ```
+    pub fn new(
+        allowable_target_distance: T,
+        allowable_target_angle: T,
+        num_max_try: usize,
+    ) -> Self {
+        Self {
+            allowable_target_distance,
+            allowable_target_angle,
+            orientation_weight: na::convert(0.1),
+            num_max_try,
+        }
+    }
+
+    fn solve_one_loop(&self, arm: &SerialChain<T>, target_pose: &Isometry3<T>, use_rotation: bool) {
+        let mut positions = arm.joint_positions();
+        for i in (0..positions.len()).rev() {
+            arm.update_transforms();
+            let end_pose = arm.end_transform();
+            let joint = arm.iter_joints().nth(i).expect("index must be in the dof");
+            let t_i = joint.world_transform().expect("cache must exist");
+            let new_position = match joint.joint_type {
+                JointType::Rotational { axis } => {
+                    let a_i = t_i.rotation * axis.into_inner();
+                    let (mut sin, mut cos) = projected_angle_terms(
+                        &a_i,
+                        &(end_pose.translation.vector - t_i.translation.vector),
+                        &(target_pose.translation.vector - t_i.translation.vector),
+                    );
+                    if use_rotation {
+                        for unit in &[Vector3::x(), Vector3::y(), Vector3::z()] {
+                            let (s, c) = projected_angle_terms(
+                                &a_i,
+                                &(end_pose.rotation * unit),
+                                &(target_pose.rotation * unit),
+                            );
+                            sin += s * self.orientation_weight;
+                            cos += c * self.orientation_weight;
+                        }
+                    }
+                    positions[i] + sin.atan2(cos)
+                }
+                JointType::Linear { axis } => {
+                    let a_i = t_i.rotation * axis.into_inner();
+                    positions[i]
+                        + a_i.dot(&(target_pose.translation.vector - end_pose.translation.vector))
+                }
+                JointType::Fixed => positions[i],
+            };
+            positions[i] = clamp_to_limits(&joint, new_position);
+            drop(joint);
+            arm.set_joint_positions_unchecked(&positions);
+        }
+    }
+
+    fn solve_internal(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        let use_rotation = check_heuristic_constraints(constraints)?;
+        for _ in 0..self.num_max_try {
+            self.solve_one_loop(arm, target_pose, use_rotation);
+            if is_reached(
+                arm,
+                target_pose,
+                constraints,
+                self.allowable_target_distance,
+                self.allowable_target_angle,
+            ) {
+                let positions = arm.joint_positions();
+                arm.set_joint_positions(&positions)?;
+                return Ok(());
+            }
+        }
+        Err(IKError::NotConvergedError {
+            error: format!("CCD has not converged: tried {} times", self.num_max_try),
+        })
+    }
+}
+
+impl<T> InverseKinematicsSolver<T> for CCDIKSolver<T>
+where
+    T: RealField,
+{
+    /// Set joint positions of `arm` to reach the `target_pose`
+    ///
+    /// The joint positions of `arm` are restored if it fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::prelude::*;
+    ///
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let mut target = arm.end_transform();
+    /// target.translation.vector.x -= 0.1;
+    ///
+    /// let solver = k::CCDIKSolver::new(0.001, 0.01, 1000);
+    /// solver.solve(&arm, &target).unwrap();
+    /// ```
+    fn solve_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        let orig_positions = arm.joint_positions();
+        let re = self.solve_internal(arm, target_pose, constraints);
+        if re.is_err() {
+            arm.set_joint_positions(&orig_positions)?;
+        };
+        re
+    }
+}
+
+impl<T> Default for CCDIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(na::convert(0.001), na::convert(0.005), 100)
+    }
+}
+
+/// Inverse Kinematics Solver using Forward And Backward Reaching Inverse Kinematics (FABRIK)
+///
+/// The positions of the joints are moved by the FABRIK iteration, and then
+/// the joint positions are calculated from the root to follow them within the limits.
+/// Only the position of the end can be constrained, use `CCDIKSolver` for the orientation.
+pub struct FABRIKSolver<T: RealField> {
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// How many times the joints are tried to be moved
+    pub num_max_try: usize,
+}
+
+impl<T> FABRIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `FABRIKSolver`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::FABRIKSolver::new(0.001, 100);
+    /// ```
+    pub fn new(allowable_target_distance: T, num_max_try: usize) -> Self {
+        Self {
+            allowable_target_distance,
+            num_max_try,
+        }
+    }
+
+    /// Positions of the movable joints and the end in the world frame
+    fn points(arm: &SerialChain<T>) -> Vec<Vector3<T>> {
+        arm.update_transforms();
+        let mut points = arm
+            .iter_joints()
+            .map(|joint| {
+                joint
+                    .world_transform()
+                    .expect("cache must exist")
+                    .translation
+                    .vector
+            })
+            .collect::<Vec<_>>();
+        points.push(arm.end_transform().translation.vector);
+        points
+    }
+
+    /// One forward and backward reaching of the points
+    fn reach(points: &[Vector3<T>], target: &Vector3<T>) -> Vec<Vector3<T>> {
+        let lengths = points
+            .windows(2)
+            .map(|pair| (pair[1] - pair[0]).norm())
+            .collect::<Vec<_>>();
+        let move_to = |from: &Vector3<T>, toward: &Vector3<T>, length: T| {
+            let diff = toward - from;
+            let norm = diff.norm();
+            if norm > T::default_epsilon() {
+                from + diff * (length / norm)
+            } else {
+                *from
+            }
+        };
+        let mut reached = points.to_vec();
+        let n = reached.len() - 1;
+        // backward: from the end to the root
+        reached[n] = *target;
+        for i in (0..n).rev() {
+            reached[i] = move_to(&reached[i + 1], &reached[i], lengths[i]);
+        }
+        // forward: from the root to the end
+        reached[0] = points[0];
+        for i in 0..n {
+            reached[i + 1] = move_to(&reached[i], &reached[i + 1], lengths[i]);
+        }
+        reached
+    }
+
+    /// Move the joints from the root to follow the reached points
+    fn follow(arm: &SerialChain<T>, reached: &[Vector3<T>]) {
+        let mut positions = arm.joint_positions();
+        for i in 0..positions.len() {
+            let points = Self::points(arm);
+            let joint = arm.iter_joints().nth(i).expect("index must be in the dof");
+            let t_i = joint.world_transform().expect("cache must exist");
+            let p_i = t_i.translation.vector;
+            let new_position = match joint.joint_type {
+                JointType::Rotational { axis } => {
+                    let a_i = t_i.rotation * axis.into_inner();
+                    // rotate all the descendant points toward the reached points
+                    let (sin, cos) = (i + 1..points.len())
+                        .map(|k| {
+                            projected_angle_terms(&a_i, &(points[k] - p_i), &(reached[k] - p_i))
+                        })
+                        .fold((T::zero(), T::zero()), |(sin, cos), (s, c)| {
+                            (sin + s, cos + c)
+                        });
+                    positions[i] + sin.atan2(cos)
+                }
+                JointType::Linear { axis } => {
+                    let a_i = t_i.rotation * axis.into_inner();
+                    positions[i] + a_i.dot(&(reached[i + 1] - points[i + 1]))
+                }
+                JointType::Fixed => positions[i],
+            };
+            positions[i] = clamp_to_limits(&joint, new_position);
+            drop(joint);
+            arm.set_joint_positions_unchecked(&positions);
+        }
+    }
+
+    fn solve_internal(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        if check_heuristic_constraints(constraints)? {
+            return Err(IKError::PreconditionError {
+                error: "FABRIKSolver supports only the position constraints".to_owned(),
+            });
+        }
+        let target = target_pose.translation.vector;
+        for _ in 0..self.num_max_try {
+            let reached = Self::reach(&Self::points(arm), &target);
+            Self::follow(arm, &reached);
+            if (arm.end_transform().translation.vector - target).norm()
+                < self.allowable_target_distance
+            {
+                let positions = arm.joint_positions();
+                arm.set_joint_positions(&positions)?;
+                return Ok(());
+            }
+        }
+        Err(IKError::NotConvergedError {
+            error: format!("FABRIK has not converged: tried {} times", self.num_max_try),
+        })
+    }
+}
+
+impl<T> InverseKinematicsSolver<T> for FABRIKSolver<T>
+where
+    T: RealField,
+{
+    /// Set joint positions of `arm` to reach the position of `target_pose`
+    ///
+    /// `solve` is the same as `solve_with_constraints` with the position constraints.
+    /// The joint positions of `arm` are restored if it fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::prelude::*;
+    ///
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let mut target = arm.end_transform();
+    /// target.translation.vector.x -= 0.1;
+    ///
+    /// let solver = k::FABRIKSolver::new(0.001, 100);
+    /// solver.solve(&arm, &target).unwrap();
+    /// ```
+    fn solve(&self, arm: &SerialChain<T>, target_pose: &Isometry3<T>) -> Result<(), IKError> {
+        let constraints = Constraints {
+            rotation_x: false,
+            rotation_y: false,
+            rotation_z: false,
+            ..Constraints::default()
+        };
+        self.solve_with_constraints(arm, target_pose, &constraints)
+    }
+
+    fn solve_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        let orig_positions = arm.joint_positions();
+        let re = self.solve_internal(arm, target_pose, constraints);
+        if re.is_err() {
+            arm.set_joint_positions(&orig_positions)?;
+        };
+        re
+    }
+}
+
+impl<T> Default for FABRIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(na::convert(0.001), 100)
+    }
+}
+
+#[test]
+fn test_heuristic_ik_long_chain() {
+    use na::Translation3;
+    use node::*;
+
+    // tentacle with 12 joints
+    let nodes = (0..12)
+        .map(|i| {
+            JointBuilder::<f64>::new()
+                .name(&format!("j{}", i))
+                .translation(Translation3::new(0.0, 0.0, if i == 0 { 0.0 } else { 0.1 }))
+                .joint_type(JointType::Rotational {
+                    axis: if i % 2 == 0 {
+                        Vector3::x_axis()
+                    } else {
+                        Vector3::y_axis()
+                    },
+                })
+                .limits(Some((-0.8..=0.8).into()))
+                .into_node()
+        })
+        .collect::<Vec<_>>();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(0.0, 0.0, 0.1))
+        .into_node();
+    for i in 1..nodes.len() {
+        nodes[i].set_parent(&nodes[i - 1]);
+    }
+    end.set_parent(&nodes[11]);
+    let arm = SerialChain::from_end(&end);
+    arm.set_joint_positions(&[0.3, 0.2, -0.1, 0.4, 0.2, 0.1, -0.3, 0.2, 0.1, 0.3, 0.0, 0.2])
+        .unwrap();
+    let target = arm.end_transform();
+    let initial = vec![0.1; 12];
+
+    arm.set_joint_positions(&initial).unwrap();
+    FABRIKSolver::new(0.001, 200).solve(&arm, &target).unwrap();
+    let diff = arm.end_transform().translation.vector - target.translation.vector;
+    assert!(diff.norm() < 0.001);
+
+    arm.set_joint_positions(&initial).unwrap();
+    CCDIKSolver::new(0.001, 0.01, 1000)
+        .solve(&arm, &target)
+        .unwrap();
+    let diff = arm.end_transform().translation.vector - target.translation.vector;
+    assert!(diff.norm() < 0.001);
+    let angle = arm.end_transform().rotation.angle_to(&target.rotation);
+    assert!(angle < 0.01);
+    assert!(arm
+        .joint_positions()
+        .iter()
+        .all(|position| position.abs() <= 0.8));
+
+    // the orientation can not be solved by FABRIK
+    assert!(FABRIKSolver::new(0.001, 200)
+        .solve_with_constraints(&arm, &target, &Constraints::default())
+        .is_err());
+}
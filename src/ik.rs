@@ -13,11 +13,13 @@
   See the License for the specific language governing permissions and
   limitations under the License.
 */
-use na::{self, DVector, Isometry3, RealField, Vector3, Vector6};
+use na::{self, DMatrix, DVector, Isometry3, RealField, Vector3, Vector6};
+use std::fmt::{self, Display};
 
 use chain::*;
 use errors::*;
 use funcs::*;
+use ik_goal::*;
 
 /// From 'Humanoid Robot (Kajita)' P.64
 fn calc_pose_diff<T>(a: &Isometry3<T>, b: &Isometry3<T>) -> Vector6<T>
@@ -31,15 +33,40 @@ where
     )
 }
 
-fn calc_pose_diff_with_constraints<T>(
+/// Difference of the poses in the frame of the constraints
+fn calc_pose_diff_in_frame<T>(
     a: &Isometry3<T>,
     b: &Isometry3<T>,
-    constraints_array: [bool; 6],
+    frame: ConstraintsFrame,
+) -> Vector6<T>
+where
+    T: RealField,
+{
+    let diff = calc_pose_diff(a, b);
+    match frame {
+        ConstraintsFrame::World => diff,
+        ConstraintsFrame::EndEffector => {
+            let inv_rot = b.rotation.inverse();
+            let p_diff = inv_rot * Vector3::new(diff[0], diff[1], diff[2]);
+            let w_diff = inv_rot * Vector3::new(diff[3], diff[4], diff[5]);
+            Vector6::new(
+                p_diff[0], p_diff[1], p_diff[2], w_diff[0], w_diff[1], w_diff[2],
+            )
+        }
+    }
+}
+
+/// Difference from `b` to `a` of the constrained coordinates (without weights)
+pub(crate) fn calc_pose_diff_with_constraints<T>(
+    a: &Isometry3<T>,
+    b: &Isometry3<T>,
+    constraints: &Constraints<T>,
 ) -> DVector<T>
 where
     T: RealField,
 {
-    let full_diff = calc_pose_diff(a, b);
+    let constraints_array = constraints_to_bool_array(*constraints);
+    let full_diff = calc_pose_diff_in_frame(a, b, constraints.frame);
     let use_dof = constraints_array.into_iter().filter(|x| **x).count();
     let mut diff = DVector::from_element(use_dof, na::zero());
     let mut index = 0;
@@ -52,30 +79,120 @@ where
     diff
 }
 
+pub(crate) fn remove_unconstrained_rows<T>(
+    mut jacobi: DMatrix<T>,
+    constraints_array: [bool; 6],
+) -> DMatrix<T>
+where
+    T: RealField,
+{
+    let mut removed_count = 0;
+    for (i, use_i) in constraints_array.iter().enumerate() {
+        if !use_i {
+            jacobi = jacobi.remove_row(i - removed_count);
+            removed_count += 1;
+        }
+    }
+    jacobi
+}
+
+/// Convert the world frame Jacobian of the end at `end_pose` to the rows of the constrained coordinates
+pub(crate) fn jacobian_with_constraints<T>(
+    mut jacobi: DMatrix<T>,
+    end_pose: &Isometry3<T>,
+    constraints: &Constraints<T>,
+) -> DMatrix<T>
+where
+    T: RealField,
+{
+    if let ConstraintsFrame::EndEffector = constraints.frame {
+        let inv_rot = end_pose.rotation.inverse().to_rotation_matrix();
+        for i in 0..jacobi.ncols() {
+            for offset in &[0, 3] {
+                let rotated = inv_rot * jacobi.fixed_slice::<na::U3, na::U1>(*offset, i);
+                jacobi
+                    .fixed_slice_mut::<na::U3, na::U1>(*offset, i)
+                    .copy_from(&rotated);
+            }
+        }
+    }
+    remove_unconstrained_rows(jacobi, constraints_to_bool_array(*constraints))
+}
+
+/// Multiply the weights of the constrained coordinates to the rows of the Jacobian and the error
+pub(crate) fn apply_constraints_weights<T>(
+    mut jacobi: DMatrix<T>,
+    mut err: DVector<T>,
+    constraints: &Constraints<T>,
+) -> (DMatrix<T>, DVector<T>)
+where
+    T: RealField,
+{
+    let weights = constraints_to_bool_array(*constraints)
+        .iter()
+        .zip(constraints.weights.iter())
+        .filter(|(use_i, _)| **use_i)
+        .map(|(_, weight)| *weight)
+        .collect::<Vec<T>>();
+    for (i, weight) in weights.into_iter().enumerate() {
+        jacobi.row_mut(i).apply(|v| v * weight);
+        err[i] *= weight;
+    }
+    (jacobi, err)
+}
+
+/// A frame in which the constrained coordinates are defined
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConstraintsFrame {
+    /// The world (root) frame
+    World,
+    /// The frame of the end of the arm
+    EndEffector,
+}
+
 /// A bundle of flags determining which coordinates are constrained for a target
+///
+/// Set only the fields to change and fill the others by `..Default::default()`.
 #[derive(Clone, Copy, Debug)]
-pub struct Constraints {
+pub struct Constraints<T: RealField> {
     /// true means the constraint is used.
-    ///  The coordinates is the world, not the end of the arm.
+    ///  The coordinates are defined in `frame`.
     pub position_x: bool,
     pub position_y: bool,
     pub position_z: bool,
     pub rotation_x: bool,
     pub rotation_y: bool,
     pub rotation_z: bool,
+    /// The frame of the coordinates
+    pub frame: ConstraintsFrame,
+    /// Weights of [position_x, position_y, position_z, rotation_x, rotation_y, rotation_z].
+    ///
+    /// The weights matter when the constraints can not be satisfied at the same time
+    /// during the iteration, or for the least squares of a redundant system.
+    pub weights: [T; 6],
+    /// Per-coordinate allowable errors in the same order as `weights`.
+    ///
+    /// If it is `Some`, it is used instead of the allowable distance and angle of the solver.
+    pub tolerances: Option<[T; 6]>,
 }
 
-impl Default for Constraints {
-    /// Initialize with all true
+impl<T> Default for Constraints<T>
+where
+    T: RealField,
+{
+    /// Initialize with all true in the world frame
     ///
     /// ```
-    /// let c = k::Constraints::default();
+    /// let c = k::Constraints::<f64>::default();
     /// assert!(c.position_x);
     /// assert!(c.position_y);
     /// assert!(c.position_z);
     /// assert!(c.rotation_x);
     /// assert!(c.rotation_y);
     /// assert!(c.rotation_z);
+    /// assert_eq!(c.frame, k::ConstraintsFrame::World);
+    /// assert_eq!(c.weights, [1.0; 6]);
+    /// assert!(c.tolerances.is_none());
     /// ```
     fn default() -> Self {
         Self {
@@ -85,11 +202,17 @@ impl Default for Constraints {
             rotation_x: true,
             rotation_y: true,
             rotation_z: true,
+            frame: ConstraintsFrame::World,
+            weights: [T::one(); 6],
+            tolerances: None,
         }
     }
 }
 
-fn constraints_to_bool_array(constraints: Constraints) -> [bool; 6] {
+pub(crate) fn constraints_to_bool_array<T>(constraints: Constraints<T>) -> [bool; 6]
+where
+    T: RealField,
+{
     let mut arr = [true; 6];
     arr[0] = constraints.position_x;
     arr[1] = constraints.position_y;
@@ -100,6 +223,33 @@ fn constraints_to_bool_array(constraints: Constraints) -> [bool; 6] {
     arr
 }
 
+/// Check if the constrained difference is within the tolerances
+///
+/// `allowable_target_distance` and `allowable_target_angle` are used
+/// if `constraints.tolerances` is `None`.
+pub(crate) fn is_target_reached<T>(
+    target_diff: &DVector<T>,
+    constraints: &Constraints<T>,
+    allowable_target_distance: T,
+    allowable_target_angle: T,
+) -> bool
+where
+    T: RealField,
+{
+    match constraints.tolerances {
+        Some(tolerances) => constraints_to_bool_array(*constraints)
+            .iter()
+            .zip(tolerances.iter())
+            .filter(|(use_i, _)| **use_i)
+            .zip(target_diff.iter())
+            .all(|((_, tolerance), diff)| diff.abs() <= *tolerance),
+        None => {
+            let (len_diff, rot_diff) = target_diff_to_len_rot_diff(target_diff, constraints);
+            len_diff.norm() < allowable_target_distance && rot_diff.norm() < allowable_target_angle
+        }
+    }
+}
+
 /// IK solver
 pub trait InverseKinematicsSolver<T>
 where
@@ -114,8 +264,165 @@ where
         &self,
         arm: &SerialChain<T>,
         target_pose: &Isometry3<T>,
-        constraints: &Constraints,
+        constraints: &Constraints<T>,
     ) -> Result<(), IKError>;
+    /// Move the end transform of the `arm` to `target_pose` and returns the details
+    ///
+    /// It returns `Ok` even if it has not converged, and `IKReport::joint_positions`
+    /// has the positions of the last iteration then. The positions of `arm` are
+    /// restored if it has not converged. By default, the report is made from the
+    /// result of `solve_with_constraints`, so the positions of the last iteration
+    /// are the restored positions.
+    fn solve_with_report(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<IKReport<T>, IKError> {
+        let converged = self
+            .solve_with_constraints(arm, target_pose, constraints)
+            .is_ok();
+        let diff = calc_pose_diff_with_constraints(target_pose, &arm.end_transform(), constraints);
+        let (len_diff, rot_diff) = target_diff_to_len_rot_diff(&diff, constraints);
+        Ok(IKReport {
+            converged,
+            num_iterations: 0,
+            position_error: len_diff.norm(),
+            orientation_error: rot_diff.norm(),
+            joint_positions: arm.joint_positions(),
+            limits_active: is_limits_active(arm),
+            trace: None,
+        })
+    }
+    /// Move the end of the `arm` to satisfy the `goal`
+    ///
+    /// Only `IKGoal::Pose` is supported by default.
+    fn solve_goal(&self, arm: &SerialChain<T>, goal: &IKGoal<T>) -> Result<(), IKError> {
+        match *goal {
+            IKGoal::Pose {
+                ref target,
+                ref constraints,
+            } => self.solve_with_constraints(arm, target, constraints),
+            _ => Err(IKError::PreconditionError {
+                error: format!("{:?} is not supported by this solver", goal),
+            }),
+        }
+    }
+}
+
+/// A step of the iteration of IK
+#[derive(Debug, Clone)]
+pub struct IKTraceStep<T: RealField> {
+    /// Joint positions after this step
+    pub joint_positions: Vec<T>,
+    /// Distance between the end and the target after this step
+    pub position_error: T,
+    /// Angle between the end and the target after this step
+    pub orientation_error: T,
+}
+
+/// Detailed result of IK
+#[derive(Debug, Clone)]
+pub struct IKReport<T: RealField> {
+    /// true if the target is reached
+    pub converged: bool,
+    /// How many times the joints are moved
+    pub num_iterations: usize,
+    /// Distance between the end and the target of the last iteration
+    pub position_error: T,
+    /// Angle between the end and the target of the last iteration
+    pub orientation_error: T,
+    /// Joint positions of the last iteration
+    pub joint_positions: Vec<T>,
+    /// true if any joint reached its limit during the iteration
+    pub limits_active: bool,
+    /// All the steps of the iteration if it is recorded
+    pub trace: Option<Vec<IKTraceStep<T>>>,
+}
+
+impl<T> Display for IKReport<T>
+where
+    T: RealField,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "converged = {}, iterations = {}, position error = {}, orientation error = {}, limits active = {}",
+            self.converged,
+            self.num_iterations,
+            self.position_error,
+            self.orientation_error,
+            self.limits_active
+        )
+    }
+}
+
+/// IK solver which finds multiple solutions
+///
+/// For example, elbow-up and elbow-down configurations of an arm.
+pub trait InverseKinematicsSolutions<T>
+where
+    T: RealField,
+{
+    /// Find the joint positions of `arm` which reach `target_pose`
+    fn solutions(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+    ) -> Result<Vec<Vec<T>>, IKError> {
+        self.solutions_with_constraints(arm, target_pose, &Constraints::default())
+    }
+    /// Find the joint positions of `arm` which reach `target_pose` with constraints
+    ///
+    /// The solutions are distinct and sorted by the distance from the current
+    /// joint positions of `arm`, which are not changed.
+    fn solutions_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<Vec<Vec<T>>, IKError>;
+}
+
+/// Remove the duplicated solutions and sort them by the distance from `seed`
+///
+/// The solutions whose all positions are closer than `tolerance` are treated as the same.
+///
+/// # Examples
+///
+/// ```
+/// let solutions = vec![vec![1.0, 1.0], vec![0.1, 0.0], vec![1.0, 1.0001]];
+/// let ranked = k::rank_solutions(solutions, &[0.0, 0.0], 0.001);
+/// assert_eq!(ranked, vec![vec![0.1, 0.0], vec![1.0, 1.0]]);
+/// ```
+pub fn rank_solutions<T>(solutions: Vec<Vec<T>>, seed: &[T], tolerance: T) -> Vec<Vec<T>>
+where
+    T: RealField,
+{
+    let distance = |solution: &Vec<T>| {
+        solution
+            .iter()
+            .zip(seed.iter())
+            .fold(T::zero(), |sum, (a, b)| sum + (*a - *b) * (*a - *b))
+    };
+    let mut ranked: Vec<Vec<T>> = Vec::new();
+    for solution in solutions {
+        let is_duplicated = ranked.iter().any(|other| {
+            other
+                .iter()
+                .zip(solution.iter())
+                .all(|(a, b)| (*a - *b).abs() < tolerance)
+        });
+        if !is_duplicated {
+            ranked.push(solution);
+        }
+    }
+    ranked.sort_by(|a, b| {
+        distance(a)
+            .partial_cmp(&distance(b))
+            .unwrap_or(::std::cmp::Ordering::Equal)
+    });
+    ranked
 }
 
 /// Inverse Kinematics Solver using Jacobian matrix
@@ -128,6 +435,8 @@ pub struct JacobianIKSolver<T: RealField> {
     pub jacobian_multiplier: T,
     /// How many times the joints are tried to be moved
     pub num_max_try: usize,
+    /// Record all the iterations in `IKReport::trace` of `solve_with_report`
+    pub record_trace: bool,
     /// Nullspace function for a redundant system
     nullspace_function: Option<Box<dyn Fn(&[T]) -> Vec<T>>>,
 }
@@ -156,6 +465,7 @@ where
             allowable_target_angle,
             jacobian_multiplier,
             num_max_try,
+            record_trace: false,
             nullspace_function: None,
         }
     }
@@ -193,23 +503,17 @@ where
         &self,
         arm: &SerialChain<T>,
         target_pose: &Isometry3<T>,
-        constraints_array: [bool; 6],
+        constraints: &Constraints<T>,
     ) -> Result<DVector<T>, IKError> {
         let orig_positions = arm.joint_positions();
         let dof = orig_positions.len();
         let t_n = arm.end_transform();
-        let err = calc_pose_diff_with_constraints(target_pose, &t_n, constraints_array);
-        let orig_positions = arm.joint_positions();
-        let mut jacobi = jacobian(arm);
-        let use_dof = constraints_array.into_iter().filter(|x| **x).count();
-        let mut removed_count = 0;
-        for (i, use_i) in constraints_array.iter().enumerate() {
-            if !use_i {
-                jacobi = jacobi.remove_row(i - removed_count);
-                removed_count += 1;
-            }
-        }
-        let positions_vec = if dof > use_dof {
+        let err = calc_pose_diff_with_constraints(target_pose, &t_n, constraints);
+        let jacobi = jacobian_with_constraints(jacobian(arm), &t_n, constraints);
+        let (jacobi, err) = apply_constraints_weights(jacobi, err, constraints);
+        let use_dof = err.len();
+        let has_mimic = arm.iter().any(|node| node.mimic_parent().is_some());
+        let positions_vec = if dof > use_dof || has_mimic {
             const EPS: f64 = 0.0001;
             // redundant: pseudo inverse
             match self.nullspace_function {
@@ -244,61 +548,182 @@ where
         Ok(calc_pose_diff_with_constraints(
             target_pose,
             &arm.end_transform(),
-            constraints_array,
+            constraints,
         ))
     }
 
-    fn solve_with_constraints_internal(
+    fn solve_with_report_internal(
         &self,
         arm: &SerialChain<T>,
         target_pose: &Isometry3<T>,
-        constraints: &Constraints,
-    ) -> Result<(), IKError> {
+        constraints: &Constraints<T>,
+    ) -> Result<IKReport<T>, IKError> {
         let constraints_array = constraints_to_bool_array(*constraints);
         let orig_positions = arm.joint_positions();
         let use_dof = constraints_array.into_iter().filter(|x| **x).count();
-        if orig_positions.len() < use_dof {
+        // mimic joints can not move independently
+        let independent_dof = arm
+            .iter()
+            .filter(|node| node.joint().is_movable() && node.mimic_parent().is_none())
+            .count();
+        if independent_dof < use_dof {
             return Err(IKError::PreconditionError {
                 error: format!(
                     "Input Dof={}, must be greater than {}",
-                    orig_positions.len(),
-                    use_dof
+                    independent_dof, use_dof
                 ),
             });
         }
-        let mut last_target_distance = None;
+        let initial_diff =
+            calc_pose_diff_with_constraints(target_pose, &arm.end_transform(), constraints);
+        let (len_diff, rot_diff) = target_diff_to_len_rot_diff(&initial_diff, constraints);
+        let mut report = IKReport {
+            converged: false,
+            num_iterations: 0,
+            position_error: len_diff.norm(),
+            orientation_error: rot_diff.norm(),
+            joint_positions: orig_positions.clone(),
+            limits_active: false,
+            trace: if self.record_trace {
+                Some(Vec::new())
+            } else {
+                None
+            },
+        };
         for _ in 0..self.num_max_try {
             let target_diff =
-                self.solve_one_loop_with_constraints(&arm, target_pose, constraints_array)?;
-            let (len_diff, rot_diff) = target_diff_to_len_rot_diff(&target_diff, constraints_array);
-            if len_diff.norm() < self.allowable_target_distance
-                && rot_diff.norm() < self.allowable_target_angle
-            {
-                let non_checked_positions = arm.joint_positions();
-                arm.set_joint_positions(&non_checked_positions)?;
-                return Ok(());
+                self.solve_one_loop_with_constraints(&arm, target_pose, constraints)?;
+            let (len_diff, rot_diff) = target_diff_to_len_rot_diff(&target_diff, constraints);
+            report.num_iterations += 1;
+            report.position_error = len_diff.norm();
+            report.orientation_error = rot_diff.norm();
+            report.joint_positions = arm.joint_positions();
+            report.limits_active = report.limits_active || is_limits_active(arm);
+            if let Some(ref mut trace) = report.trace {
+                trace.push(IKTraceStep {
+                    joint_positions: report.joint_positions.clone(),
+                    position_error: report.position_error,
+                    orientation_error: report.orientation_error,
+                });
+            }
+            if is_target_reached(
+                &target_diff,
+                constraints,
+                self.allowable_target_distance,
+                self.allowable_target_angle,
+            ) {
+                arm.set_joint_positions(&report.joint_positions)?;
+                report.converged = true;
+                return Ok(report);
             }
-            last_target_distance = Some((len_diff, rot_diff));
         }
         arm.set_joint_positions(&orig_positions)?;
+        Ok(report)
+    }
+
+    fn solve_goal_internal(&self, arm: &SerialChain<T>, goal: &IKGoal<T>) -> Result<(), IKError> {
+        const EPS: f64 = 0.0001;
+        for _ in 0..self.num_max_try {
+            let orig_positions = arm.joint_positions();
+            let (jacobi, err) = goal.jacobian_and_error(arm);
+            let d_q = match self.nullspace_function {
+                Some(ref f) => {
+                    let dof = orig_positions.len();
+                    let jacobi_inv =
+                        jacobi
+                            .clone()
+                            .pseudo_inverse(na::convert(EPS))
+                            .map_err(|error| IKError::InvalidArgumentsError {
+                                error: error.to_owned(),
+                            })?;
+                    jacobi_inv.clone() * err
+                        + (DMatrix::identity(dof, dof) - jacobi_inv * jacobi)
+                            * DVector::from_vec(f(&orig_positions))
+                }
+                None => jacobi
+                    .svd(true, true)
+                    .solve(&err, na::convert(EPS))
+                    .map_err(|error| IKError::InvalidArgumentsError {
+                        error: error.to_owned(),
+                    })?,
+            };
+            let positions = self.add_positions_with_multiplier(&orig_positions, d_q.as_slice());
+            arm.set_joint_positions_unchecked(&positions);
+            if goal.is_reached(
+                arm,
+                self.allowable_target_distance,
+                self.allowable_target_angle,
+            ) {
+                arm.set_joint_positions(&positions)?;
+                return Ok(());
+            }
+        }
         Err(IKError::NotConvergedError {
             error: format!(
-                "iteration has not converged: tried {} timed, diff = {}, {}",
-                self.num_max_try,
-                last_target_distance.unwrap().0,
-                last_target_distance.unwrap().1,
+                "iteration has not converged for {:?}: tried {} times",
+                goal, self.num_max_try
             ),
         })
     }
+
+    /// Move the end transform of the `arm` to `target_pose` and returns the details
+    ///
+    /// Unlike `solve_with_constraints`, it returns `Ok` even if the iteration has
+    /// not converged. Check `IKReport::converged` in that case. The joint positions
+    /// are restored if it has not converged.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f32>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let mut target = arm.end_transform();
+    /// target.translation.vector.x -= 0.1;
+    ///
+    /// let mut solver = k::JacobianIKSolver::default();
+    /// solver.record_trace = true;
+    /// let report = solver
+    ///     .solve_with_report(&arm, &target, &k::Constraints::default())
+    ///     .unwrap();
+    /// println!("{}", report);
+    /// assert_eq!(report.trace.unwrap().len(), report.num_iterations);
+    /// ```
+    pub fn solve_with_report(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<IKReport<T>, IKError> {
+        let orig_positions = arm.joint_positions();
+        let re = self.solve_with_report_internal(arm, target_pose, constraints);
+        if re.is_err() {
+            arm.set_joint_positions(&orig_positions)?;
+        };
+        re
+    }
+}
+
+/// Check if any joint is at (or over) its limit
+fn is_limits_active<T>(arm: &SerialChain<T>) -> bool
+where
+    T: RealField,
+{
+    arm.iter_joints()
+        .any(|joint| match (joint.limits, joint.joint_position()) {
+            (Some(range), Some(position)) => position <= range.min || position >= range.max,
+            _ => false,
+        })
 }
 
-fn target_diff_to_len_rot_diff<T>(
+pub(crate) fn target_diff_to_len_rot_diff<T>(
     target_diff: &DVector<T>,
-    constraints_array: [bool; 6],
+    constraints: &Constraints<T>,
 ) -> (Vector3<T>, Vector3<T>)
 where
     T: RealField,
 {
+    let constraints_array = constraints_to_bool_array(*constraints);
     let mut len_diff = Vector3::zeros();
     let mut index = 0;
     for i in 0..3 {
@@ -392,10 +817,58 @@ where
         &self,
         arm: &SerialChain<T>,
         target_pose: &Isometry3<T>,
-        constraints: &Constraints,
+        constraints: &Constraints<T>,
     ) -> Result<(), IKError> {
+        let report = self.solve_with_report(arm, target_pose, constraints)?;
+        if report.converged {
+            Ok(())
+        } else {
+            Err(IKError::NotConvergedError {
+                error: format!("iteration has not converged: {}", report),
+            })
+        }
+    }
+
+    /// Same as the inherent `JacobianIKSolver::solve_with_report`
+    fn solve_with_report(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<IKReport<T>, IKError> {
+        JacobianIKSolver::solve_with_report(self, arm, target_pose, constraints)
+    }
+
+    /// Move the end of the `arm` to satisfy the `goal`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::prelude::*;
+    ///
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    ///
+    /// // point the z axis of the wrist at the point
+    /// let goal = k::IKGoal::LookAt {
+    ///     axis: k::Vector3::z_axis(),
+    ///     point: na::Point3::new(0.5, -0.5, 0.5),
+    /// };
+    /// let solver = k::JacobianIKSolver::new(0.001, 0.001, 0.5, 100);
+    /// solver.solve_goal(&arm, &goal).unwrap();
+    /// # extern crate nalgebra as na;
+    /// ```
+    fn solve_goal(&self, arm: &SerialChain<T>, goal: &IKGoal<T>) -> Result<(), IKError> {
+        if let IKGoal::Pose {
+            ref target,
+            ref constraints,
+        } = *goal
+        {
+            return self.solve_with_constraints(arm, target, constraints);
+        }
         let orig_positions = arm.joint_positions();
-        let re = self.solve_with_constraints_internal(arm, target_pose, constraints);
+        let re = self.solve_goal_internal(arm, goal);
         if re.is_err() {
             arm.set_joint_positions(&orig_positions)?;
         };
@@ -413,11 +886,14 @@ where
 }
 
 /// Utility function to create nullspace function using reference joint positions.
-/// This is just an example to use nullspace.
+/// This is just an example to use nullspace. See `nullspace` module for more functions.
 ///
 /// H(q) = 1/2(q-q^)T W (q-q^)
 /// dH(q) / dq = W (q-q^)
 ///
+/// Like the functions in `nullspace` module, it returns the negative gradient
+/// -dH(q) / dq = W (q^-q), which moves the joints to the reference positions.
+///
 /// https://minus9d.hatenablog.com/entry/20120912/1347460308
 pub fn create_reference_positions_nullspace_function<T: RealField>(
     reference_positions: Vec<T>,
@@ -429,7 +905,7 @@ pub fn create_reference_positions_nullspace_function<T: RealField>(
     move |positions| {
         let mut derivative_vec = vec![na::convert(0.0); dof];
         for i in 0..dof {
-            derivative_vec[i] = weight_vector[i] * (positions[i] - reference_positions[i]);
+            derivative_vec[i] = weight_vector[i] * (reference_positions[i] - positions[i]);
         }
         derivative_vec
     }
@@ -441,6 +917,6 @@ fn test_nullspace_func() {
     let pos1 = vec![0.5, 0.5];
     let values = f(&pos1);
     assert_eq!(values.len(), 2);
-    assert_eq!(values[0], 0.25);
-    assert_eq!(values[1], -0.05);
+    assert_eq!(values[0], -0.25);
+    assert_eq!(values[1], 0.05);
 }
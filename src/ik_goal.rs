@@ -0,0 +1,286 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, DVector, Isometry3, Point3, RealField, Unit, Vector3};
+
+use chain::*;
+use funcs::*;
+use ik::*;
+
+/// A goal of the end of an arm for `InverseKinematicsSolver::solve_goal`
+#[derive(Debug, Clone)]
+pub enum IKGoal<T: RealField> {
+    /// Reach the pose with the constraints
+    Pose {
+        target: Isometry3<T>,
+        constraints: Constraints<T>,
+    },
+    /// Reach the position in the world frame, any rotation is allowed
+    Position(Point3<T>),
+    /// Align `axis` of the end (in the end frame) with `direction` in the world frame
+    AxisAlignment {
+        axis: Unit<Vector3<T>>,
+        direction: Unit<Vector3<T>>,
+    },
+    /// Point `axis` of the end (in the end frame) at `point` in the world frame
+    LookAt {
+        axis: Unit<Vector3<T>>,
+        point: Point3<T>,
+    },
+}
+
+impl<T> IKGoal<T>
+where
+    T: RealField,
+{
+    /// Create a full pose goal
+    pub fn pose(target: Isometry3<T>) -> Self {
+        IKGoal::Pose {
+            target,
+            constraints: Constraints::default(),
+        }
+    }
+
+    /// Returns the error of the current `arm` and its Jacobian
+    ///
+    /// Both are in the world frame.
+    pub(crate) fn jacobian_and_error(&self, arm: &SerialChain<T>) -> (DMatrix<T>, DVector<T>) {
+        let end_pose = arm.end_transform();
+        let jacobi = jacobian(arm);
+        match *self {
+            IKGoal::Pose {
+                ref target,
+                ref constraints,
+            } => {
+                let err = calc_pose_diff_with_constraints(target, &end_pose, constraints);
+                let jacobi = jacobian_with_constraints(jacobi, &end_pose, constraints);
+                apply_constraints_weights(jacobi, err, constraints)
+            }
+            IKGoal::Position(ref point) => {
+                let err = point.coords - end_pose.translation.vector;
+                (
+                    jacobi.rows(0, 3).into_owned(),
+                    DVector::from_column_slice(err.as_slice()),
+                )
+            }
+            IKGoal::AxisAlignment {
+                ref axis,
+                ref direction,
+            } => axis_alignment_jacobian_and_error(&jacobi, &end_pose, axis, direction),
+            IKGoal::LookAt {
+                ref axis,
+                ref point,
+            } => look_at_jacobian_and_error(&jacobi, &end_pose, axis, point),
+        }
+    }
+
+    /// Check if the current `arm` satisfies the goal
+    pub(crate) fn is_reached(
+        &self,
+        arm: &SerialChain<T>,
+        allowable_target_distance: T,
+        allowable_target_angle: T,
+    ) -> bool {
+        match *self {
+            IKGoal::Pose {
+                ref target,
+                ref constraints,
+            } => {
+                let diff =
+                    calc_pose_diff_with_constraints(target, &arm.end_transform(), constraints);
+                is_target_reached(
+                    &diff,
+                    constraints,
+                    allowable_target_distance,
+                    allowable_target_angle,
+                )
+            }
+            IKGoal::Position(_) => {
+                self.jacobian_and_error(arm).1.norm() < allowable_target_distance
+            }
+            IKGoal::AxisAlignment { .. } | IKGoal::LookAt { .. } => {
+                self.jacobian_and_error(arm).1.norm() < allowable_target_angle
+            }
+        }
+    }
+}
+
+/// The rotation from `axis` of the end to `direction` and the Jacobian for it
+///
+/// The rotation around the axis is free, so it is removed from the Jacobian.
+fn axis_alignment_jacobian_and_error<T>(
+    jacobi: &DMatrix<T>,
+    end_pose: &Isometry3<T>,
+    axis: &Unit<Vector3<T>>,
+    direction: &Unit<Vector3<T>>,
+) -> (DMatrix<T>, DVector<T>)
+where
+    T: RealField,
+{
+    let current_axis = end_pose.rotation * axis.into_inner();
+    let cross = current_axis.cross(direction);
+    let cos = na::clamp(current_axis.dot(direction), -T::one(), T::one());
+    let angle = cos.acos();
+    let rotation_axis = if cross.norm() > na::convert(1e-9) {
+        cross.normalize()
+    } else if cos > T::zero() {
+        Vector3::zeros()
+    } else {
+        // opposite direction: any perpendicular axis can be used
+        let other = if current_axis.x.abs() < na::convert(0.9) {
+            Vector3::x()
+        } else {
+            Vector3::y()
+        };
+        current_axis.cross(&other).normalize()
+    };
+    let err = rotation_axis * angle;
+    let projection = na::Matrix3::identity() - current_axis * current_axis.transpose();
+    let mut rot_jacobi = DMatrix::zeros(3, jacobi.ncols());
+    for i in 0..jacobi.ncols() {
+        let column = projection * jacobi.fixed_slice::<na::U3, na::U1>(3, i);
+        rot_jacobi
+            .fixed_slice_mut::<na::U3, na::U1>(0, i)
+            .copy_from(&column);
+    }
+    (rot_jacobi, DVector::from_column_slice(err.as_slice()))
+}
+
+/// The rotation from `axis` of the end to the direction to `point` and the Jacobian for it
+///
+/// Unlike `AxisAlignment`, the direction `d = r / |r|` (`r = point - p_end`) changes
+/// by `-(I - d d^T) / |r| * J_v * dq` when the end moves, where `J_v` is the
+/// position rows of the Jacobian.
+fn look_at_jacobian_and_error<T>(
+    jacobi: &DMatrix<T>,
+    end_pose: &Isometry3<T>,
+    axis: &Unit<Vector3<T>>,
+    point: &Point3<T>,
+) -> (DMatrix<T>, DVector<T>)
+where
+    T: RealField,
+{
+    let r = point.coords - end_pose.translation.vector;
+    let distance = r.norm();
+    if distance < na::convert(1e-9) {
+        // the point is at the end: any direction looks at it
+        return (DMatrix::zeros(3, jacobi.ncols()), DVector::zeros(3));
+    }
+    let direction = Unit::new_unchecked(r / distance);
+    let (mut rot_jacobi, err) =
+        axis_alignment_jacobian_and_error(jacobi, end_pose, axis, &direction);
+    // the angular velocity of the direction is d x dd, and the rotation of the direction
+    // decreases the error like the rotation of the end in the opposite direction
+    let direction_jacobi = direction.cross_matrix()
+        * (na::Matrix3::identity() - direction.into_inner() * direction.transpose())
+        / distance;
+    for i in 0..jacobi.ncols() {
+        let column = direction_jacobi * jacobi.fixed_slice::<na::U3, na::U1>(0, i);
+        let mut rot_column = rot_jacobi.fixed_slice_mut::<na::U3, na::U1>(0, i);
+        rot_column += column;
+    }
+    (rot_jacobi, err)
+}
+
+#[test]
+fn test_axis_alignment_error() {
+    use na::{Translation3, UnitQuaternion};
+
+    let end_pose = Isometry3::from_parts(
+        Translation3::new(0.0, 0.0, 0.0),
+        UnitQuaternion::from_axis_angle(&Vector3::y_axis(), 0.5),
+    );
+    let jacobi = DMatrix::<f64>::identity(6, 6);
+    let (rot_jacobi, err) = axis_alignment_jacobian_and_error(
+        &jacobi,
+        &end_pose,
+        &Vector3::z_axis(),
+        &Vector3::z_axis(),
+    );
+    assert!((err[0]).abs() < 1e-9);
+    assert!((err[1] + 0.5).abs() < 1e-9);
+    assert!((err[2]).abs() < 1e-9);
+    // rotation around the current axis does not change the error
+    let current_axis = end_pose.rotation * Vector3::z();
+    let column = rot_jacobi.fixed_slice::<na::U3, na::U6>(0, 0)
+        * na::Vector6::new(
+            0.0,
+            0.0,
+            0.0,
+            current_axis.x,
+            current_axis.y,
+            current_axis.z,
+        );
+    assert!(column.norm() < 1e-9);
+}
+
+#[test]
+fn test_look_at_while_the_end_moves() {
+    use joint::*;
+    use na::Translation3;
+    use node::*;
+
+    // the yaw of the base moves the end a lot
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(0.5, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    end.set_parent(&j1);
+    let arm = SerialChain::<f64>::from_end(&end);
+    let goal = IKGoal::LookAt {
+        axis: Vector3::x_axis(),
+        point: Point3::new(0.5, 1.5, 0.0),
+    };
+    let error_at = |positions: &[f64]| {
+        arm.set_joint_positions(positions).unwrap();
+        goal.jacobian_and_error(&arm).1
+    };
+    // the Jacobian includes the change of the direction by the motion of the end
+    let positions = [0.3, 0.4];
+    let jacobi = {
+        arm.set_joint_positions(&positions).unwrap();
+        goal.jacobian_and_error(&arm).0
+    };
+    let step = 1e-6;
+    for i in 0..2 {
+        let mut moved = positions;
+        moved[i] += step;
+        let numerical = (error_at(&moved) - error_at(&positions)) / step;
+        for j in 0..3 {
+            assert!((numerical[j] + jacobi[(j, i)]).abs() < 1e-4);
+        }
+    }
+
+    arm.set_joint_positions(&[0.0, 0.0]).unwrap();
+    let solver = JacobianIKSolver::new(0.001, 0.0001, 0.5, 100);
+    solver.solve_goal(&arm, &goal).unwrap();
+    let end_pose = arm.end_transform();
+    let direction = (Point3::new(0.5, 1.5, 0.0).coords - end_pose.translation.vector).normalize();
+    assert!(((end_pose.rotation * Vector3::x()).dot(&direction) - 1.0).abs() < 1e-6);
+}
@@ -0,0 +1,301 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, Isometry3, RealField, Translation3, UnitQuaternion, Vector3};
+
+/// Interpolate the rotation on the shortest arc (SLERP)
+fn slerp<T>(start: &UnitQuaternion<T>, end: &UnitQuaternion<T>, t: T) -> UnitQuaternion<T>
+where
+    T: RealField,
+{
+    let relative = start.inverse() * end;
+    start * UnitQuaternion::from_scaled_axis(relative.scaled_axis() * t)
+}
+
+/// Interpolate the translation linearly and the rotation by SLERP
+///
+/// `t` = 0 is `start` and `t` = 1 is `end`.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+///
+/// let start = Isometry3::<f64>::identity();
+/// let end = Isometry3::new(Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+/// let middle = interpolate_linear(&start, &end, 0.5);
+/// assert!((middle.translation.vector.x - 0.5).abs() < 1e-9);
+/// assert!((middle.rotation.angle() - 0.5).abs() < 1e-9);
+/// ```
+pub fn interpolate_linear<T>(start: &Isometry3<T>, end: &Isometry3<T>, t: T) -> Isometry3<T>
+where
+    T: RealField,
+{
+    let translation = start.translation.vector.lerp(&end.translation.vector, t);
+    Isometry3::from_parts(
+        Translation3::from(translation),
+        slerp(&start.rotation, &end.rotation, t),
+    )
+}
+
+/// Twist (scaled rotation axis, translation) of the screw motion from `start` to `end`
+///
+/// Both are in the frame of `start`.
+fn screw_twist<T>(start: &Isometry3<T>, end: &Isometry3<T>) -> (Vector3<T>, Vector3<T>)
+where
+    T: RealField,
+{
+    let relative = start.inverse() * end;
+    let omega = relative.rotation.scaled_axis();
+    let p = relative.translation.vector;
+    let angle = omega.norm();
+    if angle < T::default_epsilon() {
+        return (omega, p);
+    }
+    // inverse of the left Jacobian of SO(3)
+    let half: T = na::convert(0.5);
+    let coefficient =
+        (T::one() - angle * angle.sin() * half / (T::one() - angle.cos())) / (angle * angle);
+    let v = p - omega.cross(&p) * half + omega.cross(&omega.cross(&p)) * coefficient;
+    (omega, v)
+}
+
+/// Interpolate along the screw motion (the geodesic of SE(3))
+///
+/// The frame rotates around a fixed axis and translates along it at the same time
+/// with constant velocities, so the origin of the frame moves on a helix.
+/// `t` = 0 is `start` and `t` = 1 is `end`.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+///
+/// // rotate around the z axis at (0, 1, 0)
+/// let start = Isometry3::<f64>::identity();
+/// let end = Isometry3::new(Vector3::new(1.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.57079632679));
+/// let middle = interpolate_screw(&start, &end, 0.5);
+/// let radius = (middle.translation.vector - Vector3::new(0.0, 1.0, 0.0)).norm();
+/// assert!((radius - 1.0).abs() < 1e-6);
+/// ```
+pub fn interpolate_screw<T>(start: &Isometry3<T>, end: &Isometry3<T>, t: T) -> Isometry3<T>
+where
+    T: RealField,
+{
+    let (omega, v) = screw_twist(start, end);
+    let omega = omega * t;
+    let v = v * t;
+    let angle = omega.norm();
+    let translation = if angle < T::default_epsilon() {
+        v
+    } else {
+        // left Jacobian of SO(3)
+        let c1 = (T::one() - angle.cos()) / (angle * angle);
+        let c2 = (angle - angle.sin()) / (angle * angle * angle);
+        v + omega.cross(&v) * c1 + omega.cross(&omega.cross(&v)) * c2
+    };
+    start
+        * Isometry3::from_parts(
+            Translation3::from(translation),
+            UnitQuaternion::from_scaled_axis(omega),
+        )
+}
+
+/// Generate the poses between two poses with the limits of the step size
+///
+/// The number of the steps is decided by the longer one of the translation and the rotation.
+/// The outputs contain both the start and the end, and can be passed to
+/// `CartesianPathSolver::solve_path` directly.
+pub struct PoseInterpolator<T: RealField> {
+    /// Maximum translation between the adjacent poses
+    pub max_translation_step: T,
+    /// Maximum rotation angle between the adjacent poses
+    pub max_rotation_step: T,
+}
+
+impl<T> PoseInterpolator<T>
+where
+    T: RealField,
+{
+    /// Create instance of `PoseInterpolator`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let interpolator = k::PoseInterpolator::<f64>::new(0.01, 0.05);
+    /// ```
+    pub fn new(max_translation_step: T, max_rotation_step: T) -> Self {
+        Self {
+            max_translation_step,
+            max_rotation_step,
+        }
+    }
+
+    fn num_steps(&self, length: T, angle: T) -> usize {
+        let steps = (length / self.max_translation_step)
+            .max(angle / self.max_rotation_step)
+            .ceil();
+        na::try_convert::<T, f64>(steps)
+            .map(|steps| steps as usize)
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    fn sample<F>(num_steps: usize, pose_at: F) -> Vec<Isometry3<T>>
+    where
+        F: Fn(T) -> Isometry3<T>,
+    {
+        (0..=num_steps)
+            .map(|i| pose_at(na::convert(i as f64 / num_steps as f64)))
+            .collect()
+    }
+
+    /// Straight line of the translation with SLERP of the rotation
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let start = arm.end_transform();
+    /// let mut end = start.clone();
+    /// end.translation.vector.x -= 0.1;
+    ///
+    /// let interpolator = k::PoseInterpolator::new(0.01, 0.05);
+    /// let poses = interpolator.linear(&start, &end);
+    /// assert_eq!(poses.len(), 11);
+    /// let solver = k::CartesianPathSolver::new(k::JacobianIKSolver::default(), 0.1);
+    /// let trajectory = solver
+    ///     .solve_path(&arm, &poses, &k::Constraints::default())
+    ///     .unwrap();
+    /// assert_eq!(trajectory.len(), 11);
+    /// ```
+    pub fn linear(&self, start: &Isometry3<T>, end: &Isometry3<T>) -> Vec<Isometry3<T>> {
+        let length = (end.translation.vector - start.translation.vector).norm();
+        let angle = start.rotation.angle_to(&end.rotation);
+        Self::sample(self.num_steps(length, angle), |t| {
+            interpolate_linear(start, end, t)
+        })
+    }
+
+    /// Screw motion from `start` to `end`
+    ///
+    /// The length of the translation is measured along the helix.
+    pub fn screw(&self, start: &Isometry3<T>, end: &Isometry3<T>) -> Vec<Isometry3<T>> {
+        let (omega, v) = screw_twist(start, end);
+        Self::sample(self.num_steps(v.norm(), omega.norm()), |t| {
+            interpolate_screw(start, end, t)
+        })
+    }
+
+    /// Circular arc from `start` through `via` to `end` with SLERP of the rotation
+    ///
+    /// `via` is the position which the arc passes through. Returns `None` if
+    /// the three positions are on a line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let start = Isometry3::<f64>::translation(1.0, 0.0, 0.0);
+    /// let end = Isometry3::translation(-1.0, 0.0, 0.0);
+    /// let interpolator = PoseInterpolator::new(0.1, 0.1);
+    /// // half circle
+    /// let poses = interpolator.arc(&start, &Vector3::new(0.0, 1.0, 0.0), &end).unwrap();
+    /// assert_eq!(poses.len(), 33);
+    /// assert!(poses.iter().all(|pose| (pose.translation.vector.norm() - 1.0).abs() < 1e-9));
+    /// assert!(interpolator.arc(&start, &Vector3::zeros(), &end).is_none());
+    /// ```
+    pub fn arc(
+        &self,
+        start: &Isometry3<T>,
+        via: &Vector3<T>,
+        end: &Isometry3<T>,
+    ) -> Option<Vec<Isometry3<T>>> {
+        let p0 = start.translation.vector;
+        let u = via - p0;
+        let w = end.translation.vector - p0;
+        let normal = u.cross(&w);
+        let normal_squared = normal.norm_squared();
+        if normal_squared < T::default_epsilon() {
+            return None;
+        }
+        let two: T = na::convert(2.0);
+        let center = p0
+            + (w.cross(&normal) * u.norm_squared() + normal.cross(&u) * w.norm_squared())
+                / (two * normal_squared);
+        let radius = (p0 - center).norm();
+        let e1 = (p0 - center) / radius;
+        let e2 = normal.normalize().cross(&e1);
+        let to_end = end.translation.vector - center;
+        let mut arc_angle = e2.dot(&to_end).atan2(e1.dot(&to_end));
+        if arc_angle < T::zero() {
+            arc_angle += T::two_pi();
+        }
+        let angle = start.rotation.angle_to(&end.rotation);
+        Some(Self::sample(
+            self.num_steps(radius * arc_angle, angle),
+            |t| {
+                let theta = arc_angle * t;
+                Isometry3::from_parts(
+                    Translation3::from(center + (e1 * theta.cos() + e2 * theta.sin()) * radius),
+                    slerp(&start.rotation, &end.rotation, t),
+                )
+            },
+        ))
+    }
+}
+
+#[test]
+fn test_pose_interpolation_steps() {
+    let start = Isometry3::new(Vector3::new(0.1, 0.2, 0.3), Vector3::new(0.0, 0.5, 0.0));
+    let end = Isometry3::new(Vector3::new(0.5, -0.2, 0.4), Vector3::new(1.0, 0.0, 0.5));
+    let interpolator = PoseInterpolator::new(0.02, 0.05);
+    for poses in &[
+        interpolator.linear(&start, &end),
+        interpolator.screw(&start, &end),
+    ] {
+        let first = poses[0];
+        let last = poses[poses.len() - 1];
+        assert!((first.to_homogeneous() - start.to_homogeneous()).norm() < 1e-9);
+        assert!((last.to_homogeneous() - end.to_homogeneous()).norm() < 1e-9);
+        for pair in poses.windows(2) {
+            let translation = (pair[1].translation.vector - pair[0].translation.vector).norm();
+            assert!(translation <= 0.02 + 1e-9);
+            assert!(pair[0].rotation.angle_to(&pair[1].rotation) <= 0.05 + 1e-9);
+        }
+    }
+    // the screw motion of a pure rotation does not move the points on the axis
+    let rotation = Isometry3::rotation_wrt_point(
+        UnitQuaternion::from_scaled_axis(Vector3::new(0.0, 0.0, 2.0)),
+        na::Point3::new(1.0, 0.0, 0.0),
+    );
+    for t in &[0.25, 0.5, 0.75] {
+        let pose = interpolate_screw(&Isometry3::identity(), &rotation, *t);
+        let on_axis = pose * na::Point3::new(1.0, 0.0, 0.5);
+        assert!((on_axis.coords - Vector3::new(1.0, 0.0, 0.5)).norm() < 1e-9);
+    }
+    // the arc passes through the via point
+    let poses = interpolator
+        .arc(&start, &Vector3::new(0.3, 0.3, 0.0), &end)
+        .unwrap();
+    let last = poses[poses.len() - 1];
+    assert!((last.translation.vector - end.translation.vector).norm() < 1e-9);
+    assert!(poses
+        .iter()
+        .any(|pose| (pose.translation.vector - Vector3::new(0.3, 0.3, 0.0)).norm() < 0.02));
+}
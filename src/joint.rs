@@ -219,6 +219,10 @@ pub struct Joint<T: RealField> {
     velocity: T,
     /// Limits of this joint
     pub limits: Option<Range<T>>,
+    /// Limit of the absolute velocity of this joint
+    pub velocity_limit: Option<T>,
+    /// Limit of the absolute acceleration of this joint
+    pub acceleration_limit: Option<T>,
     /// local origin transform of joint
     origin: Isometry3<T>,
     /// cache of world transform
@@ -255,6 +259,8 @@ where
             position: T::zero(),
             velocity: T::zero(),
             limits: None,
+            velocity_limit: None,
+            acceleration_limit: None,
             origin: Isometry3::identity(),
             world_transform_cache: RefCell::new(None),
             world_velocity_cache: RefCell::new(None),
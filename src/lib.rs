@@ -28,27 +28,65 @@ extern crate failure;
 #[macro_use]
 extern crate log;
 extern crate nalgebra as na;
+extern crate rand;
 extern crate urdf_rs;
 
+mod analytical_ik;
+mod arm_angle;
+mod balance_ik;
+mod cartesian_path;
 mod chain;
+mod closed_loop;
+mod collision;
+mod differential_ik;
 mod errors;
 mod funcs;
+mod heuristic_ik;
 mod ik;
+mod ik_goal;
+mod interpolation;
+mod multi_ik;
+mod optimization_ik;
+mod parallel;
+mod planner;
+mod random_ik;
+mod retiming;
+mod trajectory;
+mod validator;
 
 pub mod iterator;
 pub mod joint;
 pub mod link;
 pub mod node;
+pub mod nullspace;
 pub mod prelude;
 pub mod urdf;
 
+pub use self::analytical_ik::*;
+pub use self::arm_angle::*;
+pub use self::balance_ik::*;
+pub use self::cartesian_path::*;
 pub use self::chain::*;
+pub use self::closed_loop::*;
+pub use self::collision::*;
+pub use self::differential_ik::*;
 pub use self::errors::*;
 pub use self::funcs::*;
+pub use self::heuristic_ik::*;
 pub use self::ik::*;
+pub use self::ik_goal::*;
+pub use self::interpolation::*;
 pub use self::joint::{Joint, JointType};
 pub use self::link::Link;
+pub use self::multi_ik::*;
 pub use self::node::{JointBuilder, Node};
+pub use self::optimization_ik::*;
+pub use self::parallel::*;
+pub use self::planner::*;
+pub use self::random_ik::*;
+pub use self::retiming::*;
+pub use self::trajectory::*;
+pub use self::validator::*;
 
 // re-export from nalgebra
 // include Real for backwards compatibility purposes
@@ -0,0 +1,621 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, DVector, Isometry3, RealField};
+
+use chain::*;
+use errors::*;
+use funcs::*;
+use ik::*;
+use node::*;
+
+/// A target pose of a node in a `Chain`
+#[derive(Debug, Clone)]
+pub struct IKTarget<T: RealField> {
+    /// The node which should reach `target_pose`
+    pub end: Node<T>,
+    /// Target pose in the world frame
+    pub target_pose: Isometry3<T>,
+    /// Constraints for this target
+    pub constraints: Constraints<T>,
+}
+
+impl<T> IKTarget<T>
+where
+    T: RealField,
+{
+    /// Create a target with all constraints
+    pub fn new(end: Node<T>, target_pose: Isometry3<T>) -> Self {
+        Self::with_constraints(end, target_pose, Constraints::default())
+    }
+    /// Create a target with constraints
+    pub fn with_constraints(
+        end: Node<T>,
+        target_pose: Isometry3<T>,
+        constraints: Constraints<T>,
+    ) -> Self {
+        Self {
+            end,
+            target_pose,
+            constraints,
+        }
+    }
+    /// Returns true if the current pose is within the tolerances
+    ///
+    /// Call `Chain::update_transforms()` before using this method.
+    pub(crate) fn is_reached(
+        &self,
+        allowable_target_distance: T,
+        allowable_target_angle: T,
+    ) -> bool {
+        let diff = calc_pose_diff_with_constraints(
+            &self.target_pose,
+            &self.end.world_transform().expect("cache must exist"),
+            &self.constraints,
+        );
+        is_target_reached(
+            &diff,
+            &self.constraints,
+            allowable_target_distance,
+            allowable_target_angle,
+        )
+    }
+    /// Returns the weighted Jacobian and the error for the constrained coordinates
+    pub(crate) fn jacobian_and_error(&self, chain: &Chain<T>) -> (DMatrix<T>, DVector<T>) {
+        let end_pose = self.end.world_transform().expect("cache must exist");
+        let jacobi = jacobian_with_constraints(
+            jacobian_for_node(chain, &self.end),
+            &end_pose,
+            &self.constraints,
+        );
+        let err = calc_pose_diff_with_constraints(&self.target_pose, &end_pose, &self.constraints);
+        apply_constraints_weights(jacobi, err, &self.constraints)
+    }
+}
+
+/// Stack the Jacobians and the errors of all the targets vertically
+pub(crate) fn stack_jacobians_and_errors<T>(
+    chain: &Chain<T>,
+    targets: &[IKTarget<T>],
+) -> (DMatrix<T>, DVector<T>)
+where
+    T: RealField,
+{
+    let parts = targets
+        .iter()
+        .map(|target| target.jacobian_and_error(chain))
+        .collect::<Vec<_>>();
+    let rows = parts.iter().map(|(_, err)| err.len()).sum();
+    let mut jacobi = DMatrix::zeros(rows, chain.dof());
+    let mut err = DVector::zeros(rows);
+    let mut index = 0;
+    for (j, e) in parts {
+        let num = e.len();
+        jacobi.rows_mut(index, num).copy_from(&j);
+        err.rows_mut(index, num).copy_from(&e);
+        index += num;
+    }
+    (jacobi, err)
+}
+
+/// Inverse Kinematics Solver for multiple end effectors in a branched `Chain`
+///
+/// All the targets are solved at the same time, so the joints which are
+/// shared by the targets (for example a torso) are moved to satisfy all of them.
+pub struct MultiTargetIKSolver<T: RealField> {
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, it is reached.
+    pub allowable_target_angle: T,
+    /// multiplier for jacobian
+    pub jacobian_multiplier: T,
+    /// How many times the joints are tried to be moved
+    pub num_max_try: usize,
+}
+
+impl<T> MultiTargetIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `MultiTargetIKSolver`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::MultiTargetIKSolver::new(0.01, 0.01, 0.5, 100);
+    /// ```
+    pub fn new(
+        allowable_target_distance: T,
+        allowable_target_angle: T,
+        jacobian_multiplier: T,
+        num_max_try: usize,
+    ) -> Self {
+        Self {
+            allowable_target_distance,
+            allowable_target_angle,
+            jacobian_multiplier,
+            num_max_try,
+        }
+    }
+
+    fn is_reached(&self, targets: &[IKTarget<T>]) -> bool {
+        targets.iter().all(|target| {
+            target.is_reached(self.allowable_target_distance, self.allowable_target_angle)
+        })
+    }
+
+    fn solve_internal(&self, chain: &Chain<T>, targets: &[IKTarget<T>]) -> Result<(), IKError> {
+        const EPS: f64 = 0.0001;
+        for _ in 0..self.num_max_try {
+            let (jacobi, err) = stack_jacobians_and_errors(chain, targets);
+            let d_q = jacobi
+                .svd(true, true)
+                .solve(&err, na::convert(EPS))
+                .map_err(|error| IKError::InvalidArgumentsError {
+                    error: error.to_owned(),
+                })?;
+            let positions = chain
+                .joint_positions()
+                .iter()
+                .zip(d_q.iter())
+                .map(|(position, d)| *position + self.jacobian_multiplier * *d)
+                .collect::<Vec<_>>();
+            chain.set_joint_positions_unchecked(&positions);
+            chain.update_transforms();
+            if self.is_reached(targets) {
+                let non_checked_positions = chain.joint_positions();
+                chain.set_joint_positions(&non_checked_positions)?;
+                return Ok(());
+            }
+        }
+        Err(IKError::NotConvergedError {
+            error: format!(
+                "iteration has not converged for {} targets: tried {} times",
+                targets.len(),
+                self.num_max_try
+            ),
+        })
+    }
+
+    /// Move all the `end`s of the `targets` to their `target_pose`s
+    ///
+    /// The joint positions of `chain` are restored if it fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f32>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// chain
+    ///     .set_joint_positions(&[0.2, 0.2, 0.0, -1.0, 0.0, 0.0, 0.2, 0.2, 0.0, -1.0, 0.0, 0.0])
+    ///     .unwrap();
+    /// chain.update_transforms();
+    /// let r_wrist = chain.find("r_wrist_pitch").unwrap();
+    /// let l_wrist = chain.find("l_wrist_pitch").unwrap();
+    /// let mut r_target = r_wrist.world_transform().unwrap();
+    /// let mut l_target = l_wrist.world_transform().unwrap();
+    /// r_target.translation.vector.z += 0.05;
+    /// l_target.translation.vector.z += 0.05;
+    ///
+    /// let mut constraints = k::Constraints::default();
+    /// constraints.rotation_x = false;
+    /// let targets = vec![
+    ///     k::IKTarget::with_constraints(r_wrist.clone(), r_target, constraints),
+    ///     k::IKTarget::with_constraints(l_wrist.clone(), l_target, constraints),
+    /// ];
+    /// let solver = k::MultiTargetIKSolver::default();
+    /// solver.solve(&chain, &targets).unwrap();
+    /// ```
+    pub fn solve(&self, chain: &Chain<T>, targets: &[IKTarget<T>]) -> Result<(), IKError> {
+        let orig_positions = chain.joint_positions();
+        let re = self.solve_internal(chain, targets);
+        if re.is_err() {
+            chain.set_joint_positions(&orig_positions)?;
+            chain.update_transforms();
+        }
+        re
+    }
+}
+
+impl<T> Default for MultiTargetIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(
+            na::convert(0.001),
+            na::convert(0.005),
+            na::convert(0.5),
+            100,
+        )
+    }
+}
+
+/// Calculate the joint displacement for the prioritized tasks
+///
+/// Each task is a pair of its Jacobian and its error. The lower priority tasks
+/// are solved in the nullspace of the higher priority tasks, and `nullspace_vec`
+/// is projected to the nullspace of all the tasks.
+pub(crate) fn prioritized_displacement<T>(
+    tasks: &[(DMatrix<T>, DVector<T>)],
+    dof: usize,
+    nullspace_vec: Option<DVector<T>>,
+) -> Result<DVector<T>, IKError>
+where
+    T: RealField,
+{
+    const EPS: f64 = 0.0001;
+    let mut d_q = DVector::zeros(dof);
+    let mut projector = DMatrix::identity(dof, dof);
+    for (jacobi, err) in tasks {
+        let projected = jacobi * &projector;
+        let projected_inv =
+            projected
+                .clone()
+                .pseudo_inverse(na::convert(EPS))
+                .map_err(|error| IKError::InvalidArgumentsError {
+                    error: error.to_owned(),
+                })?;
+        d_q += &projected_inv * (err - jacobi * &d_q);
+        projector -= projected_inv * projected;
+    }
+    if let Some(z) = nullspace_vec {
+        d_q += projector * z;
+    }
+    Ok(d_q)
+}
+
+/// Task-priority Inverse Kinematics Solver
+///
+/// The targets are grouped into priority levels. The targets of a lower level
+/// are solved only in the nullspace of the higher levels, so they never disturb
+/// the higher priority targets. For example, the levels for a humanoid can be
+/// `[feet, hands]`.
+pub struct HierarchicalIKSolver<T: RealField> {
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, it is reached.
+    pub allowable_target_angle: T,
+    /// multiplier for jacobian
+    pub jacobian_multiplier: T,
+    /// How many times the joints are tried to be moved
+    pub num_max_try: usize,
+    /// Nullspace function used as the lowest priority task
+    nullspace_function: Option<Box<dyn Fn(&[T]) -> Vec<T>>>,
+}
+
+impl<T> HierarchicalIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `HierarchicalIKSolver`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::HierarchicalIKSolver::new(0.01, 0.01, 0.5, 100);
+    /// ```
+    pub fn new(
+        allowable_target_distance: T,
+        allowable_target_angle: T,
+        jacobian_multiplier: T,
+        num_max_try: usize,
+    ) -> Self {
+        Self {
+            allowable_target_distance,
+            allowable_target_angle,
+            jacobian_multiplier,
+            num_max_try,
+            nullspace_function: None,
+        }
+    }
+    /// Set a null space function, which is used in the nullspace of all the levels.
+    ///
+    /// The input and the output are in the order of `chain.joint_positions()`.
+    pub fn set_nullspace_function(&mut self, func: Box<dyn Fn(&[T]) -> Vec<T>>) {
+        self.nullspace_function = Some(func);
+    }
+
+    /// Clear the null function which is set by `set_nullspace_funtion`.
+    pub fn clear_nullspace_function(&mut self) {
+        self.nullspace_function = None;
+    }
+
+    fn is_reached(&self, targets: &[IKTarget<T>]) -> bool {
+        targets.iter().all(|target| {
+            target.is_reached(self.allowable_target_distance, self.allowable_target_angle)
+        })
+    }
+
+    fn solve_internal(
+        &self,
+        chain: &Chain<T>,
+        levels: &[Vec<IKTarget<T>>],
+    ) -> Result<Vec<usize>, IKError> {
+        chain.update_transforms();
+        for _ in 0..self.num_max_try {
+            let positions = chain.joint_positions();
+            let tasks = levels
+                .iter()
+                .map(|targets| stack_jacobians_and_errors(chain, targets))
+                .collect::<Vec<_>>();
+            let nullspace_vec = self
+                .nullspace_function
+                .as_ref()
+                .map(|f| DVector::from_vec(f(&positions)));
+            let d_q = prioritized_displacement(&tasks, chain.dof(), nullspace_vec)?;
+            let positions = positions
+                .iter()
+                .zip(d_q.iter())
+                .map(|(position, d)| *position + self.jacobian_multiplier * *d)
+                .collect::<Vec<_>>();
+            chain.set_joint_positions_unchecked(&positions);
+            chain.update_transforms();
+            if levels.iter().all(|targets| self.is_reached(targets)) {
+                break;
+            }
+        }
+        if let Some(targets) = levels.first() {
+            if !self.is_reached(targets) {
+                return Err(IKError::NotConvergedError {
+                    error: format!(
+                        "iteration has not converged: tried {} times, level 0 is not reached",
+                        self.num_max_try
+                    ),
+                });
+            }
+        }
+        let non_checked_positions = chain.joint_positions();
+        chain.set_joint_positions(&non_checked_positions)?;
+        chain.update_transforms();
+        Ok(levels
+            .iter()
+            .enumerate()
+            .filter(|(_, targets)| !self.is_reached(targets))
+            .map(|(i, _)| i)
+            .collect())
+    }
+
+    /// Move the nodes to the targets of the priority levels
+    ///
+    /// `levels[0]` has the highest priority and it must be reached. The lower levels
+    /// are best-effort: the indices of the levels which are not reached are returned.
+    /// The joint positions of `chain` are restored if `levels[0]` is not reached.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let chain = Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// chain.set_joint_positions(&vec![0.2; chain.dof()]).unwrap();
+    /// chain.update_transforms();
+    /// let r_wrist = chain.find("r_wrist_pitch").unwrap();
+    /// let l_wrist = chain.find("l_wrist_pitch").unwrap();
+    /// let mut r_target = r_wrist.world_transform().unwrap();
+    /// r_target.translation.vector.z += 0.05;
+    /// let mut l_target = l_wrist.world_transform().unwrap();
+    /// l_target.translation.vector.x += 0.05;
+    /// let solver = HierarchicalIKSolver::default();
+    /// let missed = solver
+    ///     .solve(
+    ///         &chain,
+    ///         &[
+    ///             vec![IKTarget::new(r_wrist.clone(), r_target)],
+    ///             vec![IKTarget::new(l_wrist.clone(), l_target)],
+    ///         ],
+    ///     )
+    ///     .unwrap();
+    /// assert!(missed.is_empty());
+    /// ```
+    pub fn solve(
+        &self,
+        chain: &Chain<T>,
+        levels: &[Vec<IKTarget<T>>],
+    ) -> Result<Vec<usize>, IKError> {
+        let orig_positions = chain.joint_positions();
+        let re = self.solve_internal(chain, levels);
+        if re.is_err() {
+            chain.set_joint_positions(&orig_positions)?;
+            chain.update_transforms();
+        }
+        re
+    }
+}
+
+impl<T> Default for HierarchicalIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(
+            na::convert(0.001),
+            na::convert(0.005),
+            na::convert(0.5),
+            100,
+        )
+    }
+}
+
+#[test]
+fn test_prioritized_displacement() {
+    // two conflicting tasks on the same joint: the first one wins
+    let high = (
+        DMatrix::<f64>::from_row_slice(1, 2, &[1.0, 0.0]),
+        DVector::from_vec(vec![1.0]),
+    );
+    let low = (
+        DMatrix::from_row_slice(1, 2, &[1.0, 1.0]),
+        DVector::from_vec(vec![-1.0]),
+    );
+    let d_q = prioritized_displacement(&[high, low], 2, None).unwrap();
+    assert!((d_q[0] - 1.0).abs() < 1e-6);
+    assert!((d_q[1] + 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn test_multi_target_with_shared_joint() {
+    use joint::*;
+    use na::{Translation3, Vector3};
+
+    let torso = JointBuilder::new()
+        .name("torso_yaw")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let create_arm = |prefix: &str, y: f64| {
+        let shoulder = JointBuilder::new()
+            .name(&format!("{}_shoulder_pitch", prefix))
+            .translation(Translation3::new(0.0, y, 0.5))
+            .joint_type(JointType::Rotational {
+                axis: Vector3::y_axis(),
+            })
+            .into_node();
+        let roll = JointBuilder::new()
+            .name(&format!("{}_shoulder_roll", prefix))
+            .joint_type(JointType::Rotational {
+                axis: Vector3::x_axis(),
+            })
+            .into_node();
+        let elbow = JointBuilder::new()
+            .name(&format!("{}_elbow_pitch", prefix))
+            .translation(Translation3::new(0.0, 0.0, -0.3))
+            .joint_type(JointType::Rotational {
+                axis: Vector3::y_axis(),
+            })
+            .into_node();
+        let hand = JointBuilder::new()
+            .name(&format!("{}_hand", prefix))
+            .translation(Translation3::new(0.0, 0.0, -0.3))
+            .into_node();
+        shoulder.set_parent(&torso);
+        roll.set_parent(&shoulder);
+        elbow.set_parent(&roll);
+        hand.set_parent(&elbow);
+        hand
+    };
+    let r_hand = create_arm("r", -0.2);
+    let l_hand = create_arm("l", 0.2);
+    let chain = Chain::from_root(torso);
+    assert_eq!(chain.dof(), 7);
+
+    let names = chain
+        .iter_joints()
+        .map(|j| j.name.clone())
+        .collect::<Vec<_>>();
+    let answer = names
+        .iter()
+        .map(|name| match name.as_str() {
+            "torso_yaw" => 0.3,
+            "r_shoulder_pitch" | "l_shoulder_pitch" => -0.4,
+            "r_elbow_pitch" | "l_elbow_pitch" => -0.8,
+            _ => 0.1,
+        })
+        .collect::<Vec<_>>();
+    chain.set_joint_positions(&answer).unwrap();
+    chain.update_transforms();
+    let constraints = Constraints {
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    let targets = vec![
+        IKTarget::with_constraints(
+            r_hand.clone(),
+            r_hand.world_transform().unwrap(),
+            constraints,
+        ),
+        IKTarget::with_constraints(
+            l_hand.clone(),
+            l_hand.world_transform().unwrap(),
+            constraints,
+        ),
+    ];
+    chain
+        .set_joint_positions(&[0.0, -0.2, 0.0, -0.5, -0.2, 0.0, -0.5])
+        .unwrap();
+    let solver = MultiTargetIKSolver::new(0.0001, 0.001, 0.5, 200);
+    solver.solve(&chain, &targets).unwrap();
+    chain.update_transforms();
+    for target in &targets {
+        let diff = target.end.world_transform().unwrap().translation.vector
+            - target.target_pose.translation.vector;
+        assert!(diff.norm() < 0.0001);
+    }
+
+    // the transforms are restored with the positions after the failure
+    let hand = r_hand.world_transform().unwrap();
+    let unreachable = vec![IKTarget::with_constraints(
+        r_hand.clone(),
+        Isometry3::translation(2.0, 0.0, 0.0),
+        constraints,
+    )];
+    assert!(solver.solve(&chain, &unreachable).is_err());
+    let restored = r_hand.world_transform().unwrap();
+    assert!((restored.translation.vector - hand.translation.vector).norm() < 1e-9);
+}
+
+#[test]
+fn test_hierarchical_conflicting_levels() {
+    use joint::*;
+    use na::{Translation3, Vector3};
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let hand = JointBuilder::new()
+        .name("hand")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    hand.set_parent(&j1);
+    let chain = Chain::<f64>::from_root(j0);
+    let constraints = Constraints {
+        position_z: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    let target = |node: &Node<f64>, x: f64, y: f64| {
+        IKTarget::with_constraints(node.clone(), Isometry3::translation(x, y, 0.0), constraints)
+    };
+    // the elbow can not be at (0, 1) while the hand is at (1.2, 0.8)
+    let levels = vec![vec![target(&hand, 1.2, 0.8)], vec![target(&j1, 0.0, 1.0)]];
+    chain.set_joint_positions(&[0.3, 0.5]).unwrap();
+    let solver = HierarchicalIKSolver::new(0.0001, 0.001, 0.5, 200);
+    assert_eq!(solver.solve(&chain, &levels).unwrap(), vec![1]);
+    let hand_position = hand.world_transform().unwrap().translation.vector;
+    assert!((hand_position - Vector3::new(1.2, 0.8, 0.0)).norm() < 0.0001);
+
+    // the highest level is out of reach
+    let levels = vec![vec![target(&hand, 3.0, 0.0)], vec![target(&j1, 0.0, 1.0)]];
+    let positions = chain.joint_positions();
+    assert!(solver.solve(&chain, &levels).is_err());
+    assert_eq!(chain.joint_positions(), positions);
+    let restored = hand.world_transform().unwrap().translation.vector;
+    assert!((restored - hand_position).norm() < 1e-9);
+}
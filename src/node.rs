@@ -197,20 +197,36 @@ where
     /// assert!(j0.set_joint_position(1.0).is_ok());
     /// assert_eq!(j0.joint_position().unwrap(), 1.0);
     /// assert_eq!(j1.joint_position().unwrap(), 1.6);
+    /// // the position of the mimic joint is determined by j0, so it is ignored
+    /// assert!(j1.set_joint_position(0.5).is_ok());
+    /// assert_eq!(j1.joint_position().unwrap(), 1.6);
+    /// // mimic of a mimic joint
+    /// let j2 = JointBuilder::new()
+    ///     .joint_type(JointType::Linear{axis: Vector3::z_axis()})
+    ///     .into_node();
+    /// j2.set_mimic_parent(&j1, k::joint::Mimic::new(0.5, 0.0));
+    /// assert!(j0.set_joint_position(0.2).is_ok());
+    /// assert!((j2.joint_position().unwrap() - 0.2f64).abs() < 1e-9);
+    /// j0.set_joint_position_unchecked(1.0);
+    /// j2.set_joint_position_unchecked(0.0);
+    /// assert!((j2.joint_position().unwrap() - 0.8f64).abs() < 1e-9);
     /// ```
     pub fn set_joint_position(&self, position: T) -> Result<(), JointError> {
-        let mut node = self.0.borrow_mut();
-        if node.mimic_parent.is_some() {
+        if self.0.borrow().mimic_parent.is_some() {
             return Ok(());
         }
-        node.joint.set_joint_position(position)?;
-        for child in &node.mimic_children {
-            let mut child_node = child.0.borrow_mut();
-            let mimic = child_node.mimic.clone();
-            match mimic {
-                Some(m) => child_node
-                    .joint
-                    .set_joint_position(m.mimic_position(position))?,
+        self.set_joint_position_with_mimic_children(position)
+    }
+
+    /// Set the position and propagate it to the mimic children recursively
+    fn set_joint_position_with_mimic_children(&self, position: T) -> Result<(), JointError> {
+        self.0.borrow_mut().joint.set_joint_position(position)?;
+        let mimic_children = self.0.borrow().mimic_children.clone();
+        for child in &mimic_children {
+            match child.mimic() {
+                Some(m) => {
+                    child.set_joint_position_with_mimic_children(m.mimic_position(position))?
+                }
                 None => {
                     let from = self.joint().name.to_owned();
                     let to = child.joint().name.to_owned();
@@ -229,12 +245,28 @@ where
         }
         Ok(())
     }
-    #[inline]
+
+    /// Set the position of the joint and its mimic children without check
+    ///
+    /// It is ignored for a mimic joint like `set_joint_position`.
     pub fn set_joint_position_unchecked(&self, position: T) {
+        if self.0.borrow().mimic_parent.is_some() {
+            return;
+        }
+        self.set_joint_position_unchecked_with_mimic_children(position);
+    }
+
+    fn set_joint_position_unchecked_with_mimic_children(&self, position: T) {
         self.0
             .borrow_mut()
             .joint
             .set_joint_position_unchecked(position);
+        let mimic_children = self.0.borrow().mimic_children.clone();
+        for child in &mimic_children {
+            if let Some(m) = child.mimic() {
+                child.set_joint_position_unchecked_with_mimic_children(m.mimic_position(position));
+            }
+        }
     }
 
     pub(crate) fn parent_world_transform(&self) -> Option<Isometry3<T>> {
@@ -295,6 +327,36 @@ where
         self.0.borrow_mut().mimic = Some(mimic);
     }
 
+    /// The driving joint of this mimic joint
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    /// let j0 = JointBuilder::<f64>::new()
+    ///     .name("j0")
+    ///     .joint_type(JointType::Linear{axis: Vector3::z_axis()})
+    ///     .into_node();
+    /// let j1 = JointBuilder::new()
+    ///     .joint_type(JointType::Linear{axis: Vector3::z_axis()})
+    ///     .into_node();
+    /// assert!(j1.mimic_parent().is_none());
+    /// j1.set_mimic_parent(&j0, k::joint::Mimic::new(1.5, 0.1));
+    /// assert_eq!(j1.mimic_parent().unwrap().joint().name, "j0");
+    /// assert_eq!(j1.mimic().unwrap().multiplier, 1.5);
+    /// ```
+    pub fn mimic_parent(&self) -> Option<Node<T>> {
+        match self.0.borrow().mimic_parent {
+            Some(ref weak) => weak.upgrade().map(Node::from_rc),
+            None => None,
+        }
+    }
+
+    /// The relation to the driving joint if this is a mimic joint
+    pub fn mimic(&self) -> Option<Mimic<T>> {
+        self.0.borrow().mimic.clone()
+    }
+
     pub fn set_link(&self, link: Option<Link<T>>) {
         self.0.borrow_mut().link = link;
     }
@@ -475,6 +537,8 @@ pub struct JointBuilder<T: RealField> {
     name: String,
     joint_type: JointType<T>,
     limits: Option<Range<T>>,
+    velocity_limit: Option<T>,
+    acceleration_limit: Option<T>,
     origin: Isometry3<T>,
 }
 
@@ -496,6 +560,8 @@ where
             name: "".to_string(),
             joint_type: JointType::Fixed,
             limits: None,
+            velocity_limit: None,
+            acceleration_limit: None,
             origin: Isometry3::identity(),
         }
     }
@@ -514,6 +580,16 @@ where
         self.limits = limits;
         self
     }
+    /// Set the limit of the absolute velocity
+    pub fn velocity_limit(mut self, velocity_limit: Option<T>) -> JointBuilder<T> {
+        self.velocity_limit = velocity_limit;
+        self
+    }
+    /// Set the limit of the absolute acceleration
+    pub fn acceleration_limit(mut self, acceleration_limit: Option<T>) -> JointBuilder<T> {
+        self.acceleration_limit = acceleration_limit;
+        self
+    }
     /// Set the origin transform of this joint
     pub fn origin(mut self, origin: Isometry3<T>) -> JointBuilder<T> {
         self.origin = origin;
@@ -534,6 +610,8 @@ where
         let mut joint = Joint::new(&self.name, self.joint_type);
         joint.set_origin(self.origin);
         joint.limits = self.limits;
+        joint.velocity_limit = self.velocity_limit;
+        joint.acceleration_limit = self.acceleration_limit;
         joint
     }
     /// Create `Node` instead of `Joint` as output
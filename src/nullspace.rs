@@ -0,0 +1,469 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+//! Nullspace functions for `JacobianIKSolver::set_nullspace_function`
+//!
+//! All the functions return the joint displacement which decreases the cost
+//! (the negative gradient of the cost), scaled by the weight. It is added to the
+//! joint positions through the nullspace projection in `JacobianIKSolver`.
+//! `create_reference_positions_nullspace_function` has the same sign, so it can be
+//! combined with them by `combine_nullspace_functions`.
+//! The functions which use the state of the arm share the nodes with the `arm`,
+//! and the joint positions of the `arm` are restored after they are called.
+use na::{self, DVector, RealField, Vector3};
+
+use chain::*;
+use funcs::*;
+use node::*;
+
+/// Copy of the `SerialChain` which shares the nodes
+fn share_serial_chain<T>(arm: &SerialChain<T>) -> SerialChain<T>
+where
+    T: RealField,
+{
+    SerialChain::new_unchecked(Chain::from_nodes(arm.iter().cloned().collect()))
+}
+
+/// Keep the joints close to the centers of their limits
+///
+/// H(q) = 1/2 sum(((q - q_center) / (q_range / 2))^2)
+///
+/// # Examples
+///
+/// ```
+/// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+/// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+/// let mut solver = k::JacobianIKSolver::new(0.01, 0.01, 0.5, 100);
+/// solver.set_nullspace_function(Box::new(
+///     k::nullspace::create_joint_limit_avoidance_nullspace_function(&arm, 0.1),
+/// ));
+/// ```
+pub fn create_joint_limit_avoidance_nullspace_function<T>(
+    arm: &SerialChain<T>,
+    weight: T,
+) -> impl Fn(&[T]) -> Vec<T>
+where
+    T: RealField,
+{
+    let limits = arm
+        .iter_joints()
+        .map(|joint| joint.limits)
+        .collect::<Vec<_>>();
+    move |positions| {
+        let two: T = na::convert(2.0);
+        positions
+            .iter()
+            .zip(limits.iter())
+            .map(|(position, limit)| match *limit {
+                Some(ref range) if range.max > range.min => {
+                    let half_range = (range.max - range.min) / two;
+                    let center = (range.max + range.min) / two;
+                    -weight * (*position - center) / (half_range * half_range)
+                }
+                _ => T::zero(),
+            })
+            .collect()
+    }
+}
+
+/// Increase the manipulability to keep away from the singular configurations
+///
+/// H(q) = -manipulability(q). The gradient is calculated numerically.
+///
+/// # Examples
+///
+/// ```
+/// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+/// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+/// let mut solver = k::JacobianIKSolver::new(0.01, 0.01, 0.5, 100);
+/// solver.set_nullspace_function(Box::new(
+///     k::nullspace::create_manipulability_nullspace_function(&arm, 0.1),
+/// ));
+/// ```
+pub fn create_manipulability_nullspace_function<T>(
+    arm: &SerialChain<T>,
+    weight: T,
+) -> impl Fn(&[T]) -> Vec<T>
+where
+    T: RealField,
+{
+    let arm = share_serial_chain(arm);
+    move |positions| {
+        const STEP: f64 = 1e-5;
+        let step: T = na::convert(STEP);
+        let orig_positions = arm.joint_positions();
+        let mut moved = positions.to_vec();
+        let gradient = (0..positions.len())
+            .map(|i| {
+                moved[i] = positions[i] + step;
+                arm.set_joint_positions_unchecked(&moved);
+                let plus = manipulability(&arm);
+                moved[i] = positions[i] - step;
+                arm.set_joint_positions_unchecked(&moved);
+                let minus = manipulability(&arm);
+                moved[i] = positions[i];
+                weight * (plus - minus) / (step + step)
+            })
+            .collect();
+        arm.set_joint_positions_unchecked(&orig_positions);
+        arm.update_transforms();
+        gradient
+    }
+}
+
+/// Move the `elbow` node close to `preferred_position` in the world frame
+///
+/// H(q) = 1/2 |p_elbow(q) - p_preferred|^2. `elbow` must be contained in `arm`.
+///
+/// # Examples
+///
+/// ```
+/// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+/// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+/// let elbow = chain.find("r_elbow_pitch").unwrap();
+/// let mut solver = k::JacobianIKSolver::new(0.01, 0.01, 0.5, 100);
+/// solver.set_nullspace_function(Box::new(
+///     k::nullspace::create_elbow_position_nullspace_function(
+///         &arm,
+///         elbow,
+///         k::Vector3::new(0.0, -1.0, 0.0),
+///         0.1,
+///     ),
+/// ));
+/// ```
+pub fn create_elbow_position_nullspace_function<T>(
+    arm: &SerialChain<T>,
+    elbow: &Node<T>,
+    preferred_position: Vector3<T>,
+    weight: T,
+) -> impl Fn(&[T]) -> Vec<T>
+where
+    T: RealField,
+{
+    let arm = share_serial_chain(arm);
+    let elbow = elbow.clone();
+    move |positions| {
+        let orig_positions = arm.joint_positions();
+        arm.set_joint_positions_unchecked(positions);
+        let jacobi = jacobian_for_node(&arm, &elbow);
+        let diff = elbow
+            .world_transform()
+            .expect("cache must exist")
+            .translation
+            .vector
+            - preferred_position;
+        let d_q = jacobi.rows(0, 3).transpose() * diff * -weight;
+        arm.set_joint_positions_unchecked(&orig_positions);
+        arm.update_transforms();
+        d_q.as_slice().to_vec()
+    }
+}
+
+/// Keep the joints of the arm away from the obstacle points in the world frame
+///
+/// H(q) = 1/2 sum((influence_distance - d)^2) for the distances d between the
+/// origins of the joints and the obstacles which are closer than `influence_distance`.
+///
+/// # Examples
+///
+/// ```
+/// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+/// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+/// let mut solver = k::JacobianIKSolver::new(0.01, 0.01, 0.5, 100);
+/// solver.set_nullspace_function(Box::new(
+///     k::nullspace::create_obstacle_avoidance_nullspace_function(
+///         &arm,
+///         vec![k::Vector3::new(0.3, -0.3, 0.3)],
+///         0.2,
+///         1.0,
+///     ),
+/// ));
+/// ```
+pub fn create_obstacle_avoidance_nullspace_function<T>(
+    arm: &SerialChain<T>,
+    obstacles: Vec<Vector3<T>>,
+    influence_distance: T,
+    weight: T,
+) -> impl Fn(&[T]) -> Vec<T>
+where
+    T: RealField,
+{
+    let arm = share_serial_chain(arm);
+    move |positions| {
+        let orig_positions = arm.joint_positions();
+        arm.set_joint_positions_unchecked(positions);
+        arm.update_transforms();
+        let mut d_q = DVector::zeros(positions.len());
+        for node in arm.iter() {
+            let point = node
+                .world_transform()
+                .expect("cache must exist")
+                .translation
+                .vector;
+            for obstacle in &obstacles {
+                let diff = point - obstacle;
+                let distance = diff.norm();
+                if distance < influence_distance && distance > T::default_epsilon() {
+                    let jacobi = jacobian_for_node(&arm, node);
+                    d_q += jacobi.rows(0, 3).transpose()
+                        * (diff * (weight * (influence_distance - distance) / distance));
+                }
+            }
+        }
+        arm.set_joint_positions_unchecked(&orig_positions);
+        arm.update_transforms();
+        d_q.as_slice().to_vec()
+    }
+}
+
+/// Sum up the nullspace functions with the weights
+///
+/// # Examples
+///
+/// ```
+/// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+/// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+/// let mut solver = k::JacobianIKSolver::new(0.01, 0.01, 0.5, 100);
+/// solver.set_nullspace_function(Box::new(k::nullspace::combine_nullspace_functions(vec![
+///     (
+///         1.0,
+///         Box::new(k::nullspace::create_joint_limit_avoidance_nullspace_function(&arm, 0.1)),
+///     ),
+///     (
+///         0.5,
+///         Box::new(k::nullspace::create_manipulability_nullspace_function(&arm, 0.1)),
+///     ),
+/// ])));
+/// ```
+#[allow(clippy::type_complexity)]
+pub fn combine_nullspace_functions<T>(
+    functions: Vec<(T, Box<dyn Fn(&[T]) -> Vec<T>>)>,
+) -> impl Fn(&[T]) -> Vec<T>
+where
+    T: RealField,
+{
+    move |positions| {
+        let mut sum = vec![T::zero(); positions.len()];
+        for (weight, function) in &functions {
+            for (total, value) in sum.iter_mut().zip(function(positions).iter()) {
+                *total += *weight * *value;
+            }
+        }
+        sum
+    }
+}
+
+#[test]
+fn test_joint_limit_avoidance_nullspace_function() {
+    use joint::*;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .limits(Some((0.0..=2.0).into()))
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    j1.set_parent(&j0);
+    let arm = SerialChain::<f64>::from_end(&j1);
+    let f = create_joint_limit_avoidance_nullspace_function(&arm, 0.5);
+    let values = f(&[1.5, 3.0]);
+    // move to the center
+    assert!((values[0] + 0.25).abs() < 1e-9);
+    // no limits
+    assert_eq!(values[1], 0.0);
+    let combined = combine_nullspace_functions(vec![(2.0, Box::new(f))]);
+    assert!((combined(&[1.5, 3.0])[0] + 0.5).abs() < 1e-9);
+}
+
+#[test]
+fn test_nullspace_function_gradients() {
+    use joint::*;
+    use na::Translation3;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    end.set_parent(&j1);
+    let arm = SerialChain::<f64>::from_end(&end);
+    let current = [0.1, 0.2];
+    arm.set_joint_positions(&current).unwrap();
+    arm.update_transforms();
+    let end_position = end.world_transform().unwrap().translation.vector;
+
+    let position_of = |node: &Node<f64>| node.world_transform().unwrap().translation.vector;
+    // returns -weight * (numerical gradient of the cost)
+    let expected = |cost: &dyn Fn() -> f64, positions: &[f64], weight: f64| {
+        let step = 1e-6;
+        (0..2)
+            .map(|i| {
+                let mut moved = positions.to_vec();
+                moved[i] += step;
+                arm.set_joint_positions(&moved).unwrap();
+                arm.update_transforms();
+                let plus = cost();
+                moved[i] -= step + step;
+                arm.set_joint_positions(&moved).unwrap();
+                arm.update_transforms();
+                let minus = cost();
+                -weight * (plus - minus) / (step + step)
+            })
+            .collect::<Vec<_>>()
+    };
+    let preferred = Vector3::new(0.0, 1.0, 0.0);
+    let obstacles = vec![Vector3::new(0.9, 0.5, 0.0), Vector3::new(1.5, 0.9, 0.0)];
+    let manipulability_cost = || -manipulability(&arm);
+    let elbow_cost = || 0.5 * (position_of(&j1) - preferred).norm_squared();
+    let obstacle_cost = || {
+        arm.iter()
+            .flat_map(|node| {
+                let point = position_of(node);
+                obstacles
+                    .iter()
+                    .map(move |obstacle| (point - obstacle).norm())
+                    .collect::<Vec<_>>()
+            })
+            .filter(|distance| *distance < 0.8)
+            .map(|distance| 0.5 * (0.8 - distance) * (0.8 - distance))
+            .sum::<f64>()
+    };
+    let probe = [0.4, 0.9];
+    let check = |function: &dyn Fn(&[f64]) -> Vec<f64>, cost: &dyn Fn() -> f64| {
+        arm.set_joint_positions(&current).unwrap();
+        arm.update_transforms();
+        let values = function(&probe);
+        // the positions and the transforms of the arm are restored
+        assert_eq!(arm.joint_positions(), current.to_vec());
+        assert!((position_of(&end) - end_position).norm() < 1e-9);
+        let expected_values = expected(cost, &probe, 0.5);
+        assert!(expected_values.iter().any(|value| value.abs() > 1e-3));
+        for (value, expected_value) in values.iter().zip(expected_values.iter()) {
+            assert!((value - expected_value).abs() < 1e-4);
+        }
+    };
+    check(
+        &create_manipulability_nullspace_function(&arm, 0.5),
+        &manipulability_cost,
+    );
+    check(
+        &create_elbow_position_nullspace_function(&arm, &j1, preferred, 0.5),
+        &elbow_cost,
+    );
+    check(
+        &create_obstacle_avoidance_nullspace_function(&arm, obstacles.clone(), 0.8, 0.5),
+        &obstacle_cost,
+    );
+}
+
+#[test]
+fn test_combine_with_reference_positions_nullspace_function() {
+    use ik::*;
+    use joint::*;
+    use na::{Isometry3, Translation3};
+
+    // planar arm, which is redundant for the position in the plane
+    let nodes = (0..3)
+        .map(|i| {
+            JointBuilder::new()
+                .name(&format!("j{}", i))
+                .translation(Translation3::new(if i == 0 { 0.0 } else { 0.5 }, 0.0, 0.0))
+                .joint_type(JointType::Rotational {
+                    axis: Vector3::z_axis(),
+                })
+                .limits(Some((-2.0..=2.0).into()))
+                .into_node()
+        })
+        .collect::<Vec<Node<f64>>>();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(0.5, 0.0, 0.0))
+        .into_node();
+    nodes[1].set_parent(&nodes[0]);
+    nodes[2].set_parent(&nodes[1]);
+    end.set_parent(&nodes[2]);
+    let arm = SerialChain::from_end(&end);
+    let reference_positions = vec![0.0; 3];
+    let create_combined = || {
+        combine_nullspace_functions(vec![
+            (
+                1.0,
+                Box::new(create_reference_positions_nullspace_function(
+                    reference_positions.clone(),
+                    vec![2.0; 3],
+                )) as Box<dyn Fn(&[f64]) -> Vec<f64>>,
+            ),
+            (
+                1.0,
+                Box::new(create_joint_limit_avoidance_nullspace_function(&arm, 2.0)),
+            ),
+        ])
+    };
+    // both move the joints to zero, which is the reference and the center of the limits
+    let positions = [0.5, -0.5, 1.0];
+    let combined = create_combined();
+    for (value, position) in combined(&positions).iter().zip(positions.iter()) {
+        assert!(value * position < 0.0);
+    }
+
+    let constraints = Constraints {
+        position_z: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Default::default()
+    };
+    let target = Isometry3::translation(1.0, 0.6, 0.0);
+    let initial = [0.6, 0.6, 0.6];
+    let mut solver = JacobianIKSolver::new(0.001, 0.001, 0.5, 100);
+    arm.set_joint_positions(&initial).unwrap();
+    solver
+        .solve_with_constraints(&arm, &target, &constraints)
+        .unwrap();
+    let without = arm.joint_positions();
+    solver.set_nullspace_function(Box::new(create_combined()));
+    arm.set_joint_positions(&initial).unwrap();
+    solver
+        .solve_with_constraints(&arm, &target, &constraints)
+        .unwrap();
+    let with = arm.joint_positions();
+    arm.update_transforms();
+    let end_position = end.world_transform().unwrap().translation.vector;
+    assert!((end_position.x - 1.0).abs() < 0.001);
+    assert!((end_position.y - 0.6).abs() < 0.001);
+    // closer to the reference positions
+    let norm = |positions: &[f64]| positions.iter().map(|p| p * p).sum::<f64>();
+    assert!(norm(&with) < norm(&without));
+}
@@ -0,0 +1,580 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, DVector, Isometry3, RealField};
+
+use chain::*;
+use collision::*;
+use errors::*;
+use funcs::*;
+use ik::*;
+use joint::Range;
+
+/// A term of the objective function of `OptimizationIKSolver`
+///
+/// Closures `Fn(&SerialChain<T>) -> T` can also be used as a cost.
+pub trait IKCost<T>
+where
+    T: RealField,
+{
+    /// Returns the cost of the current joint positions of `arm`. Smaller is better.
+    ///
+    /// The transforms of `arm` are updated before this is called.
+    fn cost(&self, arm: &SerialChain<T>) -> T;
+}
+
+impl<T, F> IKCost<T> for F
+where
+    T: RealField,
+    F: Fn(&SerialChain<T>) -> T,
+{
+    fn cost(&self, arm: &SerialChain<T>) -> T {
+        self(arm)
+    }
+}
+
+/// Cost to keep the joints away from their limits
+///
+/// weight * (margin - d)^2 for the joints whose distance d from the limit is smaller than margin.
+#[derive(Debug, Clone)]
+pub struct JointLimitCost<T: RealField> {
+    /// Weight of the cost
+    pub weight: T,
+    /// The cost is zero if the distance from the limits is larger than this
+    pub margin: T,
+}
+
+impl<T> JointLimitCost<T>
+where
+    T: RealField,
+{
+    /// Create a cost to keep the distances from the limits larger than `margin`
+    pub fn new(weight: T, margin: T) -> Self {
+        Self { weight, margin }
+    }
+}
+
+impl<T> IKCost<T> for JointLimitCost<T>
+where
+    T: RealField,
+{
+    fn cost(&self, arm: &SerialChain<T>) -> T {
+        arm.iter_joints()
+            .filter_map(|joint| match (joint.limits, joint.joint_position()) {
+                (Some(range), Some(position)) => {
+                    let distance = (position - range.min).min(range.max - position);
+                    if distance < self.margin {
+                        Some((self.margin - distance) * (self.margin - distance))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .fold(T::zero(), |sum, cost| sum + cost)
+            * self.weight
+    }
+}
+
+/// Cost to keep the joints close to the reference positions
+///
+/// 1/2 (q - q_ref)^T W (q - q_ref), like `create_reference_positions_nullspace_function`.
+#[derive(Debug, Clone)]
+pub struct PostureCost<T: RealField> {
+    /// Preferred joint positions
+    pub reference_positions: Vec<T>,
+    /// Weights for each joint
+    pub weights: Vec<T>,
+}
+
+impl<T> PostureCost<T>
+where
+    T: RealField,
+{
+    /// Create a cost with the reference positions and the weights for each joint
+    pub fn new(reference_positions: Vec<T>, weights: Vec<T>) -> Self {
+        assert_eq!(reference_positions.len(), weights.len());
+        Self {
+            reference_positions,
+            weights,
+        }
+    }
+}
+
+impl<T> IKCost<T> for PostureCost<T>
+where
+    T: RealField,
+{
+    fn cost(&self, arm: &SerialChain<T>) -> T {
+        let half: T = na::convert(0.5);
+        arm.joint_positions()
+            .iter()
+            .zip(self.reference_positions.iter().zip(self.weights.iter()))
+            .fold(T::zero(), |sum, (position, (reference, weight))| {
+                sum + half * *weight * (*position - *reference) * (*position - *reference)
+            })
+    }
+}
+
+/// Cost to keep the arm away from the singular configurations
+///
+/// -weight * manipulability
+#[derive(Debug, Clone)]
+pub struct ManipulabilityCost<T: RealField> {
+    /// Weight of the cost
+    pub weight: T,
+}
+
+impl<T> ManipulabilityCost<T>
+where
+    T: RealField,
+{
+    /// Create a cost with the weight
+    pub fn new(weight: T) -> Self {
+        Self { weight }
+    }
+}
+
+impl<T> IKCost<T> for ManipulabilityCost<T>
+where
+    T: RealField,
+{
+    fn cost(&self, arm: &SerialChain<T>) -> T {
+        -self.weight * manipulability(arm)
+    }
+}
+
+/// Cost to keep the collision geometries of the links away from the obstacles
+///
+/// weight * (margin - d)^2 for the pairs of a link and an obstacle whose distance d
+/// (by `obstacle_distances`) is smaller than margin. `Geometry::Mesh` is ignored.
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+/// use k::link::*;
+///
+/// let j0 = JointBuilder::new()
+///     .name("j0")
+///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+///     .into_node();
+/// let end = JointBuilder::new()
+///     .name("end")
+///     .translation(Translation3::new(1.0, 0.0, 0.0))
+///     .into_node();
+/// end.set_parent(&j0);
+/// end.set_link(Some(LinkBuilder::new()
+///     .name("hand")
+///     .add_collision(Collision::new(
+///         "ball".to_owned(),
+///         Isometry3::translation(1.0, 0.0, 0.0),
+///         Geometry::Sphere { radius: 0.1 },
+///     ))
+///     .finalize()));
+/// let arm = SerialChain::<f64>::from_end(&end);
+/// let wall = Obstacle::new(
+///     "wall",
+///     Geometry::Box { depth: 0.1, width: 2.0, height: 2.0 },
+///     Isometry3::translation(1.3, 0.0, 0.0),
+/// );
+/// let cost = ObstacleDistanceCost::new(vec![wall], 1.0, 0.2);
+/// // the distance is 0.15
+/// assert!((cost.cost(&arm) - 0.05 * 0.05).abs() < 1e-6);
+/// arm.set_joint_positions(&[1.0]).unwrap();
+/// assert_eq!(cost.cost(&arm), 0.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObstacleDistanceCost<T: RealField> {
+    /// Obstacles in the world frame
+    pub obstacles: Vec<Obstacle<T>>,
+    /// Weight of the cost
+    pub weight: T,
+    /// The cost is zero if the distance from the obstacles is larger than this
+    pub margin: T,
+}
+
+impl<T> ObstacleDistanceCost<T>
+where
+    T: RealField,
+{
+    /// Create a cost to keep the distances from `obstacles` larger than `margin`
+    pub fn new(obstacles: Vec<Obstacle<T>>, weight: T, margin: T) -> Self {
+        Self {
+            obstacles,
+            weight,
+            margin,
+        }
+    }
+}
+
+impl<T> IKCost<T> for ObstacleDistanceCost<T>
+where
+    T: RealField,
+{
+    fn cost(&self, arm: &SerialChain<T>) -> T {
+        obstacle_distances(arm, &self.obstacles)
+            .iter()
+            .map(|distance| distance.result.distance)
+            .filter(|distance| *distance < self.margin)
+            .fold(T::zero(), |sum, distance| {
+                sum + (self.margin - distance) * (self.margin - distance)
+            })
+            * self.weight
+    }
+}
+
+/// Inverse Kinematics Solver which minimizes the pose error and the additional costs
+///
+/// The objective is 1/2 |W e|^2 + sum of the costs, where e is the constrained
+/// pose error and W is `Constraints::weights`. It is minimized by BFGS
+/// (quasi-Newton method) with the Armijo line search and numerical gradients.
+/// The joint positions are clamped within the limits.
+pub struct OptimizationIKSolver<T: RealField> {
+    /// If the distance is smaller than this value, it is reached.
+    pub allowable_target_distance: T,
+    /// If the angle distance is smaller than this value, it is reached.
+    pub allowable_target_angle: T,
+    /// How many times the joints are tried to be moved
+    pub num_max_try: usize,
+    /// The optimization stops if the norm of the gradient is smaller than this.
+    pub gradient_tolerance: T,
+    /// Step of the joint positions to calculate the numerical gradient
+    pub finite_difference_step: T,
+    /// Additional costs
+    costs: Vec<Box<dyn IKCost<T>>>,
+}
+
+impl<T> OptimizationIKSolver<T>
+where
+    T: RealField,
+{
+    /// Create instance of `OptimizationIKSolver` without additional costs
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::OptimizationIKSolver::new(0.001, 0.005, 100);
+    /// ```
+    pub fn new(
+        allowable_target_distance: T,
+        allowable_target_angle: T,
+        num_max_try: usize,
+    ) -> Self {
+        Self {
+            allowable_target_distance,
+            allowable_target_angle,
+            num_max_try,
+            gradient_tolerance: na::convert(1e-9),
+            finite_difference_step: na::convert(1e-5),
+            costs: Vec::new(),
+        }
+    }
+
+    /// Add a cost term to the objective
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut solver = k::OptimizationIKSolver::new(0.001, 0.005, 100);
+    /// solver.add_cost(Box::new(k::JointLimitCost::new(1.0, 0.1)));
+    /// solver.add_cost(Box::new(k::PostureCost::new(vec![0.0; 6], vec![0.01; 6])));
+    /// // any closure can be used as a cost
+    /// solver.add_cost(Box::new(|arm: &k::SerialChain<f64>| {
+    ///     let height = arm.end_transform().translation.vector.z;
+    ///     if height < 0.1 { (0.1 - height) * 10.0 } else { 0.0 }
+    /// }));
+    /// ```
+    pub fn add_cost(&mut self, cost: Box<dyn IKCost<T>>) {
+        self.costs.push(cost);
+    }
+
+    /// Clear the costs which are added by `add_cost`
+    pub fn clear_costs(&mut self) {
+        self.costs.clear();
+    }
+
+    fn objective(
+        &self,
+        arm: &SerialChain<T>,
+        positions: &[T],
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> T {
+        arm.set_joint_positions_unchecked(positions);
+        arm.update_transforms();
+        let half: T = na::convert(0.5);
+        let pose_cost = constraints_to_bool_array(*constraints)
+            .iter()
+            .zip(constraints.weights.iter())
+            .filter(|(use_i, _)| **use_i)
+            .zip(
+                calc_pose_diff_with_constraints(target_pose, &arm.end_transform(), constraints)
+                    .iter(),
+            )
+            .fold(T::zero(), |sum, ((_, weight), diff)| {
+                let weighted = *diff * *weight;
+                sum + half * weighted * weighted
+            });
+        self.costs
+            .iter()
+            .fold(pose_cost, |sum, cost| sum + cost.cost(arm))
+    }
+
+    fn gradient(
+        &self,
+        arm: &SerialChain<T>,
+        positions: &DVector<T>,
+        limits: &[Option<Range<T>>],
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> DVector<T> {
+        let two: T = na::convert(2.0);
+        let h = self.finite_difference_step;
+        DVector::from_fn(positions.len(), |i, _| {
+            let mut plus = positions.clone();
+            plus[i] += h;
+            let mut minus = positions.clone();
+            minus[i] -= h;
+            let f_plus = self.objective(
+                arm,
+                &clamp_positions(&plus, limits),
+                target_pose,
+                constraints,
+            );
+            let f_minus = self.objective(
+                arm,
+                &clamp_positions(&minus, limits),
+                target_pose,
+                constraints,
+            );
+            (f_plus - f_minus) / (two * h)
+        })
+    }
+
+    fn is_reached(
+        &self,
+        arm: &SerialChain<T>,
+        positions: &[T],
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> bool {
+        arm.set_joint_positions_unchecked(positions);
+        let diff = calc_pose_diff_with_constraints(target_pose, &arm.end_transform(), constraints);
+        is_target_reached(
+            &diff,
+            constraints,
+            self.allowable_target_distance,
+            self.allowable_target_angle,
+        )
+    }
+
+    fn solve_internal(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        const ARMIJO_COEFFICIENT: f64 = 1e-4;
+        const MIN_STEP_SIZE: f64 = 1e-10;
+        let limits = arm
+            .iter_joints()
+            .map(|joint| joint.limits)
+            .collect::<Vec<_>>();
+        let dof = limits.len();
+        let mut q = DVector::from_vec(clamp_positions(
+            &DVector::from_vec(arm.joint_positions()),
+            &limits,
+        ));
+        let mut f = self.objective(arm, q.as_slice(), target_pose, constraints);
+        let mut g = self.gradient(arm, &q, &limits, target_pose, constraints);
+        // approximation of the inverse of the Hessian
+        let mut h_inv = DMatrix::<T>::identity(dof, dof);
+        for _ in 0..self.num_max_try {
+            if self.costs.is_empty() && self.is_reached(arm, q.as_slice(), target_pose, constraints)
+            {
+                break;
+            }
+            if g.norm() < self.gradient_tolerance {
+                break;
+            }
+            let mut direction = -(&h_inv * &g);
+            if direction.dot(&g) >= T::zero() {
+                // not a descent direction, reset to the steepest descent
+                h_inv = DMatrix::identity(dof, dof);
+                direction = -g.clone();
+            }
+            let slope = direction.dot(&g);
+            let mut alpha = T::one();
+            let (q_next, f_next) = loop {
+                let candidate =
+                    DVector::from_vec(clamp_positions(&(&q + &direction * alpha), &limits));
+                let f_candidate =
+                    self.objective(arm, candidate.as_slice(), target_pose, constraints);
+                if f_candidate <= f + na::convert::<f64, T>(ARMIJO_COEFFICIENT) * alpha * slope
+                    || alpha < na::convert(MIN_STEP_SIZE)
+                {
+                    break (candidate, f_candidate);
+                }
+                alpha *= na::convert(0.5);
+            };
+            let g_next = self.gradient(arm, &q_next, &limits, target_pose, constraints);
+            let s = &q_next - &q;
+            let y = &g_next - &g;
+            let sy = s.dot(&y);
+            if sy > na::convert(1e-12) {
+                let rho = T::one() / sy;
+                let identity = DMatrix::<T>::identity(dof, dof);
+                let left = &identity - &s * y.transpose() * rho;
+                let right = &identity - &y * s.transpose() * rho;
+                h_inv = left * h_inv * right + &s * s.transpose() * rho;
+            }
+            let is_stopped = s.norm() < na::convert(MIN_STEP_SIZE);
+            q = q_next;
+            f = f_next;
+            g = g_next;
+            if is_stopped {
+                break;
+            }
+        }
+        if self.is_reached(arm, q.as_slice(), target_pose, constraints) {
+            arm.set_joint_positions(q.as_slice())?;
+            Ok(())
+        } else {
+            Err(IKError::NotConvergedError {
+                error: format!("optimization has not converged: cost = {}", f),
+            })
+        }
+    }
+}
+
+/// Clamp the positions within the limits
+fn clamp_positions<T>(positions: &DVector<T>, limits: &[Option<Range<T>>]) -> Vec<T>
+where
+    T: RealField,
+{
+    positions
+        .iter()
+        .zip(limits.iter())
+        .map(|(position, limit)| match *limit {
+            Some(ref range) => na::clamp(*position, range.min, range.max),
+            None => *position,
+        })
+        .collect()
+}
+
+impl<T> InverseKinematicsSolver<T> for OptimizationIKSolver<T>
+where
+    T: RealField,
+{
+    /// Minimize the objective and set joint positions of `arm`
+    ///
+    /// It fails if the pose error is not within the tolerances at the optimum.
+    /// The joint positions of `arm` are restored if it fails.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::prelude::*;
+    ///
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let mut target = arm.end_transform();
+    /// target.translation.vector.x -= 0.05;
+    ///
+    /// let solver = k::OptimizationIKSolver::new(0.001, 0.005, 100);
+    /// solver.solve(&arm, &target).unwrap();
+    /// ```
+    fn solve_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        let orig_positions = arm.joint_positions();
+        let re = self.solve_internal(arm, target_pose, constraints);
+        if re.is_err() {
+            arm.set_joint_positions(&orig_positions)?;
+        };
+        re
+    }
+}
+
+impl<T> Default for OptimizationIKSolver<T>
+where
+    T: RealField,
+{
+    fn default() -> Self {
+        Self::new(na::convert(0.001), na::convert(0.005), 100)
+    }
+}
+
+#[test]
+fn test_optimization_ik_with_posture_cost() {
+    use joint::*;
+    use na::{Translation3, Vector3};
+    use node::*;
+
+    // redundant planar arm with 3 joints for 2D position
+    let nodes = (0..3)
+        .map(|i| {
+            JointBuilder::<f64>::new()
+                .name(&format!("j{}", i))
+                .translation(Translation3::new(if i == 0 { 0.0 } else { 1.0 }, 0.0, 0.0))
+                .joint_type(JointType::Rotational {
+                    axis: Vector3::z_axis(),
+                })
+                .into_node()
+        })
+        .collect::<Vec<_>>();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    nodes[1].set_parent(&nodes[0]);
+    nodes[2].set_parent(&nodes[1]);
+    end.set_parent(&nodes[2]);
+    let arm = SerialChain::from_end(&end);
+    arm.set_joint_positions(&[0.1, 0.2, 0.3]).unwrap();
+    let mut target = arm.end_transform();
+    target.translation.vector.x -= 0.3;
+    let constraints = Constraints {
+        position_z: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    let mut solver = OptimizationIKSolver::new(0.001, 0.001, 200);
+    solver.add_cost(Box::new(PostureCost::new(
+        vec![0.0, 0.0, 1.0],
+        vec![1e-4; 3],
+    )));
+    solver
+        .solve_with_constraints(&arm, &target, &constraints)
+        .unwrap();
+    let diff = arm.end_transform().translation.vector - target.translation.vector;
+    assert!(diff.norm() < 0.001);
+    // the redundancy is used to get closer to the reference posture
+    let positions = arm.joint_positions();
+    let without_cost = OptimizationIKSolver::new(0.001, 0.001, 200);
+    arm.set_joint_positions(&[0.1, 0.2, 0.3]).unwrap();
+    without_cost
+        .solve_with_constraints(&arm, &target, &constraints)
+        .unwrap();
+    let posture_error = |q: &[f64]| q[0].powi(2) + q[1].powi(2) + (q[2] - 1.0).powi(2);
+    assert!(posture_error(&positions) < posture_error(&arm.joint_positions()));
+}
@@ -0,0 +1,724 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{
+    self, DMatrix, DVector, Isometry3, RealField, Translation3, Unit, UnitQuaternion, Vector3,
+};
+
+use chain::*;
+use errors::*;
+use joint::*;
+use node::*;
+
+/// Check the positions of the actuators with their limits
+///
+/// All the positions are checked before any joint is moved, so the mechanism
+/// is not changed if it fails.
+fn check_actuator_limits<T>(actuators: &[Node<T>], positions: &[T]) -> Result<(), IKError>
+where
+    T: RealField,
+{
+    for (actuator, position) in actuators.iter().zip(positions.iter()) {
+        let joint = actuator.joint();
+        if let Some(range) = joint.limits {
+            if !range.is_valid(*position) {
+                return Err(JointError::OutOfLimitError {
+                    joint_name: joint.name.to_string(),
+                    message: format!(
+                        "Joint is out of range: input={}, range={:?}",
+                        position, range
+                    ),
+                }
+                .into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn set_positions<T>(nodes: &[Node<T>], positions: &[T]) -> Result<(), IKError>
+where
+    T: RealField,
+{
+    for (node, position) in nodes.iter().zip(positions.iter()) {
+        node.set_joint_position(*position)?;
+    }
+    Ok(())
+}
+
+fn actuator_positions<T>(actuators: &[Node<T>]) -> Vec<T>
+where
+    T: RealField,
+{
+    actuators
+        .iter()
+        .map(|actuator| actuator.joint_position().expect("actuator must be movable"))
+        .collect()
+}
+
+fn fixed_node<T>(name: &str, translation: Vector3<T>) -> Node<T>
+where
+    T: RealField,
+{
+    JointBuilder::new()
+        .name(name)
+        .translation(Translation3::from(translation))
+        .into_node()
+}
+
+fn passive_node<T>(name: &str, axis: Unit<Vector3<T>>, translation: Vector3<T>) -> Node<T>
+where
+    T: RealField,
+{
+    JointBuilder::new()
+        .name(name)
+        .translation(Translation3::from(translation))
+        .joint_type(JointType::Rotational { axis })
+        .into_node()
+}
+
+/// Create the universal joint (z and y axes) as two connected nodes
+fn universal_joint<T>(name: &str, translation: Vector3<T>) -> [Node<T>; 2]
+where
+    T: RealField,
+{
+    let joint = [
+        passive_node(&format!("{}_z", name), Vector3::z_axis(), translation),
+        passive_node(&format!("{}_y", name), Vector3::y_axis(), Vector3::zeros()),
+    ];
+    joint[1].set_parent(&joint[0]);
+    joint
+}
+
+/// Create the spherical joint (z, y and x axes) as three connected nodes
+fn spherical_joint<T>(name: &str) -> [Node<T>; 3]
+where
+    T: RealField,
+{
+    let joint = [
+        passive_node(&format!("{}_z", name), Vector3::z_axis(), Vector3::zeros()),
+        passive_node(&format!("{}_y", name), Vector3::y_axis(), Vector3::zeros()),
+        passive_node(&format!("{}_x", name), Vector3::x_axis(), Vector3::zeros()),
+    ];
+    joint[1].set_parent(&joint[0]);
+    joint[2].set_parent(&joint[1]);
+    joint
+}
+
+/// Positions of the universal joint which turn the x axis to `direction`
+fn universal_joint_positions<T>(direction: &Vector3<T>) -> [T; 2]
+where
+    T: RealField,
+{
+    [
+        direction.y.atan2(direction.x),
+        (-direction.z).atan2((direction.x * direction.x + direction.y * direction.y).sqrt()),
+    ]
+}
+
+fn universal_joint_rotation<T>(positions: &[T; 2]) -> UnitQuaternion<T>
+where
+    T: RealField,
+{
+    UnitQuaternion::from_axis_angle(&Vector3::z_axis(), positions[0])
+        * UnitQuaternion::from_axis_angle(&Vector3::y_axis(), positions[1])
+}
+
+/// Positions of the spherical joint which realize `rotation`
+fn spherical_joint_positions<T>(rotation: &UnitQuaternion<T>) -> [T; 3]
+where
+    T: RealField,
+{
+    let (roll, pitch, yaw) = rotation.euler_angles();
+    [yaw, pitch, roll]
+}
+
+/// Stewart platform (hexapod) with six linear legs
+///
+/// The legs connect `base_points` (in the base frame) and `platform_points`
+/// (in the frame of the moving platform) with the universal/spherical joints.
+/// The position of each actuator is the length of the leg.
+///
+/// The legs and the platform are the nodes of `chain`. Each leg is the universal
+/// joint (`leg{i}_base_z` and `leg{i}_base_y`) at the base point and the linear
+/// actuator `leg{i}` along its x axis. The platform is connected to the end of `leg0`
+/// by the spherical joint (`platform_z`, `platform_y` and `platform_x`). The other legs
+/// close the loops, so the ends of them meet the platform points after the IK/FK.
+/// The kinematics itself is solved by the closed-form leg vectors.
+pub struct StewartPlatform<T: RealField> {
+    /// The points where the legs are attached to the base
+    pub base_points: Vec<Vector3<T>>,
+    /// The points where the legs are attached to the platform
+    pub platform_points: Vec<Vector3<T>>,
+    /// The legs and the platform from the fixed `base` node
+    pub chain: Chain<T>,
+    /// The linear joints of the legs. The limits are checked in the IK.
+    pub actuators: Vec<Node<T>>,
+    /// The moving platform. Its world transform is the pose of the platform.
+    pub platform: Node<T>,
+    /// If the errors of all the leg lengths are smaller than this value, FK is solved.
+    pub allowable_error: T,
+    /// How many times the pose is updated in FK
+    pub num_max_try: usize,
+    universal_joints: Vec<[Node<T>; 2]>,
+    spherical_joint: [Node<T>; 3],
+}
+
+impl<T> StewartPlatform<T>
+where
+    T: RealField,
+{
+    /// Create instance of `StewartPlatform`
+    ///
+    /// The actuators have no limits, and all the joints are at zero until the IK
+    /// or FK is solved.
+    pub fn new(base_points: Vec<Vector3<T>>, platform_points: Vec<Vector3<T>>) -> Self {
+        Self::build(base_points, platform_points, &[])
+    }
+
+    fn build(
+        base_points: Vec<Vector3<T>>,
+        platform_points: Vec<Vector3<T>>,
+        limits: &[Option<Range<T>>],
+    ) -> Self {
+        let base = fixed_node("base", Vector3::zeros());
+        let mut universal_joints = Vec::new();
+        let mut actuators = Vec::new();
+        for (i, point) in base_points.iter().enumerate() {
+            let universal = universal_joint(&format!("leg{}_base", i), *point);
+            let actuator = JointBuilder::new()
+                .name(&format!("leg{}", i))
+                .joint_type(JointType::Linear {
+                    axis: Vector3::x_axis(),
+                })
+                .limits(limits.get(i).and_then(|limit| *limit))
+                .into_node();
+            universal[0].set_parent(&base);
+            actuator.set_parent(&universal[1]);
+            universal_joints.push(universal);
+            actuators.push(actuator);
+        }
+        let spherical_joint = spherical_joint("platform");
+        spherical_joint[0].set_parent(&actuators[0]);
+        let platform = fixed_node("platform", -platform_points[0]);
+        platform.set_parent(&spherical_joint[2]);
+        Self {
+            base_points,
+            platform_points,
+            chain: Chain::from_root(base),
+            actuators,
+            platform,
+            allowable_error: na::convert(1e-9),
+            num_max_try: 100,
+            universal_joints,
+            spherical_joint,
+        }
+    }
+
+    /// Rebuild the legs with the limits of the actuators
+    ///
+    /// `limits[i]` is for `leg{i}`, and the legs after `limits` have no limits.
+    pub fn with_actuator_limits(self, limits: &[Option<Range<T>>]) -> Self {
+        let mut stewart = Self::build(self.base_points, self.platform_points, limits);
+        stewart.allowable_error = self.allowable_error;
+        stewart.num_max_try = self.num_max_try;
+        stewart
+    }
+
+    /// The vectors of the legs from the base to the platform
+    fn leg_vectors(&self, pose: &Isometry3<T>) -> Vec<Vector3<T>> {
+        self.base_points
+            .iter()
+            .zip(self.platform_points.iter())
+            .map(|(base, platform)| pose * na::Point3::from(*platform) - na::Point3::from(*base))
+            .collect()
+    }
+
+    /// Calculate the lengths of the legs for the pose of the platform (analytic)
+    pub fn leg_lengths(&self, pose: &Isometry3<T>) -> Vec<T> {
+        self.leg_vectors(pose)
+            .iter()
+            .map(|leg| leg.norm())
+            .collect()
+    }
+
+    /// Jacobian from the twist of the platform [v; w] to the velocities of the legs (6 x 6)
+    pub fn jacobian(&self, pose: &Isometry3<T>) -> DMatrix<T> {
+        let mut jacobi = DMatrix::zeros(self.base_points.len(), 6);
+        for (i, (leg, platform)) in self
+            .leg_vectors(pose)
+            .iter()
+            .zip(self.platform_points.iter())
+            .enumerate()
+        {
+            let u = leg.normalize();
+            let w = (pose.rotation * platform).cross(&u);
+            for j in 0..3 {
+                jacobi[(i, j)] = u[j];
+                jacobi[(i, j + 3)] = w[j];
+            }
+        }
+        jacobi
+    }
+
+    /// Move the legs to realize the pose of the platform
+    ///
+    /// Returns the lengths of the legs. The limits of all the actuators are checked
+    /// before any joint is moved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let base_angles = [-0.2f64, 0.2, 1.89, 2.29, 3.99, 4.39];
+    /// let platform_angles = [-0.85f64, 0.85, 1.25, 2.94, 3.34, 5.04];
+    /// let base = base_angles.iter().map(|a| Vector3::new(a.cos(), a.sin(), 0.0)).collect();
+    /// let platform = platform_angles
+    ///     .iter()
+    ///     .map(|a| Vector3::new(0.5 * a.cos(), 0.5 * a.sin(), 0.0))
+    ///     .collect();
+    /// let stewart = StewartPlatform::new(base, platform);
+    /// let pose = Isometry3::translation(0.0, 0.0, 1.0);
+    /// let lengths = stewart.inverse_kinematics(&pose).unwrap();
+    /// assert_eq!(lengths.len(), 6);
+    /// assert_eq!(stewart.actuators[0].joint_position().unwrap(), lengths[0]);
+    /// let platform = stewart.platform.world_transform().unwrap();
+    /// assert!((platform.translation.vector - pose.translation.vector).norm() < 1e-9);
+    /// ```
+    pub fn inverse_kinematics(&self, pose: &Isometry3<T>) -> Result<Vec<T>, IKError> {
+        let lengths = self.leg_lengths(pose);
+        check_actuator_limits(&self.actuators, &lengths)?;
+        set_positions(&self.actuators, &lengths)?;
+        self.set_passive_joints(pose)?;
+        Ok(lengths)
+    }
+
+    /// Move the passive joints to the pose of the platform and update the transforms
+    fn set_passive_joints(&self, pose: &Isometry3<T>) -> Result<(), IKError> {
+        let legs = self.leg_vectors(pose);
+        for (universal, leg) in self.universal_joints.iter().zip(legs.iter()) {
+            set_positions(universal, &universal_joint_positions(leg))?;
+        }
+        let leg_rotation = universal_joint_rotation(&universal_joint_positions(&legs[0]));
+        set_positions(
+            &self.spherical_joint,
+            &spherical_joint_positions(&(leg_rotation.inverse() * pose.rotation)),
+        )?;
+        self.chain.update_transforms();
+        Ok(())
+    }
+
+    /// Calculate the pose of the platform from the positions of the actuators
+    ///
+    /// It is solved by the Newton method from `initial_pose`, so it converges to
+    /// the solution which is close to `initial_pose`. The passive joints are moved
+    /// to the solution.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let base_angles = [-0.2f64, 0.2, 1.89, 2.29, 3.99, 4.39];
+    /// let platform_angles = [-0.85f64, 0.85, 1.25, 2.94, 3.34, 5.04];
+    /// let base = base_angles.iter().map(|a| Vector3::new(a.cos(), a.sin(), 0.0)).collect();
+    /// let platform = platform_angles
+    ///     .iter()
+    ///     .map(|a| Vector3::new(0.5 * a.cos(), 0.5 * a.sin(), 0.0))
+    ///     .collect();
+    /// let stewart = StewartPlatform::new(base, platform);
+    /// let pose = Isometry3::new(Vector3::new(0.1, 0.0, 1.0), Vector3::new(0.0, 0.1, 0.2));
+    /// stewart.inverse_kinematics(&pose).unwrap();
+    /// let solved = stewart
+    ///     .forward_kinematics(&Isometry3::translation(0.0, 0.0, 1.0))
+    ///     .unwrap();
+    /// assert!((solved.translation.vector - pose.translation.vector).norm() < 1e-6);
+    /// ```
+    pub fn forward_kinematics(&self, initial_pose: &Isometry3<T>) -> Result<Isometry3<T>, IKError> {
+        let target = DVector::from_vec(actuator_positions(&self.actuators));
+        let mut pose = *initial_pose;
+        for _ in 0..self.num_max_try {
+            let err = &target - DVector::from_vec(self.leg_lengths(&pose));
+            if err.amax() < self.allowable_error {
+                self.set_passive_joints(&pose)?;
+                return Ok(pose);
+            }
+            let d_x = self
+                .jacobian(&pose)
+                .lu()
+                .solve(&err)
+                .ok_or(IKError::InverseMatrixError)?;
+            pose.translation.vector += Vector3::new(d_x[0], d_x[1], d_x[2]);
+            pose.rotation =
+                UnitQuaternion::new(Vector3::new(d_x[3], d_x[4], d_x[5])) * pose.rotation;
+        }
+        Err(IKError::NotConvergedError {
+            error: format!(
+                "forward kinematics of the stewart platform has not converged: tried {} times",
+                self.num_max_try
+            ),
+        })
+    }
+}
+
+/// Delta robot with three rotational actuators
+///
+/// The actuators are on the base (z = 0) at `base_radius` from the center, and
+/// at every 120 [deg] from the x axis. The upper arm rotates downward by the positive
+/// position from the horizontal outward direction, and the parallelogram forearm
+/// connects it to the effector (below the base) at `effector_radius` from the center.
+/// The effector keeps its orientation, so its pose is a position.
+///
+/// The arms and the effector are the nodes of `chain`. Each arm is the actuator
+/// `arm{i}`, the elbow joint (`arm{i}_elbow_z` and `arm{i}_elbow_y`) at the end of the
+/// upper arm, and the fixed `arm{i}_wrist` at the end of the forearm. The effector is
+/// connected to `arm0_wrist` by the spherical joint (`effector_z`, `effector_y` and
+/// `effector_x`), and the other wrists close the loops. The parallelogram is modeled
+/// as a single forearm.
+pub struct DeltaRobot<T: RealField> {
+    /// Distance from the center of the base to the actuators
+    pub base_radius: T,
+    /// Distance from the center of the effector to the forearms
+    pub effector_radius: T,
+    /// Length of the upper arms
+    pub upper_arm_length: T,
+    /// Length of the forearms
+    pub forearm_length: T,
+    /// The arms and the effector from the fixed `base` node
+    pub chain: Chain<T>,
+    /// The rotational joints of the upper arms. The limits are checked in the IK.
+    pub actuators: Vec<Node<T>>,
+    /// The effector. Its world translation is the position of the effector.
+    pub effector: Node<T>,
+    /// If the errors of all the forearms are smaller than this value, FK is solved.
+    pub allowable_error: T,
+    /// How many times the position is updated in FK
+    pub num_max_try: usize,
+    elbow_joints: Vec<[Node<T>; 2]>,
+    spherical_joint: [Node<T>; 3],
+}
+
+impl<T> DeltaRobot<T>
+where
+    T: RealField,
+{
+    /// Create instance of `DeltaRobot`
+    ///
+    /// The actuators have no limits, and all the joints are at zero until the IK
+    /// or FK is solved.
+    pub fn new(base_radius: T, effector_radius: T, upper_arm_length: T, forearm_length: T) -> Self {
+        Self::build(
+            base_radius,
+            effector_radius,
+            upper_arm_length,
+            forearm_length,
+            &[],
+        )
+    }
+
+    fn build(
+        base_radius: T,
+        effector_radius: T,
+        upper_arm_length: T,
+        forearm_length: T,
+        limits: &[Option<Range<T>>],
+    ) -> Self {
+        let base = fixed_node("base", Vector3::zeros());
+        let mut elbow_joints = Vec::new();
+        let mut actuators = Vec::new();
+        let mut wrists = Vec::new();
+        for i in 0..3 {
+            let rotation = Self::arm_rotation(i);
+            let actuator = JointBuilder::new()
+                .name(&format!("arm{}", i))
+                .translation(Translation3::from(rotation * Vector3::x() * base_radius))
+                .rotation(rotation)
+                .joint_type(JointType::Rotational {
+                    axis: Vector3::y_axis(),
+                })
+                .limits(limits.get(i).and_then(|limit| *limit))
+                .into_node();
+            let elbow =
+                universal_joint(&format!("arm{}_elbow", i), Vector3::x() * upper_arm_length);
+            let wrist = fixed_node(&format!("arm{}_wrist", i), Vector3::x() * forearm_length);
+            actuator.set_parent(&base);
+            elbow[0].set_parent(&actuator);
+            wrist.set_parent(&elbow[1]);
+            actuators.push(actuator);
+            elbow_joints.push(elbow);
+            wrists.push(wrist);
+        }
+        let spherical_joint = spherical_joint("effector");
+        spherical_joint[0].set_parent(&wrists[0]);
+        let effector = fixed_node("effector", -Vector3::x() * effector_radius);
+        effector.set_parent(&spherical_joint[2]);
+        Self {
+            base_radius,
+            effector_radius,
+            upper_arm_length,
+            forearm_length,
+            chain: Chain::from_root(base),
+            actuators,
+            effector,
+            allowable_error: na::convert(1e-9),
+            num_max_try: 100,
+            elbow_joints,
+            spherical_joint,
+        }
+    }
+
+    /// Rebuild the arms with the limits of the actuators
+    ///
+    /// `limits[i]` is for `arm{i}`, and the arms after `limits` have no limits.
+    pub fn with_actuator_limits(self, limits: &[Option<Range<T>>]) -> Self {
+        let mut delta = Self::build(
+            self.base_radius,
+            self.effector_radius,
+            self.upper_arm_length,
+            self.forearm_length,
+            limits,
+        );
+        delta.allowable_error = self.allowable_error;
+        delta.num_max_try = self.num_max_try;
+        delta
+    }
+
+    fn arm_rotation(index: usize) -> UnitQuaternion<T> {
+        let angle: T = na::convert(index as f64 * 2.0 * ::std::f64::consts::PI / 3.0);
+        UnitQuaternion::from_axis_angle(&Vector3::z_axis(), angle)
+    }
+
+    /// Rotation of the upper arm for the position of the actuator
+    fn upper_arm_rotation(index: usize, position: T) -> UnitQuaternion<T> {
+        Self::arm_rotation(index) * UnitQuaternion::from_axis_angle(&Vector3::y_axis(), position)
+    }
+
+    /// Positions of the elbows for the positions of the actuators
+    fn elbows(&self, positions: &[T]) -> Vec<Vector3<T>> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(i, angle)| {
+                Self::arm_rotation(i)
+                    * Vector3::new(
+                        self.base_radius + self.upper_arm_length * angle.cos(),
+                        T::zero(),
+                        -self.upper_arm_length * angle.sin(),
+                    )
+            })
+            .collect()
+    }
+
+    /// Calculate the positions of the actuators for the position of the effector (analytic)
+    pub fn actuator_positions_for(&self, position: &Vector3<T>) -> Result<Vec<T>, IKError> {
+        let two: T = na::convert(2.0);
+        (0..3)
+            .map(|i| {
+                // in the frame of the arm
+                let p = Self::arm_rotation(i).inverse() * position;
+                let a = p.x + self.effector_radius - self.base_radius;
+                let k =
+                    (a * a + p.y * p.y + p.z * p.z + self.upper_arm_length * self.upper_arm_length
+                        - self.forearm_length * self.forearm_length)
+                        / (two * self.upper_arm_length);
+                let rho = (a * a + p.z * p.z).sqrt();
+                if rho < T::default_epsilon() || k.abs() > rho {
+                    return Err(IKError::NotConvergedError {
+                        error: format!("{} is out of the workspace of arm{}", position, i),
+                    });
+                }
+                // elbow outward solution
+                Ok((-p.z).atan2(a) - (k / rho).acos())
+            })
+            .collect()
+    }
+
+    /// Move the arms to realize the position of the effector
+    ///
+    /// Returns the positions of the actuators. The limits of all the actuators are
+    /// checked before any joint is moved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let delta = DeltaRobot::new(0.2, 0.05, 0.3, 0.6);
+    /// let positions = delta.inverse_kinematics(&Vector3::new(0.05, 0.0, -0.5)).unwrap();
+    /// assert_eq!(positions.len(), 3);
+    /// let effector = delta.effector.world_transform().unwrap();
+    /// assert!((effector.translation.vector - Vector3::new(0.05, 0.0, -0.5)).norm() < 1e-9);
+    /// // out of the workspace
+    /// assert!(delta.inverse_kinematics(&Vector3::new(0.0, 0.0, -2.0)).is_err());
+    /// ```
+    pub fn inverse_kinematics(&self, position: &Vector3<T>) -> Result<Vec<T>, IKError> {
+        let positions = self.actuator_positions_for(position)?;
+        check_actuator_limits(&self.actuators, &positions)?;
+        set_positions(&self.actuators, &positions)?;
+        self.set_passive_joints(position, &positions)?;
+        Ok(positions)
+    }
+
+    /// Move the passive joints to the position of the effector and update the transforms
+    fn set_passive_joints(&self, position: &Vector3<T>, positions: &[T]) -> Result<(), IKError> {
+        let mut elbow_positions = Vec::new();
+        for (i, (elbow_joint, elbow)) in self
+            .elbow_joints
+            .iter()
+            .zip(self.elbows(positions).iter())
+            .enumerate()
+        {
+            let wrist = position + Self::arm_rotation(i) * Vector3::x() * self.effector_radius;
+            let forearm = Self::upper_arm_rotation(i, positions[i]).inverse() * (wrist - elbow);
+            elbow_positions.push(universal_joint_positions(&forearm));
+            set_positions(elbow_joint, &elbow_positions[i])?;
+        }
+        let forearm_rotation = Self::upper_arm_rotation(0, positions[0])
+            * universal_joint_rotation(&elbow_positions[0]);
+        set_positions(
+            &self.spherical_joint,
+            &spherical_joint_positions(&forearm_rotation.inverse()),
+        )?;
+        self.chain.update_transforms();
+        Ok(())
+    }
+
+    /// Calculate the position of the effector from the positions of the actuators
+    ///
+    /// It is solved by the Newton method from `initial_position`. Use the position
+    /// below the base to get the usual solution. The passive joints are moved to
+    /// the solution.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let delta = DeltaRobot::new(0.2, 0.05, 0.3, 0.6);
+    /// let position = Vector3::new(0.05, -0.1, -0.5);
+    /// delta.inverse_kinematics(&position).unwrap();
+    /// let solved = delta.forward_kinematics(&Vector3::new(0.0, 0.0, -0.4)).unwrap();
+    /// assert!((solved - position).norm() < 1e-6);
+    /// ```
+    pub fn forward_kinematics(&self, initial_position: &Vector3<T>) -> Result<Vector3<T>, IKError> {
+        let positions = actuator_positions(&self.actuators);
+        let elbows = self.elbows(&positions);
+        let mut position = *initial_position;
+        for _ in 0..self.num_max_try {
+            let mut jacobi = DMatrix::zeros(3, 3);
+            let mut err = DVector::zeros(3);
+            for (i, elbow) in elbows.iter().enumerate() {
+                let wrist = position + Self::arm_rotation(i) * Vector3::x() * self.effector_radius;
+                let forearm = wrist - elbow;
+                err[i] = self.forearm_length * self.forearm_length - forearm.norm_squared();
+                for j in 0..3 {
+                    jacobi[(i, j)] = forearm[j] + forearm[j];
+                }
+            }
+            if err.amax() < self.allowable_error {
+                self.set_passive_joints(&position, &positions)?;
+                return Ok(position);
+            }
+            let d_p = jacobi.lu().solve(&err).ok_or(IKError::InverseMatrixError)?;
+            position += Vector3::new(d_p[0], d_p[1], d_p[2]);
+        }
+        Err(IKError::NotConvergedError {
+            error: format!(
+                "forward kinematics of the delta robot has not converged: tried {} times",
+                self.num_max_try
+            ),
+        })
+    }
+}
+
+#[test]
+fn test_parallel_ik_fk() {
+    let base_angles = [-0.2f64, 0.2, 1.89, 2.29, 3.99, 4.39];
+    let platform_angles = [-0.85f64, 0.85, 1.25, 2.94, 3.34, 5.04];
+    let base: Vec<_> = base_angles
+        .iter()
+        .map(|a| Vector3::new(a.cos(), a.sin(), 0.0))
+        .collect();
+    let platform: Vec<_> = platform_angles
+        .iter()
+        .map(|a| Vector3::new(0.5 * a.cos(), 0.5 * a.sin(), 0.0))
+        .collect();
+    let stewart = StewartPlatform::new(base.clone(), platform.clone());
+    let pose = Isometry3::new(Vector3::new(0.1, -0.05, 0.9), Vector3::new(0.1, -0.1, 0.2));
+    let check_platform = |stewart: &StewartPlatform<f64>, pose: &Isometry3<f64>| {
+        let solved = stewart.platform.world_transform().unwrap();
+        assert!((solved.translation.vector - pose.translation.vector).norm() < 1e-6);
+        assert!(solved.rotation.angle_to(&pose.rotation) < 1e-6);
+        // the other legs meet the platform
+        for (actuator, point) in stewart.actuators.iter().zip(platform.iter()) {
+            let end = actuator.world_transform().unwrap().translation.vector;
+            assert!((end - (pose * na::Point3::from(*point)).coords).norm() < 1e-6);
+        }
+    };
+    let lengths = stewart.inverse_kinematics(&pose).unwrap();
+    check_platform(&stewart, &pose);
+    let other = Isometry3::translation(0.0, 0.0, 1.0);
+    stewart.inverse_kinematics(&other).unwrap();
+    set_positions(&stewart.actuators, &lengths).unwrap();
+    let solved = stewart.forward_kinematics(&other).unwrap();
+    assert!((solved.translation.vector - pose.translation.vector).norm() < 1e-6);
+    assert!(solved.rotation.angle_to(&pose.rotation) < 1e-6);
+    check_platform(&stewart, &pose);
+    // limits of the last leg, the other joints are not moved either
+    let mut limits = vec![None; 5];
+    limits.push(Some((0.0..=lengths[5] + 0.01).into()));
+    let limited = StewartPlatform::new(base, platform.clone()).with_actuator_limits(&limits);
+    limited.inverse_kinematics(&pose).unwrap();
+    let moved = Isometry3::translation(0.0, 0.0, 1.1);
+    assert!(limited.inverse_kinematics(&moved).is_err());
+    for (actuator, length) in limited.actuators.iter().zip(lengths.iter()) {
+        assert_eq!(actuator.joint_position().unwrap(), *length);
+    }
+    check_platform(&limited, &pose);
+
+    let delta = DeltaRobot::new(0.2, 0.05, 0.3, 0.6);
+    let position = Vector3::new(-0.1, 0.08, -0.45);
+    let check_effector = |delta: &DeltaRobot<f64>, position: &Vector3<f64>| {
+        let effector = delta.effector.world_transform().unwrap();
+        assert!((effector.translation.vector - position).norm() < 1e-6);
+        assert!(effector.rotation.angle() < 1e-6);
+        for i in 0..3 {
+            let wrist = position + DeltaRobot::<f64>::arm_rotation(i) * Vector3::x() * 0.05;
+            let node = delta.chain.find(&format!("arm{}_wrist", i)).unwrap();
+            let end = node.world_transform().unwrap().translation.vector;
+            assert!((end - wrist).norm() < 1e-6);
+        }
+    };
+    let positions = delta.inverse_kinematics(&position).unwrap();
+    check_effector(&delta, &position);
+    delta
+        .inverse_kinematics(&Vector3::new(0.0, 0.0, -0.6))
+        .unwrap();
+    set_positions(&delta.actuators, &positions).unwrap();
+    let solved = delta
+        .forward_kinematics(&Vector3::new(0.0, 0.0, -0.4))
+        .unwrap();
+    assert!((solved - position).norm() < 1e-6);
+    check_effector(&delta, &position);
+    let limited = DeltaRobot::new(0.2, 0.05, 0.3, 0.6)
+        .with_actuator_limits(&[None, Some((-1.0..=positions[1] + 0.01).into())]);
+    limited.inverse_kinematics(&position).unwrap();
+    assert!(limited
+        .inverse_kinematics(&Vector3::new(0.0, 0.0, -0.6))
+        .is_err());
+    assert_eq!(limited.actuators[0].joint_position().unwrap(), positions[0]);
+    check_effector(&limited, &position);
+}
@@ -0,0 +1,398 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, RealField};
+use rand;
+
+use chain::*;
+use errors::*;
+use random_ik::*;
+
+/// Check if the current state of the chain is valid (for example, collision free)
+///
+/// The transforms of the chain are updated before it is called.
+/// Closures `Fn(&Chain<T>) -> bool` implement this trait.
+pub trait ValidityChecker<T: RealField> {
+    /// Returns true if the current joint positions of `chain` are valid
+    fn is_valid(&self, chain: &Chain<T>) -> bool;
+}
+
+impl<T, F> ValidityChecker<T> for F
+where
+    T: RealField,
+    F: Fn(&Chain<T>) -> bool,
+{
+    fn is_valid(&self, chain: &Chain<T>) -> bool {
+        self(chain)
+    }
+}
+
+fn distance<T>(a: &[T], b: &[T]) -> T
+where
+    T: RealField,
+{
+    a.iter()
+        .zip(b.iter())
+        .fold(T::zero(), |sum, (x, y)| sum + (*x - *y) * (*x - *y))
+        .sqrt()
+}
+
+/// Move from `from` toward `to` by `step` at most
+fn steer<T>(from: &[T], to: &[T], step: T) -> Vec<T>
+where
+    T: RealField,
+{
+    let d = distance(from, to);
+    if d <= step {
+        return to.to_vec();
+    }
+    let rate = step / d;
+    from.iter()
+        .zip(to.iter())
+        .map(|(a, b)| *a + (*b - *a) * rate)
+        .collect()
+}
+
+/// Tree of the joint positions
+struct Tree<T: RealField> {
+    /// joint positions and the index of the parent
+    nodes: Vec<(Vec<T>, Option<usize>)>,
+}
+
+impl<T> Tree<T>
+where
+    T: RealField,
+{
+    fn new(root: Vec<T>) -> Self {
+        Tree {
+            nodes: vec![(root, None)],
+        }
+    }
+
+    fn nearest(&self, positions: &[T]) -> usize {
+        let mut nearest = 0;
+        let mut min_distance = distance(&self.nodes[0].0, positions);
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            let d = distance(&node.0, positions);
+            if d < min_distance {
+                nearest = i;
+                min_distance = d;
+            }
+        }
+        nearest
+    }
+
+    /// Positions from the root to the node
+    fn path_from_root(&self, index: usize) -> Vec<Vec<T>> {
+        let mut path = Vec::new();
+        let mut current = Some(index);
+        while let Some(i) = current {
+            path.push(self.nodes[i].0.clone());
+            current = self.nodes[i].1;
+        }
+        path.reverse();
+        path
+    }
+}
+
+/// Result of extending a tree
+enum ExtendStatus {
+    Trapped,
+    Advanced(usize),
+    Reached(usize),
+}
+
+/// Joint space motion planner by RRT-Connect
+///
+/// Two trees from the start and the goal are grown toward random joint positions
+/// within the limits of the joints, and they are connected greedily. The found
+/// path is shortened by random shortcutting.
+pub struct RRTConnectPlanner<T, C>
+where
+    T: RealField,
+    C: ValidityChecker<T>,
+{
+    /// Checker of the states
+    pub checker: C,
+    /// Maximum distance in the joint space of one extension of the trees
+    pub step_size: T,
+    /// Interval in the joint space to check the validity on the edges
+    pub check_resolution: T,
+    /// How many times the trees are extended
+    pub num_max_try: usize,
+    /// How many times the shortcut is tried
+    pub num_shortcut_try: usize,
+}
+
+impl<T, C> RRTConnectPlanner<T, C>
+where
+    T: RealField,
+    C: ValidityChecker<T>,
+{
+    /// Create instance of `RRTConnectPlanner`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let planner = k::RRTConnectPlanner::new(|_: &k::Chain<f64>| true);
+    /// ```
+    pub fn new(checker: C) -> Self {
+        Self {
+            checker,
+            step_size: na::convert(0.1),
+            check_resolution: na::convert(0.02),
+            num_max_try: 1000,
+            num_shortcut_try: 100,
+        }
+    }
+
+    fn is_valid(&self, chain: &Chain<T>, positions: &[T]) -> bool {
+        chain.set_joint_positions_unchecked(positions);
+        chain.update_transforms();
+        self.checker.is_valid(chain)
+    }
+
+    /// Check the validity of the straight line between `from` and `to` (both ends included)
+    fn is_edge_valid(&self, chain: &Chain<T>, from: &[T], to: &[T]) -> bool {
+        let d = distance(from, to);
+        let num = na::try_convert::<T, f64>((d / self.check_resolution).ceil())
+            .map(|num| num as usize)
+            .unwrap_or(1)
+            .max(1);
+        (0..=num).all(|i| {
+            let rate: T = na::convert(i as f64 / num as f64);
+            let positions = from
+                .iter()
+                .zip(to.iter())
+                .map(|(a, b)| *a + (*b - *a) * rate)
+                .collect::<Vec<_>>();
+            self.is_valid(chain, &positions)
+        })
+    }
+
+    fn extend(&self, chain: &Chain<T>, tree: &mut Tree<T>, target: &[T]) -> ExtendStatus {
+        let nearest = tree.nearest(target);
+        let new_positions = steer(&tree.nodes[nearest].0, target, self.step_size);
+        if !self.is_edge_valid(chain, &tree.nodes[nearest].0, &new_positions) {
+            return ExtendStatus::Trapped;
+        }
+        let reached = distance(&new_positions, target) <= T::default_epsilon();
+        tree.nodes.push((new_positions, Some(nearest)));
+        let index = tree.nodes.len() - 1;
+        if reached {
+            ExtendStatus::Reached(index)
+        } else {
+            ExtendStatus::Advanced(index)
+        }
+    }
+
+    fn connect(&self, chain: &Chain<T>, tree: &mut Tree<T>, target: &[T]) -> ExtendStatus {
+        loop {
+            match self.extend(chain, tree, target) {
+                ExtendStatus::Advanced(_) => {}
+                status => return status,
+            }
+        }
+    }
+
+    fn plan_internal(
+        &self,
+        chain: &Chain<T>,
+        start: &[T],
+        goal: &[T],
+    ) -> Result<Vec<Vec<T>>, PlanningError> {
+        for (positions, is_start) in &[(start, true), (goal, false)] {
+            chain.set_joint_positions(positions)?;
+            chain.update_transforms();
+            if !self.checker.is_valid(chain) {
+                let error = format!("{:?}", positions);
+                return Err(if *is_start {
+                    PlanningError::InvalidStartError { error }
+                } else {
+                    PlanningError::InvalidGoalError { error }
+                });
+            }
+        }
+        if self.is_edge_valid(chain, start, goal) {
+            return Ok(vec![start.to_vec(), goal.to_vec()]);
+        }
+        let mut tree_a = Tree::new(start.to_vec());
+        let mut tree_b = Tree::new(goal.to_vec());
+        // true if tree_a is grown from the start
+        let mut is_a_start = true;
+        for _ in 0..self.num_max_try {
+            let random = random_joint_positions(chain);
+            let new_index = match self.extend(chain, &mut tree_a, &random) {
+                ExtendStatus::Trapped => None,
+                ExtendStatus::Advanced(index) | ExtendStatus::Reached(index) => Some(index),
+            };
+            if let Some(new_index) = new_index {
+                let new_positions = tree_a.nodes[new_index].0.clone();
+                if let ExtendStatus::Reached(index) =
+                    self.connect(chain, &mut tree_b, &new_positions)
+                {
+                    let mut path = tree_a.path_from_root(new_index);
+                    let mut path_b = tree_b.path_from_root(index);
+                    // the connecting positions are at the ends of both paths
+                    path_b.pop();
+                    path_b.reverse();
+                    path.extend(path_b);
+                    if !is_a_start {
+                        path.reverse();
+                    }
+                    return Ok(path);
+                }
+            }
+            ::std::mem::swap(&mut tree_a, &mut tree_b);
+            is_a_start = !is_a_start;
+        }
+        Err(PlanningError::NotFoundError {
+            error: format!("tried {} times", self.num_max_try),
+        })
+    }
+
+    /// Plan the path from `start` to `goal` in the joint space of `chain`
+    ///
+    /// The result contains both `start` and `goal`, and all the straight lines between
+    /// the adjacent positions are valid. The joint positions of `chain` and its transforms
+    /// are restored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let j0 = JointBuilder::new()
+    ///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+    ///     .limits(Some((-3.0..=3.0).into()))
+    ///     .into_node();
+    /// let j1 = JointBuilder::new()
+    ///     .translation(Translation3::new(1.0, 0.0, 0.0))
+    ///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+    ///     .limits(Some((-3.0..=3.0).into()))
+    ///     .into_node();
+    /// j1.set_parent(&j0);
+    /// let chain = Chain::<f64>::from_root(j0);
+    /// // the first joint can not be between -0.5 and 0.5 if the second one is positive
+    /// let checker = |chain: &Chain<f64>| {
+    ///     let positions = chain.joint_positions();
+    ///     positions[0].abs() > 0.5 || positions[1] < 0.0
+    /// };
+    /// let planner = RRTConnectPlanner::new(checker);
+    /// let path = planner.plan(&chain, &[-1.0, 1.0], &[1.0, 1.0]).unwrap();
+    /// assert_eq!(path[0], vec![-1.0, 1.0]);
+    /// assert_eq!(path[path.len() - 1], vec![1.0, 1.0]);
+    /// ```
+    pub fn plan(
+        &self,
+        chain: &Chain<T>,
+        start: &[T],
+        goal: &[T],
+    ) -> Result<Vec<Vec<T>>, PlanningError> {
+        let orig_positions = chain.joint_positions();
+        let re = self
+            .plan_internal(chain, start, goal)
+            .map(|path| self.shortcut(chain, path));
+        chain.set_joint_positions_unchecked(&orig_positions);
+        chain.update_transforms();
+        re
+    }
+
+    /// Shorten the path by connecting random pairs of the positions with straight lines
+    ///
+    /// The joint positions of `chain` are used for the validity check.
+    pub fn shortcut(&self, chain: &Chain<T>, mut path: Vec<Vec<T>>) -> Vec<Vec<T>> {
+        for _ in 0..self.num_shortcut_try {
+            if path.len() < 3 {
+                break;
+            }
+            let mut i = rand::random::<usize>() % path.len();
+            let mut j = rand::random::<usize>() % path.len();
+            if i > j {
+                ::std::mem::swap(&mut i, &mut j);
+            }
+            if j < i + 2 {
+                continue;
+            }
+            if self.is_edge_valid(chain, &path[i], &path[j]) {
+                path.drain(i + 1..j);
+            }
+        }
+        path
+    }
+}
+
+#[test]
+fn test_rrt_connect_around_obstacle() {
+    use joint::*;
+    use na::{Translation3, Vector3};
+    use node::*;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .limits(Some((-3.0..=3.0).into()))
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .limits(Some((-3.0..=3.0).into()))
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    end.set_parent(&j1);
+    let chain = Chain::<f64>::from_root(j0);
+    // ball obstacle in front of the arm
+    let checker = |chain: &Chain<f64>| {
+        let end = chain
+            .find("end")
+            .unwrap()
+            .world_transform()
+            .unwrap()
+            .translation
+            .vector;
+        (end - Vector3::new(1.5, 0.0, 0.0)).norm() > 0.6
+    };
+    let planner = RRTConnectPlanner::new(checker);
+    chain.set_joint_positions(&[0.1, 0.2]).unwrap();
+    chain.update_transforms();
+    let end_transform = chain.find("end").unwrap().world_transform().unwrap();
+    let start = [-0.8, 0.5];
+    let goal = [0.8, -0.5];
+    let path = planner.plan(&chain, &start, &goal).unwrap();
+    assert_eq!(chain.joint_positions(), vec![0.1, 0.2]);
+    assert_eq!(
+        chain.find("end").unwrap().world_transform().unwrap(),
+        end_transform
+    );
+    assert_eq!(path[0], start.to_vec());
+    assert_eq!(path[path.len() - 1], goal.to_vec());
+    for pair in path.windows(2) {
+        assert!(planner.is_edge_valid(&chain, &pair[0], &pair[1]));
+    }
+    // invalid goal
+    match planner.plan(&chain, &start, &[0.0, 0.0]) {
+        Err(PlanningError::InvalidGoalError { .. }) => {}
+        _ => panic!("goal must be invalid"),
+    }
+}
@@ -0,0 +1,399 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, Isometry3, RealField};
+use rand::{self, Rng, SeedableRng, StdRng};
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use chain::*;
+use errors::*;
+use ik::*;
+
+/// Generate random joint positions within the limits of the joints
+///
+/// The joints without limits use [-pi, pi].
+///
+/// # Examples
+///
+/// ```
+/// use k::*;
+///
+/// let node = JointBuilder::<f64>::new()
+///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+///     .limits(Some((0.0..=1.0).into()))
+///     .into_node();
+/// let chain = Chain::from_root(node);
+/// let positions = random_joint_positions(&chain);
+/// assert!(positions[0] >= 0.0 && positions[0] <= 1.0);
+/// ```
+pub fn random_joint_positions<T>(chain: &Chain<T>) -> Vec<T>
+where
+    T: RealField,
+{
+    random_joint_positions_with_rng(chain, &mut rand::thread_rng())
+}
+
+/// Generate random joint positions within the limits of the joints using `rng`
+///
+/// Use a seeded `rng` to get the same positions every time.
+///
+/// # Examples
+///
+/// ```
+/// extern crate rand;
+/// # extern crate k;
+/// use rand::SeedableRng;
+///
+/// # fn main() {
+/// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+/// let positions1 =
+///     k::random_joint_positions_with_rng(&chain, &mut rand::StdRng::from_seed(&[1, 2]));
+/// let positions2 =
+///     k::random_joint_positions_with_rng(&chain, &mut rand::StdRng::from_seed(&[1, 2]));
+/// assert_eq!(positions1, positions2);
+/// # }
+/// ```
+pub fn random_joint_positions_with_rng<T, R>(chain: &Chain<T>, rng: &mut R) -> Vec<T>
+where
+    T: RealField,
+    R: Rng,
+{
+    chain
+        .iter_joints()
+        .map(|joint| {
+            let rate: T = na::convert(rng.gen::<f64>());
+            match joint.limits {
+                Some(ref range) => range.min + (range.max - range.min) * rate,
+                None => (rate * na::convert(2.0) - T::one()) * T::pi(),
+            }
+        })
+        .collect()
+}
+
+/// Wrapper of an IK solver which tries random initial positions
+///
+/// It is used to find a solution from bad initial positions, or to find multiple
+/// solutions (for example elbow-up and elbow-down) with an iterative solver like
+/// `JacobianIKSolver`.
+pub struct RandomRestartIKSolver<T, S>
+where
+    T: RealField,
+    S: InverseKinematicsSolver<T>,
+{
+    /// The solver which is used from each initial positions
+    pub solver: S,
+    /// How many random initial positions are tried
+    pub num_restarts: usize,
+    /// Stop trying new initial positions after this time
+    pub time_budget: Option<Duration>,
+    /// The solutions are the same if the differences of all the positions are smaller than this.
+    pub same_solution_tolerance: T,
+    /// Generator of the random initial positions
+    rng: RefCell<StdRng>,
+}
+
+impl<T, S> RandomRestartIKSolver<T, S>
+where
+    T: RealField,
+    S: InverseKinematicsSolver<T>,
+{
+    /// Create instance of `RandomRestartIKSolver`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let solver = k::RandomRestartIKSolver::new(k::JacobianIKSolver::<f64>::default(), 10);
+    /// ```
+    pub fn new(solver: S, num_restarts: usize) -> Self {
+        Self {
+            solver,
+            num_restarts,
+            time_budget: None,
+            same_solution_tolerance: na::convert(0.001),
+            rng: RefCell::new(StdRng::from_seed(&[rand::random::<usize>()])),
+        }
+    }
+
+    /// Reset the generator of the random initial positions with `seed`
+    ///
+    /// The same initial positions are tried every time after this is called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut solver = k::RandomRestartIKSolver::new(k::JacobianIKSolver::<f64>::default(), 10);
+    /// solver.set_seed(&[1, 2, 3]);
+    /// ```
+    pub fn set_seed(&mut self, seed: &[usize]) {
+        self.rng = RefCell::new(StdRng::from_seed(seed));
+    }
+
+    /// Set the initial positions for the `num_try`-th trial
+    ///
+    /// The first trial uses the current positions. It returns false if the budget is exhausted.
+    fn prepare_trial(&self, arm: &SerialChain<T>, num_try: usize, start: &Instant) -> bool {
+        if num_try > self.num_restarts {
+            return false;
+        }
+        if let Some(budget) = self.time_budget {
+            if start.elapsed() > budget {
+                return false;
+            }
+        }
+        if num_try > 0 {
+            arm.set_joint_positions_unchecked(&random_joint_positions_with_rng(
+                arm,
+                &mut *self.rng.borrow_mut(),
+            ));
+        }
+        true
+    }
+}
+
+/// Clamp the positions within the limits of the joints
+fn clamp_to_limits<T>(arm: &SerialChain<T>, positions: &[T]) -> Vec<T>
+where
+    T: RealField,
+{
+    arm.iter_joints()
+        .zip(positions.iter())
+        .map(|(joint, position)| match joint.limits {
+            Some(ref range) => na::clamp(*position, range.min, range.max),
+            None => *position,
+        })
+        .collect()
+}
+
+/// Sum of the position error and the rotation error
+fn pose_error<T>(
+    arm: &SerialChain<T>,
+    target_pose: &Isometry3<T>,
+    constraints: &Constraints<T>,
+) -> T
+where
+    T: RealField,
+{
+    let diff = calc_pose_diff_with_constraints(target_pose, &arm.end_transform(), constraints);
+    let (len_diff, rot_diff) = target_diff_to_len_rot_diff(&diff, constraints);
+    len_diff.norm() + rot_diff.norm()
+}
+
+impl<T, S> InverseKinematicsSolver<T> for RandomRestartIKSolver<T, S>
+where
+    T: RealField,
+    S: InverseKinematicsSolver<T>,
+{
+    /// Solve from the current positions, and then from random positions until it succeeds
+    ///
+    /// If it fails within `num_restarts` and `time_budget`, the positions of `arm` are
+    /// set to the best (the closest to the target) result of the last iterations of
+    /// `solver`, and `NotConvergedError` is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::prelude::*;
+    ///
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let target = arm.end_transform();
+    /// arm.set_joint_positions(&[0.0, 0.0, 0.0, 0.0, 0.0, 0.0]).unwrap();
+    ///
+    /// let mut solver = k::RandomRestartIKSolver::new(k::JacobianIKSolver::default(), 100);
+    /// solver.time_budget = Some(std::time::Duration::from_millis(100));
+    /// solver.solve(&arm, &target).unwrap_or_else(|err| {
+    ///     println!("Err: {}", err);
+    /// });
+    /// ```
+    fn solve_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<(), IKError> {
+        let start = Instant::now();
+        let mut best: Option<(T, Vec<T>)> = None;
+        let mut num_try = 0;
+        while self.prepare_trial(arm, num_try, &start) {
+            num_try += 1;
+            let positions = match self.solver.solve_with_report(arm, target_pose, constraints) {
+                Ok(ref report) if report.converged => return Ok(()),
+                Ok(report) => clamp_to_limits(arm, &report.joint_positions),
+                Err(_) => continue,
+            };
+            arm.set_joint_positions(&positions)?;
+            let error = pose_error(arm, target_pose, constraints);
+            match best {
+                Some((best_error, _)) if best_error <= error => {}
+                _ => best = Some((error, positions)),
+            }
+        }
+        match best {
+            Some((error, positions)) => {
+                arm.set_joint_positions(&positions)?;
+                Err(IKError::NotConvergedError {
+                    error: format!(
+                        "not converged in {} trials, the best error = {}",
+                        num_try, error
+                    ),
+                })
+            }
+            None => Err(IKError::NotConvergedError {
+                error: format!("not converged in {} trials", num_try),
+            }),
+        }
+    }
+}
+
+impl<T, S> InverseKinematicsSolutions<T> for RandomRestartIKSolver<T, S>
+where
+    T: RealField,
+    S: InverseKinematicsSolver<T>,
+{
+    /// Solve from the current positions and random positions within the budget
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::prelude::*;
+    ///
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// arm.set_joint_positions(&[0.1, 0.2, 0.0, -0.5, 0.0, -0.3]).unwrap();
+    /// let target = arm.end_transform();
+    /// arm.set_joint_positions(&[0.0, 0.0, 0.0, -0.1, 0.0, 0.0]).unwrap();
+    ///
+    /// let solver = k::RandomRestartIKSolver::new(k::JacobianIKSolver::new(0.001, 0.005, 0.5, 100), 5);
+    /// let solutions = solver.solutions(&arm, &target).unwrap();
+    /// for solution in solutions {
+    ///     println!("{:?}", solution);
+    /// }
+    /// ```
+    fn solutions_with_constraints(
+        &self,
+        arm: &SerialChain<T>,
+        target_pose: &Isometry3<T>,
+        constraints: &Constraints<T>,
+    ) -> Result<Vec<Vec<T>>, IKError> {
+        let start = Instant::now();
+        let orig_positions = arm.joint_positions();
+        let mut solutions = Vec::new();
+        let mut num_try = 0;
+        while self.prepare_trial(arm, num_try, &start) {
+            num_try += 1;
+            if self
+                .solver
+                .solve_with_constraints(arm, target_pose, constraints)
+                .is_ok()
+            {
+                solutions.push(arm.joint_positions());
+            }
+        }
+        arm.set_joint_positions(&orig_positions)?;
+        Ok(rank_solutions(
+            solutions,
+            &orig_positions,
+            self.same_solution_tolerance,
+        ))
+    }
+}
+
+#[test]
+fn test_random_restart_solutions() {
+    use joint::*;
+    use na::{Translation3, Vector3};
+    use node::*;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .limits(Some((-3.0..=3.0).into()))
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .limits(Some((-3.0..=3.0).into()))
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    j1.set_parent(&j0);
+    end.set_parent(&j1);
+    let arm = SerialChain::<f64>::from_end(&end);
+    arm.set_joint_positions(&[0.3, 1.0]).unwrap();
+    let target = arm.end_transform();
+    arm.set_joint_positions(&[0.2, 0.9]).unwrap();
+
+    let constraints = Constraints {
+        position_z: false,
+        rotation_x: false,
+        rotation_y: false,
+        rotation_z: false,
+        ..Constraints::default()
+    };
+    let mut solver = RandomRestartIKSolver::new(JacobianIKSolver::new(0.0001, 0.001, 0.5, 100), 30);
+    solver.set_seed(&[1, 2, 3]);
+    let solutions = solver
+        .solutions_with_constraints(&arm, &target, &constraints)
+        .unwrap();
+    assert_eq!(arm.joint_positions(), vec![0.2, 0.9]);
+    for solution in &solutions {
+        arm.set_joint_positions(solution).unwrap();
+        let diff = arm.end_transform().translation.vector - target.translation.vector;
+        assert!(diff.norm() < 0.0001);
+    }
+    // elbow-up and elbow-down
+    assert_eq!(solutions.len(), 2);
+    assert!((solutions[0][0] - 0.3).abs() < 0.001);
+    assert!((solutions[0][1] - 1.0).abs() < 0.001);
+    assert!((solutions[1][0] - 1.3).abs() < 0.001);
+    assert!((solutions[1][1] + 1.0).abs() < 0.001);
+
+    // from the singular positions
+    arm.set_joint_positions(&[0.0, 0.0]).unwrap();
+    solver
+        .solve_with_constraints(&arm, &target, &constraints)
+        .unwrap();
+    let diff = arm.end_transform().translation.vector - target.translation.vector;
+    assert!(diff.norm() < 0.0001);
+
+    // no trial converges with too few iterations
+    let target = Isometry3::translation(1.2, 0.8, 0.0);
+    let initial = vec![2.0, 1.0];
+    arm.set_joint_positions(&initial).unwrap();
+    let mut solver = RandomRestartIKSolver::new(JacobianIKSolver::new(0.0001, 0.001, 0.5, 2), 5);
+    solver.set_seed(&[4, 5, 6]);
+    assert!(solver
+        .solve_with_constraints(&arm, &target, &constraints)
+        .is_err());
+    let best_error = pose_error(&arm, &target, &constraints);
+    let mut rng = StdRng::from_seed(&[4, 5, 6]);
+    let mut seeds = vec![initial];
+    for _ in 0..5 {
+        seeds.push(random_joint_positions_with_rng(&arm, &mut rng));
+    }
+    for seed in &seeds {
+        arm.set_joint_positions(seed).unwrap();
+        assert!(best_error < pose_error(&arm, &target, &constraints));
+    }
+}
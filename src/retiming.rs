@@ -0,0 +1,479 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, RealField};
+
+use chain::*;
+use errors::*;
+use trajectory::*;
+
+/// Time-optimal parameterization of a joint-space path
+///
+/// The path q(s) is the cubic spline through the waypoints, parameterized by the
+/// accumulated distance s. The path is discretized into about `num_grid` sections
+/// (the waypoints are on the grid), and the squared path velocity x = (ds/dt)^2 is
+/// maximized at each grid point under the velocity and the acceleration limits of all
+/// the joints (TOPP by reachability analysis): the backward pass calculates the maximum
+/// x from which the path can stop at the end, and the forward pass accelerates as much
+/// as possible within it. The accelerations are limited at both ends of each section.
+pub struct TimeOptimalRetimer {
+    /// Number of the sections of the discretized path (approximately)
+    pub num_grid: usize,
+}
+
+/// Path derivatives at a grid point
+struct GridPoint<T: RealField> {
+    /// dq/ds
+    first: Vec<T>,
+    /// d^2q/ds^2
+    second: Vec<T>,
+}
+
+impl TimeOptimalRetimer {
+    /// Create instance of `TimeOptimalRetimer`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let retimer = k::TimeOptimalRetimer::new(100);
+    /// ```
+    pub fn new(num_grid: usize) -> Self {
+        Self { num_grid }
+    }
+
+    /// Retime the path of `chain`'s joints with the limits of the joints
+    ///
+    /// All the movable joints of `chain` must have `velocity_limit` and `acceleration_limit`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let j0 = JointBuilder::new()
+    ///     .name("j0")
+    ///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+    ///     .velocity_limit(Some(1.0))
+    ///     .acceleration_limit(Some(2.0))
+    ///     .into_node();
+    /// let chain = Chain::<f64>::from_root(j0);
+    /// let retimer = TimeOptimalRetimer::new(100);
+    /// let trajectory = retimer.retime(&chain, &[vec![0.0], vec![2.0]]).unwrap();
+    /// // accelerate 0.5 [s], cruise 1.5 [s], and decelerate 0.5 [s]
+    /// assert!((trajectory.duration() - 2.5).abs() < 0.05);
+    /// ```
+    pub fn retime<T>(
+        &self,
+        chain: &Chain<T>,
+        path: &[Vec<T>],
+    ) -> Result<JointTrajectory<T>, JointError>
+    where
+        T: RealField,
+    {
+        let mut velocity_limits = Vec::with_capacity(chain.dof());
+        let mut acceleration_limits = Vec::with_capacity(chain.dof());
+        for joint in chain.iter_joints() {
+            match (joint.velocity_limit, joint.acceleration_limit) {
+                (Some(velocity), Some(acceleration)) => {
+                    velocity_limits.push(velocity);
+                    acceleration_limits.push(acceleration);
+                }
+                _ => {
+                    return Err(JointError::InvalidArgumentsError {
+                        error: format!(
+                            "joint {} does not have the velocity and the acceleration limits",
+                            joint.name
+                        ),
+                    })
+                }
+            }
+        }
+        let joint_names = chain
+            .iter_joints()
+            .map(|joint| joint.name.to_owned())
+            .collect();
+        self.retime_with_limits(joint_names, path, &velocity_limits, &acceleration_limits)
+    }
+
+    /// Retime the path with the limits of the absolute velocities and accelerations
+    ///
+    /// The trajectory starts and stops with zero velocity. It is interpolated by the quintic
+    /// polynomials, and its points are at both ends of the short time around each grid
+    /// point, in which the acceleration along the path changes linearly.
+    pub fn retime_with_limits<T>(
+        &self,
+        joint_names: Vec<String>,
+        path: &[Vec<T>],
+        velocity_limits: &[T],
+        acceleration_limits: &[T],
+    ) -> Result<JointTrajectory<T>, JointError>
+    where
+        T: RealField,
+    {
+        let dof = joint_names.len();
+        for size in &[velocity_limits.len(), acceleration_limits.len()] {
+            if *size != dof {
+                return Err(JointError::SizeMismatchError {
+                    input: *size,
+                    required: dof,
+                });
+            }
+        }
+        if velocity_limits
+            .iter()
+            .chain(acceleration_limits.iter())
+            .any(|limit| *limit <= T::zero())
+        {
+            return Err(JointError::InvalidArgumentsError {
+                error: "limits must be positive".to_owned(),
+            });
+        }
+        if self.num_grid == 0 {
+            return Err(JointError::InvalidArgumentsError {
+                error: "num_grid must be positive".to_owned(),
+            });
+        }
+        // remove the duplicated waypoints and parameterize by the distance
+        let mut waypoints: Vec<Vec<T>> = Vec::with_capacity(path.len());
+        let mut distances = Vec::with_capacity(path.len());
+        for positions in path {
+            if positions.len() != dof {
+                return Err(JointError::SizeMismatchError {
+                    input: positions.len(),
+                    required: dof,
+                });
+            }
+            match waypoints.last() {
+                None => distances.push(T::zero()),
+                Some(last) => {
+                    let distance = last
+                        .iter()
+                        .zip(positions.iter())
+                        .fold(T::zero(), |sum, (a, b)| sum + (*b - *a) * (*b - *a))
+                        .sqrt();
+                    if distance <= T::default_epsilon() {
+                        continue;
+                    }
+                    distances.push(distances[distances.len() - 1] + distance);
+                }
+            }
+            waypoints.push(positions.clone());
+        }
+        if waypoints.is_empty() {
+            return Err(JointError::InvalidArgumentsError {
+                error: "path is empty".to_owned(),
+            });
+        }
+        if waypoints.len() == 1 {
+            let point = TrajectoryPoint::new(
+                T::zero(),
+                waypoints.remove(0),
+                vec![T::zero(); dof],
+                vec![T::zero(); dof],
+            );
+            return JointTrajectory::new(joint_names, vec![point], InterpolationType::Cubic);
+        }
+        let spline = self.path_spline(&joint_names, &distances, waypoints)?;
+        let length = distances[distances.len() - 1];
+        // the grid points include the waypoints, at which the third derivatives of the
+        // path jump
+        let mut grid_distances = vec![T::zero()];
+        for pair in distances.windows(2) {
+            let num = na::try_convert::<T, f64>(
+                ((pair[1] - pair[0]) / length * na::convert(self.num_grid as f64)).round(),
+            )
+            .map(|num| num as usize)
+            .unwrap_or(1)
+            .max(1);
+            for i in 1..=num {
+                let rate: T = na::convert(i as f64 / num as f64);
+                grid_distances.push(pair[0] + (pair[1] - pair[0]) * rate);
+            }
+        }
+        let n = grid_distances.len() - 1;
+        let steps = grid_distances
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        let grid = grid_distances
+            .iter()
+            .map(|distance| {
+                let sample = spline.sample(*distance);
+                GridPoint {
+                    first: sample.velocities,
+                    second: sample.accelerations,
+                }
+            })
+            .collect::<Vec<_>>();
+        let two: T = na::convert(2.0);
+        let infinity: T = na::convert(f64::MAX);
+
+        // bounds of u = d^2s/dt^2 for x = (ds/dt)^2, where the accelerations are
+        // first * u + second * x
+        let acceleration_bounds = |first: &[T], second: &[T], x: T| -> (T, T) {
+            let mut lower = -infinity;
+            let mut upper = infinity;
+            for ((first, second), a) in first
+                .iter()
+                .zip(second.iter())
+                .zip(acceleration_limits.iter())
+            {
+                let a = *a;
+                let centripetal = *second * x;
+                if first.abs() > T::default_epsilon() {
+                    let bound0 = (-a - centripetal) / *first;
+                    let bound1 = (a - centripetal) / *first;
+                    lower = lower.max(bound0.min(bound1));
+                    upper = upper.min(bound0.max(bound1));
+                } else if centripetal.abs() > a {
+                    return (infinity, -infinity);
+                }
+            }
+            (lower, upper)
+        };
+        // x changes to x + 2 * step * u at the end of the section, so the accelerations
+        // at the end are (first + 2 * step * second) * u + second * x
+        let end_firsts = grid[1..]
+            .iter()
+            .zip(steps.iter())
+            .map(|(point, step)| {
+                point
+                    .first
+                    .iter()
+                    .zip(point.second.iter())
+                    .map(|(first, second)| *first + two * *step * *second)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        // bounds of u in the section i, which keep the accelerations at both of its ends
+        let section_bounds = |i: usize, x: T| -> (T, T) {
+            let (lower, upper) = acceleration_bounds(&grid[i].first, &grid[i].second, x);
+            let (end_lower, end_upper) =
+                acceleration_bounds(&end_firsts[i], &grid[i + 1].second, x);
+            (lower.max(end_lower), upper.min(end_upper))
+        };
+        let velocity_bound = |point: &GridPoint<T>| -> T {
+            point
+                .first
+                .iter()
+                .zip(velocity_limits.iter())
+                .filter(|(first, _)| first.abs() > T::default_epsilon())
+                .fold(infinity, |bound, (first, limit)| {
+                    let max_sdot = *limit / first.abs();
+                    bound.min(max_sdot * max_sdot)
+                })
+        };
+
+        // backward pass: the maximum x which can stop at the end
+        let mut controllable = vec![T::zero(); n + 1];
+        for i in (0..n).rev() {
+            let point = &grid[i];
+            let feasible = |x: T| {
+                let (lower, upper) = section_bounds(i, x);
+                lower <= upper && x + two * steps[i] * lower <= controllable[i + 1]
+            };
+            let mut low = T::zero();
+            let mut high = velocity_bound(point).min(na::convert(1e12));
+            if feasible(high) {
+                low = high;
+            } else {
+                for _ in 0..100 {
+                    let middle = (low + high) / two;
+                    if feasible(middle) {
+                        low = middle;
+                    } else {
+                        high = middle;
+                    }
+                }
+            }
+            controllable[i] = low;
+        }
+
+        // forward pass: accelerate as much as possible
+        let mut xs = vec![T::zero(); n + 1];
+        let mut us = vec![T::zero(); n + 1];
+        for i in 0..n {
+            let (_, upper) = section_bounds(i, xs[i]);
+            let u = upper.min((controllable[i + 1] - xs[i]) / (two * steps[i]));
+            us[i] = u;
+            xs[i + 1] = na::clamp(xs[i] + two * steps[i] * u, T::zero(), controllable[i + 1]);
+        }
+        // the path stops at the end, so the acceleration is limited with x = 0
+        let (lower, upper) = acceleration_bounds(&grid[n].first, &grid[n].second, T::zero());
+        us[n] = na::clamp(us[n - 1], lower, upper);
+
+        let sdots = xs.iter().map(|x| x.sqrt()).collect::<Vec<_>>();
+        let mut durations = Vec::with_capacity(n);
+        for i in 0..n {
+            let sdot_sum = sdots[i] + sdots[i + 1];
+            if sdot_sum <= T::zero() {
+                return Err(JointError::InvalidArgumentsError {
+                    error: format!("path can not move at the grid point {}", i + 1),
+                });
+            }
+            durations.push(two * steps[i] / sdot_sum);
+        }
+        let trajectory_point = |time: T, s: T, sdot: T, u: T| {
+            let sample = spline.sample(s);
+            let velocities = sample
+                .velocities
+                .iter()
+                .map(|first| *first * sdot)
+                .collect();
+            let accelerations = sample
+                .velocities
+                .iter()
+                .zip(sample.accelerations.iter())
+                .map(|(first, second)| *first * u + *second * sdot * sdot)
+                .collect();
+            TrajectoryPoint::new(time, sample.positions, velocities, accelerations)
+        };
+        // u jumps at the grid points, but the quintic polynomials can not follow the jumps
+        // of the accelerations. u is changed linearly in the short time 2 * delta around
+        // each grid point instead, and the points are put at both ends of it.
+        let ramp_ratio: T = na::convert(0.05);
+        let six: T = na::convert(6.0);
+        let four: T = na::convert(4.0);
+        let mut time = T::zero();
+        let mut points = Vec::with_capacity(2 * n);
+        points.push(trajectory_point(time, T::zero(), sdots[0], us[0]));
+        for i in 1..n {
+            time += durations[i - 1];
+            let delta = ramp_ratio * durations[i - 1].min(durations[i]);
+            let s = grid_distances[i];
+            let (before, after) = (us[i - 1], us[i]);
+            points.push(trajectory_point(
+                time - delta,
+                s - sdots[i] * delta + before * delta * delta / two,
+                sdots[i] - before * delta,
+                before,
+            ));
+            points.push(trajectory_point(
+                time + delta,
+                s + sdots[i] * delta + (four * after - before) * delta * delta / six,
+                sdots[i] + after * delta,
+                after,
+            ));
+        }
+        time += durations[n - 1];
+        points.push(trajectory_point(time, length, sdots[n], us[n]));
+        JointTrajectory::new(joint_names, points, InterpolationType::Quintic)
+    }
+
+    /// The cubic spline q(s) of the path, whose time is the distance s
+    fn path_spline<T>(
+        &self,
+        joint_names: &[String],
+        distances: &[T],
+        waypoints: Vec<Vec<T>>,
+    ) -> Result<JointTrajectory<T>, JointError>
+    where
+        T: RealField,
+    {
+        let num = waypoints.len();
+        // the directions at the ends are toward the next waypoints
+        let start_direction = waypoints[1]
+            .iter()
+            .zip(waypoints[0].iter())
+            .map(|(b, a)| (*b - *a) / distances[1])
+            .collect::<Vec<_>>();
+        let end_direction = waypoints[num - 1]
+            .iter()
+            .zip(waypoints[num - 2].iter())
+            .map(|(b, a)| (*b - *a) / (distances[num - 1] - distances[num - 2]))
+            .collect::<Vec<_>>();
+        JointTrajectory::from_named_waypoints_with_end_velocities(
+            joint_names.to_vec(),
+            distances.to_vec(),
+            waypoints,
+            start_direction,
+            end_direction,
+            InterpolationType::Cubic,
+        )
+    }
+}
+
+impl Default for TimeOptimalRetimer {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+#[test]
+fn test_retiming_respects_limits() {
+    use joint::*;
+    use na::Vector3;
+    use node::*;
+    use validator::*;
+
+    let retimer = TimeOptimalRetimer::new(200);
+    let path: Vec<Vec<f64>> = vec![
+        vec![0.0, 0.0],
+        vec![0.5, 1.0],
+        vec![1.5, 0.5],
+        vec![1.5, 0.5],
+        vec![2.0, -0.5],
+    ];
+    let velocity_limits: [f64; 2] = [1.0, 0.5];
+    let acceleration_limits: [f64; 2] = [2.0, 1.0];
+    let trajectory = retimer
+        .retime_with_limits(
+            vec!["j0".to_owned(), "j1".to_owned()],
+            &path,
+            &velocity_limits,
+            &acceleration_limits,
+        )
+        .unwrap();
+    // the duplicated waypoint is removed and the path passes through the waypoints
+    let first = &trajectory.points[0];
+    let last = &trajectory.points[trajectory.points.len() - 1];
+    assert_eq!(first.positions, vec![0.0, 0.0]);
+    assert!((last.positions[1] + 0.5).abs() < 1e-9);
+    assert_eq!(first.velocities, vec![0.0, 0.0]);
+    assert!(last.velocities.iter().all(|v| v.abs() < 1e-9));
+    let mut is_velocity_saturated = false;
+    for point in &trajectory.points {
+        for j in 0..2 {
+            assert!(point.velocities[j].abs() <= velocity_limits[j] + 1e-3);
+            assert!(point.accelerations[j].abs() <= acceleration_limits[j] + 1e-3);
+            is_velocity_saturated |= point.velocities[j].abs() > velocity_limits[j] - 1e-3;
+        }
+    }
+    // time optimal: one of the limits is active
+    assert!(is_velocity_saturated);
+    // the limits are kept between the points within the discretization error
+    let joints = ["j0", "j1"]
+        .iter()
+        .zip(velocity_limits.iter().zip(acceleration_limits.iter()))
+        .map(|(name, (velocity_limit, acceleration_limit))| {
+            JointBuilder::new()
+                .name(name)
+                .joint_type(JointType::Rotational {
+                    axis: Vector3::z_axis(),
+                })
+                .velocity_limit(Some(*velocity_limit))
+                .acceleration_limit(Some(*acceleration_limit))
+                .into_node()
+        })
+        .collect::<Vec<_>>();
+    joints[1].set_parent(&joints[0]);
+    let chain = Chain::from_root(joints[0].clone());
+    let mut validator = TrajectoryValidator::new(0.001);
+    validator.tolerance = 5e-3;
+    validator.validate(&chain, &trajectory).unwrap();
+    assert!(retimer
+        .retime_with_limits(vec!["j0".to_owned()], &[vec![0.0f64]], &[0.0], &[1.0])
+        .is_err());
+}
@@ -0,0 +1,495 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, DMatrix, DVector, RealField};
+
+use chain::*;
+use errors::*;
+
+/// Type of the interpolation between the points of the trajectory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InterpolationType {
+    /// Cubic polynomial with the positions and the velocities at both ends
+    Cubic,
+    /// Quintic polynomial with the positions, the velocities and the accelerations at both ends
+    Quintic,
+}
+
+/// Joint states at the time
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrajectoryPoint<T: RealField> {
+    /// Time from the start of the trajectory [s]
+    pub time: T,
+    /// Positions of the joints
+    pub positions: Vec<T>,
+    /// Velocities of the joints
+    pub velocities: Vec<T>,
+    /// Accelerations of the joints
+    pub accelerations: Vec<T>,
+}
+
+impl<T> TrajectoryPoint<T>
+where
+    T: RealField,
+{
+    /// Create the point which has the positions, the velocities and the accelerations
+    pub fn new(time: T, positions: Vec<T>, velocities: Vec<T>, accelerations: Vec<T>) -> Self {
+        Self {
+            time,
+            positions,
+            velocities,
+            accelerations,
+        }
+    }
+}
+
+/// Coefficients of the polynomial of a joint in a segment (from the 0th order)
+fn segment_coefficients<T>(
+    interpolation: InterpolationType,
+    start: &TrajectoryPoint<T>,
+    end: &TrajectoryPoint<T>,
+    index: usize,
+) -> [T; 6]
+where
+    T: RealField,
+{
+    let h = end.time - start.time;
+    let dp = end.positions[index] - start.positions[index];
+    let v0 = start.velocities[index];
+    let v1 = end.velocities[index];
+    let c = |value: f64| -> T { na::convert(value) };
+    match interpolation {
+        InterpolationType::Cubic => [
+            start.positions[index],
+            v0,
+            (c(3.0) * dp / h - c(2.0) * v0 - v1) / h,
+            (c(-2.0) * dp / h + v0 + v1) / (h * h),
+            T::zero(),
+            T::zero(),
+        ],
+        InterpolationType::Quintic => {
+            let a0 = start.accelerations[index];
+            let a1 = end.accelerations[index];
+            let h2 = h * h;
+            let h3 = h2 * h;
+            [
+                start.positions[index],
+                v0,
+                a0 / c(2.0),
+                (c(20.0) * dp - (c(8.0) * v1 + c(12.0) * v0) * h - (c(3.0) * a0 - a1) * h2)
+                    / (c(2.0) * h3),
+                (c(-30.0) * dp
+                    + (c(14.0) * v1 + c(16.0) * v0) * h
+                    + (c(3.0) * a0 - c(2.0) * a1) * h2)
+                    / (c(2.0) * h3 * h),
+                (c(12.0) * dp - c(6.0) * (v1 + v0) * h + (a1 - a0) * h2) / (c(2.0) * h3 * h2),
+            ]
+        }
+    }
+}
+
+/// Clamped cubic spline: velocities at the knots which make the accelerations continuous
+///
+/// The velocities at the start and the end are `start_velocity` and `end_velocity`.
+fn spline_velocities<T>(times: &[T], positions: &[T], start_velocity: T, end_velocity: T) -> Vec<T>
+where
+    T: RealField,
+{
+    let n = times.len();
+    let mut velocities = vec![T::zero(); n];
+    if n < 2 {
+        return velocities;
+    }
+    velocities[0] = start_velocity;
+    velocities[n - 1] = end_velocity;
+    if n < 3 {
+        return velocities;
+    }
+    let three: T = na::convert(3.0);
+    let two: T = na::convert(2.0);
+    // unknowns are the velocities at the interior knots
+    let m = n - 2;
+    let mut a = DMatrix::zeros(m, m);
+    let mut b = DVector::zeros(m);
+    for k in 0..m {
+        let i = k + 1;
+        let h0 = times[i] - times[i - 1];
+        let h1 = times[i + 1] - times[i];
+        a[(k, k)] = two * (T::one() / h0 + T::one() / h1);
+        if k > 0 {
+            a[(k, k - 1)] = T::one() / h0;
+        }
+        if k + 1 < m {
+            a[(k, k + 1)] = T::one() / h1;
+        }
+        b[k] = three
+            * ((positions[i] - positions[i - 1]) / (h0 * h0)
+                + (positions[i + 1] - positions[i]) / (h1 * h1));
+        if k == 0 {
+            b[k] -= start_velocity / h0;
+        }
+        if k + 1 == m {
+            b[k] -= end_velocity / h1;
+        }
+    }
+    let interior = a.lu().solve(&b).expect("the matrix is diagonally dominant");
+    for k in 0..m {
+        velocities[k + 1] = interior[k];
+    }
+    velocities
+}
+
+/// Trajectory of the joints of a `Chain`
+///
+/// The positions, the velocities and the accelerations are interpolated between
+/// the points by the polynomials. The order of the joints is the same as
+/// `Chain::iter_joints()`.
+#[derive(Clone, Debug)]
+pub struct JointTrajectory<T: RealField> {
+    /// Names of the joints
+    pub joint_names: Vec<String>,
+    /// Points of the trajectory sorted by the time
+    pub points: Vec<TrajectoryPoint<T>>,
+    /// Type of the interpolation
+    pub interpolation: InterpolationType,
+}
+
+impl<T> JointTrajectory<T>
+where
+    T: RealField,
+{
+    /// Create the trajectory from the points which have the velocities and the accelerations
+    ///
+    /// The times of the points must increase strictly.
+    pub fn new(
+        joint_names: Vec<String>,
+        points: Vec<TrajectoryPoint<T>>,
+        interpolation: InterpolationType,
+    ) -> Result<Self, JointError> {
+        if points.is_empty() {
+            return Err(JointError::InvalidArgumentsError {
+                error: "trajectory must have at least one point".to_owned(),
+            });
+        }
+        for point in &points {
+            for values in &[&point.positions, &point.velocities, &point.accelerations] {
+                if values.len() != joint_names.len() {
+                    return Err(JointError::SizeMismatchError {
+                        input: values.len(),
+                        required: joint_names.len(),
+                    });
+                }
+            }
+        }
+        if points.windows(2).any(|pair| pair[1].time <= pair[0].time) {
+            return Err(JointError::InvalidArgumentsError {
+                error: "times of the trajectory points must increase".to_owned(),
+            });
+        }
+        Ok(Self {
+            joint_names,
+            points,
+            interpolation,
+        })
+    }
+
+    /// Create the spline trajectory which passes through the waypoints of `chain`'s joints
+    ///
+    /// The velocities (and the accelerations) at the waypoints are calculated to make the
+    /// accelerations continuous, and they are zero at the start and the end.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// let trajectory = k::JointTrajectory::from_waypoints(
+    ///     &arm,
+    ///     vec![0.0, 1.0, 3.0],
+    ///     vec![vec![0.0; 6], vec![0.2; 6], vec![0.5; 6]],
+    ///     k::InterpolationType::Quintic,
+    /// )
+    /// .unwrap();
+    /// assert_eq!(trajectory.duration(), 3.0);
+    /// ```
+    pub fn from_waypoints(
+        chain: &Chain<T>,
+        times: Vec<T>,
+        positions: Vec<Vec<T>>,
+        interpolation: InterpolationType,
+    ) -> Result<Self, JointError> {
+        let joint_names = chain
+            .iter_joints()
+            .map(|joint| joint.name.to_owned())
+            .collect::<Vec<_>>();
+        Self::from_named_waypoints(joint_names, times, positions, interpolation)
+    }
+
+    /// `from_waypoints` with the names of the joints instead of `Chain`
+    pub(crate) fn from_named_waypoints(
+        joint_names: Vec<String>,
+        times: Vec<T>,
+        positions: Vec<Vec<T>>,
+        interpolation: InterpolationType,
+    ) -> Result<Self, JointError> {
+        let zeros = vec![T::zero(); joint_names.len()];
+        Self::from_named_waypoints_with_end_velocities(
+            joint_names,
+            times,
+            positions,
+            zeros.clone(),
+            zeros,
+            interpolation,
+        )
+    }
+
+    /// `from_named_waypoints` which starts with `start_velocities` and ends with `end_velocities`
+    pub(crate) fn from_named_waypoints_with_end_velocities(
+        joint_names: Vec<String>,
+        times: Vec<T>,
+        positions: Vec<Vec<T>>,
+        start_velocities: Vec<T>,
+        end_velocities: Vec<T>,
+        interpolation: InterpolationType,
+    ) -> Result<Self, JointError> {
+        if times.len() != positions.len() {
+            return Err(JointError::SizeMismatchError {
+                input: positions.len(),
+                required: times.len(),
+            });
+        }
+        if let Some(p) = positions.iter().find(|p| p.len() != joint_names.len()) {
+            return Err(JointError::SizeMismatchError {
+                input: p.len(),
+                required: joint_names.len(),
+            });
+        }
+        if times.windows(2).any(|pair| pair[1] <= pair[0]) {
+            return Err(JointError::InvalidArgumentsError {
+                error: "times of the waypoints must increase".to_owned(),
+            });
+        }
+        let dof = joint_names.len();
+        let num = times.len();
+        let mut velocities = vec![vec![T::zero(); dof]; num];
+        let mut accelerations = vec![vec![T::zero(); dof]; num];
+        for j in 0..dof {
+            let joint_positions = positions.iter().map(|p| p[j]).collect::<Vec<_>>();
+            let joint_velocities = spline_velocities(
+                &times,
+                &joint_positions,
+                start_velocities[j],
+                end_velocities[j],
+            );
+            for i in 0..num {
+                velocities[i][j] = joint_velocities[i];
+            }
+            // accelerations at the interior knots of the cubic spline
+            for i in 1..num.saturating_sub(1) {
+                let h = times[i + 1] - times[i];
+                let dp = joint_positions[i + 1] - joint_positions[i];
+                let six: T = na::convert(6.0);
+                let two: T = na::convert(2.0);
+                let four: T = na::convert(4.0);
+                accelerations[i][j] = six * dp / (h * h)
+                    - (four * joint_velocities[i] + two * joint_velocities[i + 1]) / h;
+            }
+        }
+        let points = times
+            .into_iter()
+            .zip(positions)
+            .zip(velocities.into_iter().zip(accelerations))
+            .map(|((time, p), (v, a))| TrajectoryPoint::new(time, p, v, a))
+            .collect();
+        Self::new(joint_names, points, interpolation)
+    }
+
+    /// Time of the first point
+    pub fn start_time(&self) -> T {
+        self.points[0].time
+    }
+
+    /// Time of the last point
+    pub fn end_time(&self) -> T {
+        self.points[self.points.len() - 1].time
+    }
+
+    /// Time from the first point to the last point
+    pub fn duration(&self) -> T {
+        self.end_time() - self.start_time()
+    }
+
+    /// Sample the positions, the velocities and the accelerations at `time`
+    ///
+    /// `time` is clamped within the start and the end of the trajectory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// let trajectory = k::JointTrajectory::from_waypoints(
+    ///     &arm,
+    ///     vec![0.0, 1.0, 3.0],
+    ///     vec![vec![0.0; 6], vec![0.2; 6], vec![0.5; 6]],
+    ///     k::InterpolationType::Cubic,
+    /// )
+    /// .unwrap();
+    /// let point = trajectory.sample(1.0);
+    /// assert!((point.positions[0] - 0.2).abs() < 1e-9);
+    /// assert!(point.velocities[0] > 0.0);
+    /// ```
+    pub fn sample(&self, time: T) -> TrajectoryPoint<T> {
+        if self.points.len() < 2 {
+            return self.points[0].clone();
+        }
+        let time = na::clamp(time, self.start_time(), self.end_time());
+        let segment = self
+            .points
+            .windows(2)
+            .position(|pair| time <= pair[1].time)
+            .unwrap_or(0);
+        let start = &self.points[segment];
+        let end = &self.points[segment + 1];
+        let s = time - start.time;
+        let dof = self.joint_names.len();
+        let mut point = TrajectoryPoint::new(
+            time,
+            Vec::with_capacity(dof),
+            Vec::with_capacity(dof),
+            Vec::with_capacity(dof),
+        );
+        let c = |value: f64| -> T { na::convert(value) };
+        for j in 0..dof {
+            let k = segment_coefficients(self.interpolation, start, end, j);
+            point
+                .positions
+                .push(k[0] + s * (k[1] + s * (k[2] + s * (k[3] + s * (k[4] + s * k[5])))));
+            point.velocities.push(
+                k[1] + s
+                    * (c(2.0) * k[2]
+                        + s * (c(3.0) * k[3] + s * (c(4.0) * k[4] + s * c(5.0) * k[5]))),
+            );
+            point.accelerations.push(
+                c(2.0) * k[2] + s * (c(6.0) * k[3] + s * (c(12.0) * k[4] + s * c(20.0) * k[5])),
+            );
+        }
+        point
+    }
+
+    /// Set the positions of `chain` to the sample at `time`
+    ///
+    /// The joints of `chain` must be the same as `joint_names`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let chain = k::Chain::<f64>::from_urdf_file("urdf/sample.urdf").unwrap();
+    /// let arm = k::SerialChain::from_end(chain.find("r_wrist_pitch").unwrap());
+    /// let trajectory = k::JointTrajectory::from_waypoints(
+    ///     &arm,
+    ///     vec![0.0, 1.0],
+    ///     vec![vec![0.0; 6], vec![0.2; 6]],
+    ///     k::InterpolationType::Quintic,
+    /// )
+    /// .unwrap();
+    /// trajectory.apply(&arm, 0.5).unwrap();
+    /// assert!((arm.joint_positions()[0] - 0.1).abs() < 1e-9);
+    /// ```
+    pub fn apply(&self, chain: &Chain<T>, time: T) -> Result<(), JointError> {
+        if chain.dof() != self.joint_names.len() {
+            return Err(JointError::SizeMismatchError {
+                input: self.joint_names.len(),
+                required: chain.dof(),
+            });
+        }
+        if let Some((joint, name)) = chain
+            .iter_joints()
+            .zip(self.joint_names.iter())
+            .find(|(joint, name)| joint.name != **name)
+        {
+            return Err(JointError::InvalidArgumentsError {
+                error: format!("joint {} is not {} of the trajectory", joint.name, name),
+            });
+        }
+        chain.set_joint_positions(&self.sample(time).positions)
+    }
+}
+
+#[test]
+fn test_spline_trajectory_continuity() {
+    use joint::*;
+    use na::Vector3;
+    use node::*;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .into_node();
+    let j1 = JointBuilder::new()
+        .name("j1")
+        .joint_type(JointType::Linear {
+            axis: Vector3::x_axis(),
+        })
+        .into_node();
+    j1.set_parent(&j0);
+    let chain = Chain::<f64>::from_root(j0);
+    let times = vec![0.0, 0.5, 1.5, 2.0];
+    let positions = vec![
+        vec![0.0, 0.0],
+        vec![0.5, -0.2],
+        vec![0.3, 0.4],
+        vec![1.0, 0.0],
+    ];
+    for &interpolation in &[InterpolationType::Cubic, InterpolationType::Quintic] {
+        let trajectory = JointTrajectory::from_waypoints(
+            &chain,
+            times.clone(),
+            positions.clone(),
+            interpolation,
+        )
+        .unwrap();
+        for (time, p) in times.iter().zip(positions.iter()) {
+            let sample = trajectory.sample(*time);
+            assert!((sample.positions[0] - p[0]).abs() < 1e-9);
+            assert!((sample.positions[1] - p[1]).abs() < 1e-9);
+        }
+        // velocities and accelerations are continuous at the waypoints
+        for time in &times[1..3] {
+            let before = trajectory.sample(time - 1e-7);
+            let after = trajectory.sample(time + 1e-7);
+            for j in 0..2 {
+                assert!((before.velocities[j] - after.velocities[j]).abs() < 1e-5);
+                assert!((before.accelerations[j] - after.accelerations[j]).abs() < 1e-4);
+            }
+        }
+        let start = trajectory.sample(-1.0);
+        assert_eq!(start.velocities, vec![0.0, 0.0]);
+        if interpolation == InterpolationType::Quintic {
+            assert!(start.accelerations.iter().all(|a| a.abs() < 1e-9));
+        }
+        trajectory.apply(&chain, 2.0).unwrap();
+        assert!((chain.joint_positions()[0] - 1.0).abs() < 1e-9);
+    }
+    assert!(JointTrajectory::from_waypoints(
+        &chain,
+        vec![0.0, 0.0],
+        vec![vec![0.0, 0.0], vec![0.0, 0.0]],
+        InterpolationType::Cubic
+    )
+    .is_err());
+}
@@ -247,6 +247,11 @@ where
                 _ => JointType::Fixed,
             })
             .limits(limit)
+            .velocity_limit(if joint.limit.velocity > 0.0 {
+                Some(na::convert(joint.limit.velocity))
+            } else {
+                None
+            })
             .rotation(quaternion_from(&joint.origin.rpy))
             .translation(translation_from(&joint.origin.xyz))
             .finalize()
@@ -0,0 +1,310 @@
+/*
+  Copyright 2017 Takashi Ogura
+
+  Licensed under the Apache License, Version 2.0 (the "License");
+  you may not use this file except in compliance with the License.
+  You may obtain a copy of the License at
+
+      http://www.apache.org/licenses/LICENSE-2.0
+
+  Unless required by applicable law or agreed to in writing, software
+  distributed under the License is distributed on an "AS IS" BASIS,
+  WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+  See the License for the specific language governing permissions and
+  limitations under the License.
+*/
+use na::{self, RealField, Vector3};
+
+use chain::*;
+use errors::*;
+use planner::*;
+use trajectory::*;
+
+fn to_f64<T>(value: T) -> f64
+where
+    T: RealField,
+{
+    na::try_convert(value).unwrap_or(f64::NAN)
+}
+
+/// Check a trajectory against the limits of the model before executing it
+///
+/// The trajectory is sampled at all of its points and at every `sample_interval`.
+/// Each sample is checked against `Joint::limits`, `Joint::velocity_limit` and
+/// `Joint::acceleration_limit` of the joints, then the joint positions are set to the
+/// chain and the origins of all the nodes are checked against `workspace`, and finally
+/// `collision_checker` is called.
+pub struct TrajectoryValidator<T: RealField> {
+    /// Interval of the samples between the points of the trajectory
+    pub sample_interval: T,
+    /// The values can exceed the limits by this tolerance
+    pub tolerance: T,
+    /// Minimum and maximum corners of the box which contains the origins of all the nodes
+    pub workspace: Option<(Vector3<T>, Vector3<T>)>,
+    /// Checker of the self-collision (or any other constraints)
+    pub collision_checker: Option<Box<dyn ValidityChecker<T>>>,
+}
+
+impl<T> TrajectoryValidator<T>
+where
+    T: RealField,
+{
+    /// Create instance of `TrajectoryValidator` which checks the limits of the joints only
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let validator = k::TrajectoryValidator::<f64>::new(0.01);
+    /// ```
+    pub fn new(sample_interval: T) -> Self {
+        Self {
+            sample_interval,
+            tolerance: na::convert(1e-6),
+            workspace: None,
+            collision_checker: None,
+        }
+    }
+
+    /// Check all the samples of `trajectory` on `chain` and returns the first violation
+    ///
+    /// The error is `JointError::TrajectoryViolationError` with the time of the sample.
+    /// The joint positions of `chain` and its transforms are restored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use k::*;
+    ///
+    /// let j0 = JointBuilder::new()
+    ///     .name("j0")
+    ///     .joint_type(JointType::Rotational { axis: Vector3::z_axis() })
+    ///     .limits(Some((-1.0..=1.0).into()))
+    ///     .velocity_limit(Some(2.0))
+    ///     .into_node();
+    /// let chain = Chain::<f64>::from_root(j0);
+    /// let trajectory = JointTrajectory::from_waypoints(
+    ///     &chain, vec![0.0, 1.0], vec![vec![0.0], vec![0.5]], InterpolationType::Cubic).unwrap();
+    /// let validator = TrajectoryValidator::new(0.01);
+    /// assert!(validator.validate(&chain, &trajectory).is_ok());
+    ///
+    /// // too fast
+    /// let trajectory = JointTrajectory::from_waypoints(
+    ///     &chain, vec![0.0, 0.1], vec![vec![0.0], vec![0.5]], InterpolationType::Cubic).unwrap();
+    /// match validator.validate(&chain, &trajectory) {
+    ///     Err(JointError::TrajectoryViolationError { name, violation, .. }) => {
+    ///         assert_eq!(name, Some("j0".to_owned()));
+    ///         match violation {
+    ///             TrajectoryViolation::VelocityLimit { .. } => {}
+    ///             _ => panic!("unexpected violation"),
+    ///         }
+    ///     }
+    ///     _ => panic!("must be error"),
+    /// }
+    /// ```
+    pub fn validate(
+        &self,
+        chain: &Chain<T>,
+        trajectory: &JointTrajectory<T>,
+    ) -> Result<(), JointError> {
+        if chain.dof() != trajectory.joint_names.len() {
+            return Err(JointError::SizeMismatchError {
+                input: trajectory.joint_names.len(),
+                required: chain.dof(),
+            });
+        }
+        if let Some((joint, name)) = chain
+            .iter_joints()
+            .zip(trajectory.joint_names.iter())
+            .find(|(joint, name)| joint.name != **name)
+        {
+            return Err(JointError::InvalidArgumentsError {
+                error: format!("joint {} is not {} of the trajectory", joint.name, name),
+            });
+        }
+        if self.sample_interval <= T::zero() {
+            return Err(JointError::InvalidArgumentsError {
+                error: "sample_interval must be positive".to_owned(),
+            });
+        }
+        let orig_positions = chain.joint_positions();
+        let re = self.validate_internal(chain, trajectory);
+        chain.set_joint_positions_unchecked(&orig_positions);
+        chain.update_transforms();
+        re
+    }
+
+    fn sample_times(&self, trajectory: &JointTrajectory<T>) -> Vec<T> {
+        let mut times = trajectory
+            .points
+            .iter()
+            .map(|point| point.time)
+            .collect::<Vec<_>>();
+        let mut time = trajectory.start_time() + self.sample_interval;
+        while time < trajectory.end_time() {
+            times.push(time);
+            time += self.sample_interval;
+        }
+        times.sort_by(|a, b| a.partial_cmp(b).expect("time must not be NaN"));
+        times
+    }
+
+    fn validate_internal(
+        &self,
+        chain: &Chain<T>,
+        trajectory: &JointTrajectory<T>,
+    ) -> Result<(), JointError> {
+        for time in self.sample_times(trajectory) {
+            let sample = trajectory.sample(time);
+            let violation_error = |name: Option<String>, violation| {
+                Err(JointError::TrajectoryViolationError {
+                    time: to_f64(time),
+                    name,
+                    violation,
+                })
+            };
+            for (i, joint) in chain.iter_joints().enumerate() {
+                let position = sample.positions[i];
+                if let Some(ref range) = joint.limits {
+                    if position < range.min - self.tolerance
+                        || position > range.max + self.tolerance
+                    {
+                        return violation_error(
+                            Some(joint.name.clone()),
+                            TrajectoryViolation::PositionLimit {
+                                position: to_f64(position),
+                            },
+                        );
+                    }
+                }
+                let velocity = sample.velocities[i];
+                if let Some(limit) = joint.velocity_limit {
+                    if velocity.abs() > limit + self.tolerance {
+                        return violation_error(
+                            Some(joint.name.clone()),
+                            TrajectoryViolation::VelocityLimit {
+                                velocity: to_f64(velocity),
+                            },
+                        );
+                    }
+                }
+                let acceleration = sample.accelerations[i];
+                if let Some(limit) = joint.acceleration_limit {
+                    if acceleration.abs() > limit + self.tolerance {
+                        return violation_error(
+                            Some(joint.name.clone()),
+                            TrajectoryViolation::AccelerationLimit {
+                                acceleration: to_f64(acceleration),
+                            },
+                        );
+                    }
+                }
+            }
+            chain.set_joint_positions_unchecked(&sample.positions);
+            chain.update_transforms();
+            if let Some((ref min, ref max)) = self.workspace {
+                for node in chain.iter() {
+                    let position = node
+                        .world_transform()
+                        .expect("cache must exist")
+                        .translation
+                        .vector;
+                    if (0..3).any(|i| position[i] < min[i] || position[i] > max[i]) {
+                        return violation_error(
+                            Some(node.joint().name.clone()),
+                            TrajectoryViolation::Workspace {
+                                position: [
+                                    to_f64(position[0]),
+                                    to_f64(position[1]),
+                                    to_f64(position[2]),
+                                ],
+                            },
+                        );
+                    }
+                }
+            }
+            if let Some(ref checker) = self.collision_checker {
+                if !checker.is_valid(chain) {
+                    return violation_error(None, TrajectoryViolation::SelfCollision);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_trajectory_validator_reports_first_violation() {
+    use joint::*;
+    use na::Translation3;
+    use node::*;
+
+    let j0 = JointBuilder::new()
+        .name("j0")
+        .joint_type(JointType::Rotational {
+            axis: Vector3::z_axis(),
+        })
+        .limits(Some((-2.0..=2.0).into()))
+        .into_node();
+    let end = JointBuilder::new()
+        .name("end")
+        .translation(Translation3::new(1.0, 0.0, 0.0))
+        .into_node();
+    end.set_parent(&j0);
+    let chain = Chain::<f64>::from_root(j0);
+    let trajectory = JointTrajectory::from_waypoints(
+        &chain,
+        vec![0.0, 1.0, 2.0],
+        vec![vec![0.0], vec![1.0], vec![2.5]],
+        InterpolationType::Quintic,
+    )
+    .unwrap();
+    let mut validator = TrajectoryValidator::new(0.01);
+    // the end leaves y < 0.5 before the joint exceeds the limit
+    validator.workspace = Some((Vector3::new(-2.0, -2.0, -2.0), Vector3::new(2.0, 0.5, 2.0)));
+    chain.set_joint_positions(&[0.3]).unwrap();
+    chain.update_transforms();
+    let end_transform = chain.find("end").unwrap().world_transform().unwrap();
+    match validator.validate(&chain, &trajectory).unwrap_err() {
+        JointError::TrajectoryViolationError {
+            time,
+            name,
+            violation: TrajectoryViolation::Workspace { position },
+        } => {
+            assert_eq!(name, Some("end".to_owned()));
+            assert!(time > 0.0 && time < 1.0);
+            assert!(position[1] > 0.5 && position[1] < 0.6);
+        }
+        error => panic!("unexpected error {}", error),
+    }
+    assert_eq!(chain.joint_positions(), vec![0.3]);
+    assert_eq!(
+        chain.find("end").unwrap().world_transform().unwrap(),
+        end_transform
+    );
+
+    validator.workspace = None;
+    match validator.validate(&chain, &trajectory).unwrap_err() {
+        JointError::TrajectoryViolationError {
+            time,
+            name,
+            violation: TrajectoryViolation::PositionLimit { position },
+        } => {
+            assert_eq!(name, Some("j0".to_owned()));
+            assert!(time > 1.0 && time < 2.0);
+            assert!(position > 2.0 && position < 2.1);
+        }
+        error => panic!("unexpected error {}", error),
+    }
+
+    validator.collision_checker = Some(Box::new(|chain: &Chain<f64>| {
+        chain.joint_positions()[0] < 0.5
+    }));
+    match validator.validate(&chain, &trajectory).unwrap_err() {
+        JointError::TrajectoryViolationError {
+            name: None,
+            violation: TrajectoryViolation::SelfCollision,
+            ..
+        } => {}
+        error => panic!("unexpected error {}", error),
+    }
+}
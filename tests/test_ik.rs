@@ -152,4 +152,146 @@ mod tests {
             assert!((init - end).abs() < 0.002);
         }
     }
+
+    #[test]
+    pub fn ik_end_effector_frame_constraints() {
+        let arm = create_joint_with_link_array6();
+        arm.set_joint_positions(&[0.8, 0.2, 0.0, -1.2, 0.5, 0.1])
+            .unwrap();
+        let target = arm.end_transform();
+        arm.set_joint_positions(&[0.4, 0.1, 0.1, -1.0, 0.1, 0.1])
+            .unwrap();
+        // free rotation around the z axis of the end
+        let constraints = k::Constraints {
+            rotation_z: false,
+            frame: k::ConstraintsFrame::EndEffector,
+            weights: [1.0, 1.0, 1.0, 0.5, 0.5, 0.0],
+            tolerances: Some([0.001, 0.001, 0.001, 0.01, 0.01, 0.0]),
+            ..Default::default()
+        };
+        let solver = k::JacobianIKSolver::new(0.001, 0.001, 0.5, 200);
+        solver
+            .solve_with_constraints(&arm, &target, &constraints)
+            .unwrap();
+        let end = arm.end_transform();
+        let position_diff = end.translation.vector - target.translation.vector;
+        assert!(position_diff.norm() < 0.002);
+        let z_diff = end.rotation * Vector3::z() - target.rotation * Vector3::z();
+        assert!(z_diff.norm() < 0.02);
+    }
+
+    #[test]
+    pub fn ik_goals() {
+        let arm = create_joint_with_link_array6();
+        arm.set_joint_positions(&[0.4, 0.1, 0.1, -1.0, 0.1, 0.1])
+            .unwrap();
+        let solver = k::JacobianIKSolver::new(0.001, 0.001, 0.5, 100);
+
+        let position = na::Point3::new(0.1, 0.1, -0.5);
+        solver
+            .solve_goal(&arm, &k::IKGoal::Position(position))
+            .unwrap();
+        let diff = arm.end_transform().translation.vector - position.coords;
+        assert!(diff.norm() < 0.001);
+
+        let direction = na::Unit::new_normalize(Vector3::new(1.0, 0.0, -1.0));
+        solver
+            .solve_goal(
+                &arm,
+                &k::IKGoal::AxisAlignment {
+                    axis: Vector3::x_axis(),
+                    direction,
+                },
+            )
+            .unwrap();
+        let axis = arm.end_transform().rotation * Vector3::x();
+        assert!((axis - direction.into_inner()).norm() < 0.002);
+    }
+
+    #[test]
+    pub fn ik_mimic() {
+        let rotational_z = || k::JointType::Rotational {
+            axis: Vector3::z_axis(),
+        };
+        let j0: k::Node<f64> = k::JointBuilder::new()
+            .name("j0")
+            .joint_type(rotational_z())
+            .into_node();
+        let j1 = k::JointBuilder::new()
+            .name("j1")
+            .joint_type(rotational_z())
+            .translation(Translation3::new(0.5, 0.0, 0.0))
+            .into_node();
+        let j2 = k::JointBuilder::new()
+            .name("j2")
+            .joint_type(rotational_z())
+            .translation(Translation3::new(0.5, 0.0, 0.0))
+            .into_node();
+        let end = k::JointBuilder::new()
+            .name("end")
+            .translation(Translation3::new(0.5, 0.0, 0.0))
+            .into_node();
+        j1.set_parent(&j0);
+        j2.set_parent(&j1);
+        end.set_parent(&j2);
+        let mimic = k::joint::Mimic::new(0.5, 0.1);
+        j1.set_mimic_parent(&j0, mimic.clone());
+        let arm = k::SerialChain::from_end(&end);
+        arm.set_joint_positions(&[0.3, 0.0, 0.4]).unwrap();
+        let target = arm.end_transform();
+        arm.set_joint_positions(&[0.0, 0.0, 0.1]).unwrap();
+        let constraints = k::Constraints {
+            position_z: false,
+            rotation_x: false,
+            rotation_y: false,
+            rotation_z: false,
+            ..Default::default()
+        };
+        let solver = k::JacobianIKSolver::new(0.0001, 0.001, 0.5, 100);
+        solver
+            .solve_with_constraints(&arm, &target, &constraints)
+            .unwrap();
+        let positions = arm.joint_positions();
+        assert!((positions[0] - 0.3).abs() < 0.001);
+        assert!((positions[1] - mimic.mimic_position(positions[0])).abs() < 1e-9);
+        assert!((positions[2] - 0.4).abs() < 0.001);
+        // all the position and the orientation can not be solved by two joints
+        assert!(solver.solve(&arm, &target).is_err());
+
+        // nested mimic: n2 mimics n1, which mimics n0
+        let nodes = (0..4)
+            .map(|i| {
+                k::JointBuilder::new()
+                    .name(&format!("n{}", i))
+                    .joint_type(rotational_z())
+                    .translation(Translation3::new(if i == 0 { 0.0 } else { 0.4 }, 0.0, 0.0))
+                    .into_node()
+            })
+            .collect::<Vec<k::Node<f64>>>();
+        let end = k::JointBuilder::new()
+            .name("nested_end")
+            .translation(Translation3::new(0.4, 0.0, 0.0))
+            .into_node();
+        for i in 1..4 {
+            nodes[i].set_parent(&nodes[i - 1]);
+        }
+        end.set_parent(&nodes[3]);
+        let nested = k::joint::Mimic::new(-2.0, 0.2);
+        nodes[1].set_mimic_parent(&nodes[0], mimic.clone());
+        nodes[2].set_mimic_parent(&nodes[1], nested.clone());
+        let arm = k::SerialChain::from_end(&end);
+        arm.set_joint_positions(&[0.3, 0.0, 0.0, 0.4]).unwrap();
+        let target = arm.end_transform();
+        arm.set_joint_positions(&[0.0, 0.0, 0.0, 0.1]).unwrap();
+        solver
+            .solve_with_constraints(&arm, &target, &constraints)
+            .unwrap();
+        let diff = arm.end_transform().translation.vector - target.translation.vector;
+        assert!(diff.xy().norm() < 0.001);
+        let positions = arm.joint_positions();
+        assert!((positions[0] - 0.3).abs() < 0.01);
+        assert!((positions[1] - mimic.mimic_position(positions[0])).abs() < 1e-9);
+        assert!((positions[2] - nested.mimic_position(positions[1])).abs() < 1e-9);
+        assert!((positions[3] - 0.4).abs() < 0.01);
+    }
 }